@@ -3,71 +3,335 @@
 // Started on August 23, 2020
 
 use super::{
-    config::Config,
+    config::{Config, InputFormat, Source},
+    control::{self, Command as ControlCommand, PaletteKind},
     input::{ColourPalette, Input},
-    optional::kill_me::KillMeModule,
+    markup::{
+        self,
+        i3bar::{self, BlockId, Click, Item},
+        Diagnostic, Markup, Target,
+    },
+    monitor::{self, Fallback, Monitor, MonitorSpec},
+    optional::{kill_me::KillMeModule, Modules},
+    region::{self, Align, RegionConfig, RegionOptions},
 };
 use anyhow::Result;
-use signal_hook::iterator::Signals;
 use std::{
-    collections::HashMap, ffi::CString, io, mem::MaybeUninit, process, ptr, sync::mpsc, thread,
-    time,
+    collections::HashMap,
+    ffi::{CString, OsString},
+    fs,
+    io::{self, Read, Write},
+    mem::{self, MaybeUninit},
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    ptr, thread,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
-use x11_dl::{xft, xinerama, xlib, xrandr};
+use x11_dl::{xft, xlib, xrandr};
 
-/// The function we dump into a seperate thread to wait for any input.
-/// Put in a seperate funtion to make some of the methods cleaner.
-///
-/// # Arguments
-/// * stdin: -> lock on the standard input for the projram.
-/// * send:  -> sender part of an across thread message pipe.
-fn input_loop(stdin: io::Stdin, send: mpsc::Sender<String>) {
-    loop {
-        let mut tmp = String::new();
-        stdin.read_line(&mut tmp).expect("wont fail.");
-        if !tmp.is_empty() {
-            send.send(tmp.trim().to_owned())
-                .expect("If this fails then the bar is already gone.");
+#[derive(Default)]
+/// Holds onto whatever we have read from stdin or a control client until there is a full line to
+/// hand out. Only ever read from after poll says there is something there, so reads never block.
+struct LineBuffer {
+    buf: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Read whatever is waiting on the fd.
+    ///
+    /// # Output
+    /// False once the other end has closed the pipe.
+    fn fill(&mut self, fd: RawFd) -> bool {
+        let mut chunk = [0u8; 4096];
+        let n = unsafe { libc::read(fd, chunk.as_mut_ptr() as *mut libc::c_void, chunk.len()) };
+        match n {
+            // Interrupted or nothing there after all, we'll get it on the next go around.
+            n if n < 0 => matches!(
+                io::Error::last_os_error().kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+            ),
+            0 => {
+                // Whatever was left without a newline still counts as a line.
+                if !self.buf.is_empty() {
+                    self.buf.push(b'\n');
+                }
+                false
+            }
+            n => {
+                self.buf.extend_from_slice(&chunk[..n as usize]);
+                true
+            }
         }
     }
+
+    /// Take all of the full lines read so far, trimmed and in order.
+    fn lines(&mut self) -> Vec<String> {
+        let end = match self.buf.iter().rposition(|&b| b == b'\n') {
+            Some(idx) => idx + 1,
+            None => return Vec::new(),
+        };
+        let full: Vec<u8> = self.buf.drain(..end).collect();
+        String::from_utf8_lossy(&full)
+            .lines()
+            .map(|l| l.trim().to_owned())
+            .collect()
+    }
+}
+
+/// Someone connected to the control socket, and whatever they sent that isn't a full line yet.
+struct Client {
+    stream: UnixStream,
+    buf: LineBuffer,
+}
+
+/// Watches the config file with inotify. The directory is watched instead of the file, since most
+/// editors save by writing a new file and renaming it over the old one.
+struct ConfigWatch {
+    fd: RawFd,
+    name: OsString,
+}
+
+impl ConfigWatch {
+    /// Start watching a file.
+    ///
+    /// # Arguments
+    /// * file: -> path to the config file.
+    fn new(file: &Path) -> io::Result<ConfigWatch> {
+        let name = file
+            .file_name()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes())?;
+        unsafe {
+            let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Only once the file is finished, a watch on creation could read it half written.
+            let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+            if libc::inotify_add_watch(fd, dir.as_ptr(), mask) < 0 {
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                return Err(err);
+            }
+            Ok(ConfigWatch {
+                fd,
+                name: name.to_owned(),
+            })
+        }
+    }
+
+    /// Read all of the events waiting, which can be for any file in the directory.
+    ///
+    /// # Output
+    /// True if any of them were for the config file.
+    fn changed(&self) -> bool {
+        const HEADER: usize = mem::size_of::<libc::inotify_event>();
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            let n =
+                unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n <= 0 {
+                return changed;
+            }
+            let mut offset = 0;
+            while offset + HEADER <= n as usize {
+                let event = unsafe {
+                    ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::inotify_event)
+                };
+                // The name follows the event, padded out with nulls.
+                let name = &buf[offset + HEADER..offset + HEADER + event.len as usize];
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                changed |= name == self.name.as_bytes();
+                offset += HEADER + event.len as usize;
+            }
+        }
+    }
+}
+
+impl Drop for ConfigWatch {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
 }
 
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 enum Error {
     #[error("Failed to open a connection to the default XDisplay")]
     DisplayOpenError,
+    #[error("Font {0} not found!!")]
+    FontNotFound(String),
+    #[error("The config wasn't loaded from anywhere, so it can't be loaded again")]
+    NoSource,
+    #[error("[{0}] is not a colour X knows")]
+    BadColour(String),
+    #[error("Another bar is already listening on {0}")]
+    SocketInUse(String),
+    #[error("There is no region named {0}")]
+    NoRegion(String),
+    #[error("Region {0} is filled by modules, it can't be set")]
+    ModuleRegion(String),
+    #[error("There is no {0:?} colour {1}, only {2} are set")]
+    NoColour(PaletteKind, usize, usize),
+}
+
+/// Send a reply to a control client. The socket blocks just for the write, with a short timeout,
+/// so a big reply like get's goes out whole but a client that stops reading can't hang the bar.
+///
+/// # Output
+/// False if the client couldn't be written to.
+fn send_reply(stream: &UnixStream, reply: &str) -> bool {
+    let mut writer = stream;
+    stream.set_nonblocking(false).is_ok()
+        && stream
+            .set_write_timeout(Some(Duration::from_millis(200)))
+            .is_ok()
+        && writer.write_all(reply.as_bytes()).is_ok()
+        && stream.set_nonblocking(true).is_ok()
+}
+
+/// Run the command attached to a clickable area of the bar.
+/// The child gets a thread to wait on it so we don't leave zombies lying around.
+///
+/// # Arguments
+/// * cmd: -> shell command to run with sh -c.
+fn run_command(cmd: &str) {
+    match Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        // The bar owns stdin, so don't let the child steal our input.
+        .stdin(Stdio::null())
+        .spawn()
+    {
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => eprintln!("Failed to run command -> {} <-: {}", cmd, e),
+    }
+}
+
+/// The list of colours of one kind in the palette.
+fn palette_list(palette: &mut ColourPalette, kind: PaletteKind) -> &mut Vec<xft::XftColor> {
+    match kind {
+        PaletteKind::Font => &mut palette.font,
+        PaletteKind::Background => &mut palette.background,
+        PaletteKind::Underline => &mut palette.underline,
+        PaletteKind::Overline => &mut palette.overline,
+    }
+}
+
+/// Names of the modifier keys held down during a click, the way i3bar sends them.
+///
+/// # Arguments
+/// * state: -> key and button mask from the XButtonEvent.
+fn modifiers(state: u32) -> Vec<&'static str> {
+    [
+        (xlib::ShiftMask, "Shift"),
+        (xlib::ControlMask, "Control"),
+        (xlib::Mod1Mask, "Mod1"),
+        (xlib::Mod2Mask, "Mod2"),
+        (xlib::Mod3Mask, "Mod3"),
+        (xlib::Mod4Mask, "Mod4"),
+        (xlib::Mod5Mask, "Mod5"),
+        (xlib::LockMask, "Lock"),
+    ]
+    .iter()
+    .filter(|&&(mask, _)| state & mask != 0)
+    .map(|&(_, name)| name)
+    .collect()
+}
+
+/// One window of the bar, on its own monitor. The fonts, colours, and parsed input are shared,
+/// but each window fits the input to its own width.
+struct BarWindow {
+    /// Name of the monitor, for addressing input lines to it.
+    monitor: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    id: xlib::Window,
+    /// Unmapped because its monitor went away.
+    hidden: bool,
+    // Everything is drawn onto the pixmap first and then copied onto the window in one go.
+    pixmap: xlib::Pixmap,
+    gc: xlib::GC,
+    draw: *mut xft::XftDraw,
+    // Each region has an Input and place on the bar at the same index.
+    inputs: Vec<Input>,
+    placements: Vec<(i32, u32)>,
 }
 
 /// Main struct of the whole program.
 pub struct Bar {
     name: String,
+    // Where the config came from, and a watch on its file if it is reloaded when saved.
+    source: Option<Source>,
+    watch: Option<ConfigWatch>,
     xlib: xlib::Xlib,
     xft: xft::Xft,
     display: *mut xlib::Display,
     screen: i32,
     top: bool,
-    monitor: String,
-    x: i32,
-    y: i32,
-    width: i32,
+    format: InputFormat,
+    // The i3bar protocol is a stream, so whatever isn't complete yet is kept in here.
+    i3bar: i3bar::Stream,
+    // Set once the generator asks for clicks, and whether the array holding them has been opened.
+    click_events: bool,
+    clicks_sent: bool,
+    strict: bool,
+    monitor: MonitorSpec,
+    // Monitors to put a window on, found when the config is loaded.
+    monitors: Vec<Monitor>,
+    // Width from the config, otherwise each window is as wide as its monitor.
+    width: Option<i32>,
+    // What happens to a window when its monitor is unplugged.
+    fallback: Fallback,
+    // XRandR and the first of its event numbers, when we are listening for monitor changes.
+    randr: Option<(xrandr::Xrandr, i32)>,
     height: i32,
     back_colour: u64,
     cmap: xlib::Colormap,
     visual: *mut xlib::Visual,
     root: u64,
-    window_id: u64,
     event: MaybeUninit<xlib::XEvent>,
-    draw: *mut xft::XftDraw,
+    windows: Vec<BarWindow>,
+    // Cleared when the windows are hidden with the hide command.
+    visible: bool,
+    // Socket for unibar-msg and where it is, so it can be removed when we close.
+    control: Option<(UnixListener, PathBuf)>,
+    // Connections to the socket that haven't closed yet.
+    clients: Vec<Client>,
     font_map: HashMap<char, usize>,
+    glyph_widths: HashMap<(usize, char), u32>,
     fonts: Vec<*mut xft::XftFont>,
+    // The names the fonts were opened with, so a reload only opens them again if they changed.
+    font_names: Vec<String>,
     font_y: i32,
     palette: ColourPalette,
     underline_height: i32,
-    left_string: Input,
-    center_string: Input,
-    right_string: Input,
+    overline_height: i32,
+    // Each region has options at the same index, and an Input and place in each window.
+    regions: Vec<RegionConfig>,
+    region_options: Vec<RegionOptions>,
+    region_gap: i32,
+    // Marquees scroll by how long the bar has been up, so every redraw agrees on where they are.
+    marquee_speed: u32,
+    started: Instant,
     kill_me: Option<KillMeModule>,
+    // Built-in modules, and the templates that put them into regions.
+    modules: Modules,
 }
 
 impl Bar {
@@ -92,32 +356,48 @@ impl Bar {
 
             Ok(Self {
                 name: String::new(),
+                source: None,
+                watch: None,
                 xlib,
                 xft,
                 display,
                 screen,
                 top: true,
-                monitor: String::new(),
-                x: 0,
-                y: 0,
-                width: 0,
+                format: InputFormat::Unibar,
+                i3bar: i3bar::Stream::default(),
+                click_events: false,
+                clicks_sent: false,
+                strict: false,
+                monitor: MonitorSpec::Display,
+                monitors: Vec::new(),
+                width: None,
+                fallback: Fallback::Hide,
+                randr: None,
                 height: 0,
                 back_colour: 0,
                 cmap,
                 visual,
                 root,
-                window_id: 0,
                 event: MaybeUninit::uninit(),
-                draw: ptr::null_mut(),
+                windows: Vec::new(),
+                visible: true,
+                control: None,
+                clients: Vec::new(),
                 font_map: HashMap::new(),
+                glyph_widths: HashMap::new(),
                 fonts: Vec::new(),
+                font_names: Vec::new(),
                 font_y: 0,
                 palette: ColourPalette::empty(),
                 underline_height: 0,
-                left_string: Input::empty(),
-                center_string: Input::empty(),
-                right_string: Input::empty(),
+                overline_height: 0,
+                regions: Vec::new(),
+                region_options: Vec::new(),
+                region_gap: 0,
+                marquee_speed: 0,
+                started: Instant::now(),
                 kill_me: None,
+                modules: Modules::default(),
             })
         }
     }
@@ -133,430 +413,1242 @@ impl Bar {
     /// generated in Bar::new().
     pub fn load_config(&mut self, conf: Config) -> Result<()> {
         // As per tradition, name first!
-        self.name = conf.name;
-        // We are setting x to 0 for now but we check for other monitors later.
-        self.x = 0;
-        // Bar height is configurable.
-        self.height = conf.height;
-        // Duh..
-        self.top = conf.top;
+        self.name = conf.name.clone();
         // load kill_me module settings
-        self.kill_me = conf.kill_me_cmd.map(KillMeModule::new);
+        self.kill_me = conf.kill_me_cmd.clone().map(KillMeModule::new);
         // Now we do all the yucky C library stuff in a big unsafe block.
         unsafe {
-            // Width for now is the full XDisplay width.
-            self.width = (self.xlib.XDisplayWidth)(self.display, self.screen);
-            // If its the top then 0, otherwise subtract bar height from monitor height.
-            self.y = if conf.top {
-                0
-            } else {
-                (self.xlib.XDisplayHeight)(self.display, self.screen) - conf.height
+            self.fonts = self.open_fonts(&conf.fonts)?;
+            self.font_names = conf.fonts.clone();
+            self.back_colour = self.get_xlib_color(&conf.back_color)?;
+            self.palette = self.load_palette(&conf)?;
+        }
+        self.apply_config(conf);
+        unsafe {
+            let display = self.display_monitor();
+            let (xinerama, randr) = match &self.monitor {
+                MonitorSpec::Display => (Vec::new(), Vec::new()),
+                _ => (
+                    monitor::xinerama_screens(&self.xlib),
+                    monitor::randr_outputs(&self.xlib)?,
+                ),
             };
+            self.monitors = monitor::select(&self.monitor, &xinerama, &randr, &display);
+        }
+        Ok(())
+    }
 
-            // Setting the monitor using Xinerama or Xrandr depending on value provided.
-            // Integer means Xinerama and any non-integer will be used to lookup in Xrandr.
-            self.monitor = conf.monitor;
-            if self.monitor.is_empty() {
-                eprintln!("No monitor provided, using full XDisplay!");
-            } else if let Ok(mon) = self.monitor.parse::<usize>() {
-                // If the monitor provided is a valid usize number. Then we are using Xinerama to
-                // detect monitors.
-
-                // xinerama stuff
-                // grab the monitor number set in the conf.
-                match xinerama::Xlib::open() {
-                    Ok(xin) => {
-                        // Grab another copy of the XDisplay. Because the Xinerama methods change the pointer
-                        // and causes the close to seg fault.
-                        let dpy = (self.xlib.XOpenDisplay)(ptr::null());
-                        // Even if we have connected to the library that doesn't necessarily mean that Xinerama
-                        // is active. So we make another check here.
-                        // Old school c bool where 0 is false and anything else is true.
-                        if let 0 = (xin.XineramaIsActive)(dpy) {
-                            eprintln!(
-                                "Xinerama is not currently active -- using full XDisplay width."
-                            );
-                        } else {
-                            // Temp var because the query strings funtion needs a pointer to a i32.
-                            let mut num_scr = 0;
-                            // Gets a dumb mutable pointer to an array of ScreenInfo objects for each screen.
-                            let scrns = (xin.XineramaQueryScreens)(dpy, &mut num_scr);
-                            // Using pointer arithmetic and the num_scr variable from the previous function we
-                            // fold the range into a Vec of ScreenInfo objects.
-                            let scrns = (0..num_scr as usize).fold(Vec::new(), |mut acc, i| {
-                                acc.push(*scrns.add(i));
-                                acc
-                            });
-                            // If the monitor set is not available, use first screen.
-                            let scrn = if mon >= num_scr as usize {
-                                eprintln!(
-                                    "Monitor index: {} is too large! Using first screen.",
-                                    mon
-                                );
-                                scrns[0]
-                            } else {
-                                scrns[mon]
-                            };
-                            self.x = scrn.x_org as i32;
-                            self.y = if self.top {
-                                scrn.y_org as i32
-                            } else {
-                                (scrn.y_org + scrn.height) as i32 - self.height
-                            };
-                            self.width = scrn.width as i32;
-                        }
-                        // Close out the temp display we opened.
-                        (self.xlib.XCloseDisplay)(dpy);
+    /// Load the config again and bring the running bar in line with it. Everything that can fail
+    /// is done before anything is changed, so a bad config leaves the bar as it was. The input
+    /// is kept and laid out again with the new fonts and colours.
+    pub fn reload(&mut self) -> Result<()> {
+        let conf = self.source.as_ref().ok_or(Error::NoSource)?.load()?;
+        unsafe {
+            let back_colour = self.get_xlib_color(&conf.back_color)?;
+            let mut palette = self.load_palette(&conf)?;
+            // Opening fonts is slow, so only if they changed.
+            let fonts = if conf.fonts == self.font_names {
+                None
+            } else {
+                match self.open_fonts(&conf.fonts) {
+                    Ok(fonts) => Some(fonts),
+                    Err(e) => {
+                        palette.destroy(&self.xft, self.display, self.cmap, self.visual);
+                        return Err(e);
                     }
-                    Err(e) => eprintln!(
-                        "Could not connect to Xinerama lib -- using full XDisplay width.\n{}",
-                        e
-                    ),
                 }
-            } else if let Ok(xrr) = xrandr::Xrandr::open() {
-                // xrandr stuff
-                // again we load a seperate pointer to the display, because otherwise we get
-                // segfaults. those are hard enough to understand when the language intends that as
-                // an error, but rust has a real hard time explaining so we just eat this and try
-                // again.
-                let dpy = (self.xlib.XOpenDisplay)(ptr::null());
-                let resources = (xrr.XRRGetScreenResources)(dpy, self.root);
-                // doesn't matter what we set here, the GetMonitors function overrides with the
-                // real val before we read.
-                let mut num_mon: i32 = 0;
-                // Now we query the library for a list on monitors and it helpfully (kill me now)
-                // returns a pointer to the first monitor and a total count in the num_mon var.
-                let mons = (xrr.XRRGetMonitors)(dpy, self.root, xlib::True, &mut num_mon);
-                // translating between weird c structs and pretty rust ones.
-                // we create a range iterator as large as the number of monitors and use pointer
-                // arithmetic to collect those into a Rust Vec.
-                // plus a quick type alias to make the code signatures easier to read.
-                type MonitorInfoList = Vec<(String, i32, i32, i32, i32)>;
-                let mons = (0..num_mon as usize).try_fold(
-                    Vec::new(),
-                    |mut acc, i| -> Result<MonitorInfoList> {
-                        let m = *mons.add(i);
-                        // The way xrandr organizes information probably makes sense if you wrote the
-                        // library. or maybe if you can find docs because they either dont exist or
-                        // suck. Basically every Xrandr Monitor has outputs. Unless you have multiple cords
-                        // from pc to monitor you only have one output.
-                        let mut tmp = (0..m.noutput as usize).try_fold(
-                            Vec::new(),
-                            |mut ac, j| -> Result<MonitorInfoList> {
-                                let output = *m.outputs.add(j);
-                                let info = *(xrr.XRRGetOutputInfo)(dpy, resources, output);
-                                // Inside the output object we have another object called CRTC.
-                                let crtc = *(xrr.XRRGetCrtcInfo)(dpy, resources, info.crtc);
-                                // This library returns strings just like arrays, you get a pointer to the
-                                // first char and a count. So we do the same iteration trick to collect
-                                // into a string.
-                                let name =
-                                    (0..info.nameLen as usize).fold(Vec::new(), |mut acc, k| {
-                                        acc.push(*info.name.add(k) as u8);
-                                        acc
-                                    });
-                                // Inside the CRTC is information that an actual human or basic ass application
-                                // like this may need. So we grab what we need there and push a tuple
-                                // containing the info into the vec, instead of the full monitor object, to
-                                // avoid all this abstraction craziness later.
-                                ac.push((
-                                    String::from_utf8(name)?,
-                                    crtc.x,
-                                    crtc.y,
-                                    crtc.width as i32,
-                                    crtc.height as i32,
-                                ));
-                                Ok(ac)
-                            },
-                        )?;
-                        // Append the tmp vec of usable monitor info to the result and finally move
-                        // onto the next Monitor.
-                        acc.append(&mut tmp);
-                        Ok(acc)
-                    },
-                )?;
-                match mons.iter().find(|m| m.0 == self.monitor) {
-                    Some(m) => {
-                        self.x = m.1;
-                        self.y = if self.top { m.2 } else { m.4 - self.height };
-                        self.width = m.3;
+            };
+            // Nothing can go wrong from here on.
+            if let Some(fonts) = fonts {
+                mem::replace(&mut self.fonts, fonts)
+                    .into_iter()
+                    .for_each(|f| (self.xft.XftFontClose)(self.display, f));
+                self.font_names = conf.fonts.clone();
+                // The lookups were for the old fonts.
+                self.font_map.clear();
+                self.glyph_widths.clear();
+            }
+            self.palette
+                .destroy(&self.xft, self.display, self.cmap, self.visual);
+            self.palette = palette;
+            self.back_colour = back_colour;
+            for win in self.windows.iter() {
+                (self.xlib.XSetForeground)(self.display, win.gc, back_colour);
+            }
+        }
+        // The kill_me module is left running as it was, so the pids it holds aren't lost.
+        self.apply_config(conf);
+        self.reload_inputs();
+        // Moves and resizes the windows for the new size and position, then redraws them.
+        unsafe { self.update_monitors() }
+    }
+
+    /// Load the config again for SIGHUP or the file being saved, keeping the old one if it fails.
+    fn reload_config(&mut self) {
+        match self.reload() {
+            Ok(()) => eprintln!("Reloaded the config."),
+            Err(e) => eprintln!("Could not reload the config, keeping the old one: {}", e),
+        }
+    }
+
+    /// Take on the settings from a config that don't need anything opened or allocated.
+    fn apply_config(&mut self, conf: Config) {
+        // Bar height is configurable.
+        self.height = conf.height;
+        // Duh..
+        self.top = conf.top;
+        // Which markup the input is written in. A stream half read in another format is no use.
+        if self.format != conf.format {
+            self.i3bar = i3bar::Stream::default();
+            self.click_events = false;
+            self.clicks_sent = false;
+        }
+        self.format = conf.format;
+        self.strict = conf.strict;
+        self.set_regions(conf.regions, &conf.region_options);
+        // Modules that changed start over, and every region they fill is drawn again on the next
+        // trip around the event loop.
+        let names: Vec<&str> = self.regions.iter().map(|r| r.name.as_str()).collect();
+        self.modules
+            .reload(&conf.modules, &conf.module_options, &conf.templates, &names);
+        self.region_gap = conf.region_gap;
+        self.marquee_speed = conf.marquee_speed;
+        // Integers are Xinerama indexes and anything else is an XRandR output name.
+        self.monitor = MonitorSpec::parse(&conf.monitor);
+        self.width = conf.width;
+        self.fallback = conf.monitor_fallback;
+        self.underline_height = conf.ul_height;
+        self.overline_height = conf.ol_height;
+        self.font_y = conf.font_y;
+        // The file never changes between reloads, so a watch we already have is kept.
+        match conf.source.as_ref().and_then(|s| s.file()) {
+            Some(file) if conf.watch_config => {
+                if self.watch.is_none() {
+                    match ConfigWatch::new(file) {
+                        Ok(watch) => self.watch = Some(watch),
+                        Err(e) => eprintln!("Could not watch {}: {}", file.display(), e),
                     }
-                    None => eprintln!(
-                        "Xrandr monitor -> {} <- not found, using full XDisplay!",
-                        self.monitor
-                    ),
                 }
-                (self.xlib.XCloseDisplay)(dpy);
-            } else {
-                eprintln!("XRandr not available, using full XDisplay!");
             }
+            _ => self.watch = None,
+        }
+        self.source = conf.source;
+    }
+
+    /// Set the regions of the bar. Each region gets its own Input, the classic three if none are
+    /// set. Regions that keep their name keep their input, new ones start out empty.
+    ///
+    /// # Arguments
+    /// * regions: -> the regions from the config.
+    /// * options: -> the options for each region, by lowercase name.
+    fn set_regions(
+        &mut self,
+        regions: Vec<RegionConfig>,
+        options: &HashMap<String, RegionOptions>,
+    ) {
+        let regions = if regions.is_empty() {
+            RegionConfig::defaults()
+        } else {
+            regions
+        };
+        self.region_options = regions
+            .iter()
+            .map(|r| {
+                options
+                    .get(&r.name.to_lowercase())
+                    .copied()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let old = &self.regions;
+        for win in self.windows.iter_mut() {
+            let mut inputs: Vec<Option<Input>> =
+                mem::take(&mut win.inputs).into_iter().map(Some).collect();
+            win.inputs = regions
+                .iter()
+                .map(|r| {
+                    old.iter()
+                        .position(|o| o.name == r.name)
+                        .and_then(|idx| inputs[idx].take())
+                        .unwrap_or_else(Input::empty)
+                })
+                .collect();
+            win.placements = regions.iter().map(|_| (0, 0)).collect();
+        }
+        self.regions = regions;
+    }
 
-            if let Some(width) = conf.width {
-                self.width = width;
+    /// Lay out every input again from its markup, after the fonts or colours changed. Indexes
+    /// past the end of the new fonts and colours are dropped.
+    fn reload_inputs(&mut self) {
+        let limits = self.palette.limits(self.fonts.len());
+        for win in self.windows.iter_mut() {
+            for input in win.inputs.iter_mut() {
+                let mut markup = input.markup().clone();
+                markup.clamp(&limits);
+                let mut fresh = Input::empty();
+                fresh.load(
+                    &self.xft,
+                    self.display,
+                    self.visual,
+                    self.cmap,
+                    &self.fonts,
+                    &mut self.font_map,
+                    &mut self.glyph_widths,
+                    &mut self.palette,
+                    markup,
+                );
+                *input = fresh;
             }
-            self.underline_height = conf.ul_height;
-            self.fonts = conf.fonts.iter().try_fold(
-                Vec::new(),
-                |mut acc, fs| -> Result<Vec<*mut xft::XftFont>> {
-                    acc.push(self.get_font(fs)?);
-                    Ok(acc)
-                },
-            )?;
-            self.font_y = conf.font_y;
-            self.back_colour = self.get_xlib_color(&conf.back_color)?;
-            type XftColorList = Vec<xft::XftColor>;
-            self.palette.font =
-                conf.ft_clrs
-                    .iter()
-                    .try_fold(Vec::new(), |mut acc, s| -> Result<XftColorList> {
-                        acc.push(self.get_xft_colour(s)?);
-                        Ok(acc)
-                    })?;
-            self.palette.background =
-                conf.bg_clrs
-                    .iter()
-                    .try_fold(Vec::new(), |mut acc, s| -> Result<XftColorList> {
-                        acc.push(self.get_xft_colour(s)?);
-                        Ok(acc)
-                    })?;
-            self.palette.underline =
-                conf.ul_clrs
-                    .iter()
-                    .try_fold(Vec::new(), |mut acc, s| -> Result<XftColorList> {
-                        acc.push(self.get_xft_colour(s)?);
-                        Ok(acc)
-                    })?;
         }
-        Ok(())
+        self.prune_colours();
+    }
+
+    /// Free the literal colours that went away with the old inputs.
+    fn prune_colours(&mut self) {
+        self.palette.prune(
+            &self.xft,
+            self.display,
+            self.visual,
+            self.cmap,
+            self.windows.iter().flat_map(|w| w.inputs.iter()),
+        );
+    }
+
+    /// Open each of the fonts, closing the ones already opened if any of them can't be.
+    ///
+    /// # Arguments
+    /// * names: -> the fonts in FcLookup form.
+    unsafe fn open_fonts(&self, names: &[String]) -> Result<Vec<*mut xft::XftFont>> {
+        let mut fonts = Vec::new();
+        for name in names {
+            match self.get_font(name) {
+                Ok(font) => fonts.push(font),
+                Err(e) => {
+                    fonts
+                        .iter()
+                        .for_each(|&f| (self.xft.XftFontClose)(self.display, f));
+                    return Err(e);
+                }
+            }
+        }
+        Ok(fonts)
+    }
+
+    /// Allocate all of the palette colours from the config, freeing them again if any of them
+    /// can't be.
+    unsafe fn load_palette(&self, conf: &Config) -> Result<ColourPalette> {
+        let mut palette = ColourPalette::empty();
+        for (kind, names) in [
+            (PaletteKind::Font, &conf.ft_clrs),
+            (PaletteKind::Background, &conf.bg_clrs),
+            (PaletteKind::Underline, &conf.ul_clrs),
+            (PaletteKind::Overline, &conf.ol_clrs),
+        ] {
+            for name in names {
+                match self.get_xft_colour(name) {
+                    Ok(colour) => palette_list(&mut palette, kind).push(colour),
+                    Err(e) => {
+                        palette.destroy(&self.xft, self.display, self.cmap, self.visual);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(palette)
     }
 
     pub fn init(&mut self) -> Result<()> {
         unsafe {
-            // Manually set the attributes here so we can get more fine grain control.
-            let mut attributes: MaybeUninit<xlib::XSetWindowAttributes> = MaybeUninit::uninit();
-            let atts = attributes.as_mut_ptr();
-            (*atts).background_pixel = self.back_colour;
-            (*atts).colormap = self.cmap;
-            (*atts).override_redirect = xlib::False;
-            (*atts).event_mask =
-                xlib::ExposureMask | xlib::ButtonPressMask | xlib::VisibilityChangeMask;
-            let mut attributes = attributes.assume_init();
-
-            // Use the attributes we created to make a window.
-            self.window_id = (self.xlib.XCreateWindow)(
-                self.display,             // Display to use.
-                self.root,                // Parent window.
-                self.x,                   // X position (from top-left.
-                self.y,                   // Y position (from top-left.
-                self.width as u32,        // Length of the bar in x direction.
-                self.height as u32,       // Height of the bar in y direction.
-                0,                        // Border-width.
-                xlib::CopyFromParent,     // Window depth.
-                xlib::InputOutput as u32, // Window class.
-                self.visual,              // Visual type to use.
-                xlib::CWBackPixel | xlib::CWColormap | xlib::CWOverrideRedirect | xlib::CWEventMask, // Mask for which attributes are set.
-                &mut attributes, // Pointer to the attributes to use.
-            );
-            self.draw =
-                (self.xft.XftDrawCreate)(self.display, self.window_id, self.visual, self.cmap);
+            for m in self.monitors.clone() {
+                let win = self.create_window(&m)?;
+                self.set_atoms(&win)?;
+                // Get the background onto the pixmap so the first Expose has something to copy.
+                self.draw_display(&win);
+                // Map it up.
+                (self.xlib.XMapWindow)(self.display, win.id);
+                self.windows.push(win);
+            }
+            self.select_randr_events();
+        }
+        Ok(())
+    }
+
+    /// Without a monitor the bar goes across the full XDisplay.
+    unsafe fn display_monitor(&self) -> Monitor {
+        Monitor {
+            name: String::new(),
+            x: 0,
+            y: 0,
+            width: (self.xlib.XDisplayWidth)(self.display, self.screen),
+            height: (self.xlib.XDisplayHeight)(self.display, self.screen),
+            primary: false,
+        }
+    }
 
-            self.set_atoms()?;
+    /// Ask XRandR to tell us when monitors are plugged in, unplugged, or change resolution.
+    unsafe fn select_randr_events(&mut self) {
+        let xrr = match xrandr::Xrandr::open() {
+            Ok(xrr) => xrr,
+            Err(_) => return,
+        };
+        let (mut event_base, mut error_base) = (0, 0);
+        if (xrr.XRRQueryExtension)(self.display, &mut event_base, &mut error_base) == 0 {
+            eprintln!("XRandr extension not available, monitor changes will be missed.");
+            return;
+        }
+        (xrr.XRRSelectInput)(
+            self.display,
+            self.root,
+            xrandr::RRScreenChangeNotifyMask
+                | xrandr::RRCrtcChangeNotifyMask
+                | xrandr::RROutputChangeNotifyMask,
+        );
+        self.randr = Some((xrr, event_base));
+    }
+
+    /// Find the monitors again after XRandR says they changed. Windows are moved and resized to
+    /// match their monitor, made for monitors that are new, and the ones whose monitor is gone
+    /// fall back on whatever the config says.
+    unsafe fn update_monitors(&mut self) -> Result<()> {
+        let display = self.display_monitor();
+        let xinerama = monitor::xinerama_screens(&self.xlib);
+        let randr = monitor::randr_outputs(&self.xlib)?;
+        let wanted = monitor::select(&self.monitor, &xinerama, &randr, &display);
+        let all = if randr.is_empty() { &xinerama } else { &randr };
+        let primary = monitor::primary(all).unwrap_or(&display).clone();
 
-            // Map it up.
-            (self.xlib.XMapWindow)(self.display, self.window_id);
+        // Windows the config no longer asks for are gone for good, not just unplugged.
+        let (mut windows, unwanted): (Vec<BarWindow>, Vec<BarWindow>) =
+            mem::take(&mut self.windows).into_iter().partition(|w| {
+                self.monitor.wants(&w.monitor) || wanted.iter().any(|m| m.name == w.monitor)
+            });
+        unwanted.iter().for_each(|w| self.destroy_window(w));
+        let mut lost = Vec::new();
+        for (idx, win) in windows.iter_mut().enumerate() {
+            match wanted.iter().find(|m| m.name == win.monitor) {
+                Some(m) => self.place_window(win, m)?,
+                None => lost.push(idx),
+            }
         }
+        for idx in lost {
+            // Don't cover up a window that is already on the primary monitor.
+            let taken = windows
+                .iter()
+                .any(|w| !w.hidden && w.monitor == primary.name);
+            let win = &mut windows[idx];
+            if self.fallback == Fallback::Primary && !taken {
+                eprintln!(
+                    "Monitor {} is gone, moving to {}.",
+                    win.monitor, primary.name
+                );
+                self.place_window(win, &primary)?;
+            } else if !win.hidden {
+                eprintln!(
+                    "Monitor {} is gone, hiding until it comes back.",
+                    win.monitor
+                );
+                (self.xlib.XUnmapWindow)(self.display, win.id);
+                win.hidden = true;
+            }
+        }
+        for m in wanted.iter() {
+            if windows.iter().any(|w| w.monitor == m.name) {
+                continue;
+            }
+            let mut win = self.create_window(m)?;
+            // New windows show the same input as the rest.
+            if let Some(first) = windows.first() {
+                win.inputs = first.inputs.clone();
+            }
+            self.set_atoms(&win)?;
+            if self.visible {
+                (self.xlib.XMapWindow)(self.display, win.id);
+            }
+            windows.push(win);
+        }
+        self.windows = windows;
+        self.fit_regions();
+        self.draw_all();
         Ok(())
     }
 
+    /// Free everything that belongs to a window, and the window itself.
+    unsafe fn destroy_window(&self, win: &BarWindow) {
+        (self.xft.XftDrawDestroy)(win.draw);
+        (self.xlib.XFreeGC)(self.display, win.gc);
+        (self.xlib.XFreePixmap)(self.display, win.pixmap);
+        (self.xlib.XDestroyWindow)(self.display, win.id);
+    }
+
+    /// Move and resize a window to fit on a monitor, showing it if it was hidden.
+    ///
+    /// # Arguments
+    /// * win: -> the window to move.
+    /// * m:   -> the monitor it goes on.
+    unsafe fn place_window(&self, win: &mut BarWindow, m: &Monitor) -> Result<()> {
+        let width = self.width.unwrap_or(m.width);
+        let y = self.window_y(m);
+        if (win.x, win.y, win.width, win.height) != (m.x, y, width, self.height) {
+            (self.xlib.XMoveResizeWindow)(
+                self.display,
+                win.id,
+                m.x,
+                y,
+                width as u32,
+                self.height as u32,
+            );
+            // The pixmap has to be the same size as the window.
+            if (win.width, win.height) != (width, self.height) {
+                (self.xft.XftDrawDestroy)(win.draw);
+                (self.xlib.XFreePixmap)(self.display, win.pixmap);
+                let (pixmap, draw) = self.create_buffer(win.id, width);
+                win.pixmap = pixmap;
+                win.draw = draw;
+            }
+            win.x = m.x;
+            win.y = y;
+            win.width = width;
+            win.height = self.height;
+            self.set_struts(win)?;
+        }
+        if win.hidden {
+            if self.visible {
+                (self.xlib.XMapWindow)(self.display, win.id);
+            }
+            win.hidden = false;
+        }
+        Ok(())
+    }
+
+    /// If its the top then the top of the monitor, otherwise the bar height up from the bottom.
+    fn window_y(&self, m: &Monitor) -> i32 {
+        if self.top {
+            m.y
+        } else {
+            m.y + m.height - self.height
+        }
+    }
+
+    /// Off-screen buffer the same size as the window, Xft draws onto it instead.
+    ///
+    /// # Arguments
+    /// * id:    -> the window the pixmap is copied onto.
+    /// * width: -> width of the window.
+    unsafe fn create_buffer(
+        &self,
+        id: xlib::Window,
+        width: i32,
+    ) -> (xlib::Pixmap, *mut xft::XftDraw) {
+        let pixmap = (self.xlib.XCreatePixmap)(
+            self.display,
+            id,
+            width as u32,
+            self.height as u32,
+            (self.xlib.XDefaultDepth)(self.display, self.screen) as u32,
+        );
+        let draw = (self.xft.XftDrawCreate)(self.display, pixmap, self.visual, self.cmap);
+        (pixmap, draw)
+    }
+
+    /// Make the window, and the pixmap it is drawn from, for the bar on a monitor.
+    ///
+    /// # Arguments
+    /// * m: -> the monitor the window goes on.
+    unsafe fn create_window(&self, m: &Monitor) -> Result<BarWindow> {
+        let width = self.width.unwrap_or(m.width);
+        let y = self.window_y(m);
+        // Manually set the attributes here so we can get more fine grain control.
+        let mut attributes: MaybeUninit<xlib::XSetWindowAttributes> = MaybeUninit::uninit();
+        let atts = attributes.as_mut_ptr();
+        // No background so the server never clears the window, the pixmap covers all of it.
+        (*atts).background_pixmap = 0;
+        (*atts).colormap = self.cmap;
+        (*atts).override_redirect = xlib::False;
+        (*atts).event_mask =
+            xlib::ExposureMask | xlib::ButtonPressMask | xlib::VisibilityChangeMask;
+        let mut attributes = attributes.assume_init();
+
+        // Use the attributes we created to make a window.
+        let id = (self.xlib.XCreateWindow)(
+            self.display,             // Display to use.
+            self.root,                // Parent window.
+            m.x,                      // X position (from top-left.
+            y,                        // Y position (from top-left.
+            width as u32,             // Length of the bar in x direction.
+            self.height as u32,       // Height of the bar in y direction.
+            0,                        // Border-width.
+            xlib::CopyFromParent,     // Window depth.
+            xlib::InputOutput as u32, // Window class.
+            self.visual,              // Visual type to use.
+            xlib::CWBackPixmap | xlib::CWColormap | xlib::CWOverrideRedirect | xlib::CWEventMask, // Mask for which attributes are set.
+            &mut attributes, // Pointer to the attributes to use.
+        );
+        let (pixmap, draw) = self.create_buffer(id, width);
+        let gc = (self.xlib.XCreateGC)(self.display, pixmap, 0, ptr::null_mut());
+        (self.xlib.XSetForeground)(self.display, gc, self.back_colour);
+        Ok(BarWindow {
+            monitor: m.name.clone(),
+            x: m.x,
+            y,
+            width,
+            height: self.height,
+            id,
+            hidden: false,
+            pixmap,
+            gc,
+            draw,
+            inputs: self.regions.iter().map(|_| Input::empty()).collect(),
+            placements: self.regions.iter().map(|_| (0, 0)).collect(),
+        })
+    }
+
+    /// Wait on the X connection, stdin, and any signals all at once with poll(2), so updates are
+    /// drawn as soon as they come in and an idle bar doesn't wake up at all.
     pub fn event_loop(&mut self) -> Result<()> {
-        // Input thread. Has to be seperate to not block xlib events.
-        let (tx, rx) = mpsc::channel();
-        // If we don't call the stdin function in this thread and pass it then sometimes we lose
-        // the connection and the threads both panic.
-        thread::spawn(move || input_loop(io::stdin(), tx));
-
-        // Signals that are incoming.
-        let signals = Signals::new(&[
+        // Signals get written into a pipe so they can wake up the poll with everything else.
+        let (signal_read, signal_write) = UnixStream::pair()?;
+        signal_read.set_nonblocking(true)?;
+        for &sig in &[
             signal_hook::SIGTERM,
             signal_hook::SIGINT,
             signal_hook::SIGQUIT,
-            signal_hook::SIGHUP,
-        ])?;
+        ] {
+            signal_hook::pipe::register(sig, signal_write.try_clone()?)?;
+        }
+        // SIGHUP gets a pipe of its own, it loads the config again instead of shutting down.
+        let (hup_read, hup_write) = UnixStream::pair()?;
+        hup_read.set_nonblocking(true)?;
+        signal_hook::pipe::register(signal_hook::SIGHUP, hup_write)?;
+
+        let mut stdin = LineBuffer::default();
+        let mut fds = vec![
+            libc::pollfd {
+                fd: unsafe { (self.xlib.XConnectionNumber)(self.display) },
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: signal_read.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: -1,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: hup_read.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: -1,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // The bar still works without the socket, it just can't be sent commands.
+        match self.open_socket() {
+            Ok((listener, path)) => {
+                fds[3].fd = listener.as_raw_fd();
+                self.control = Some((listener, path));
+            }
+            Err(e) => eprintln!("Control socket not available: {}", e),
+        }
 
+        let mut next_tick = Instant::now();
         loop {
+            // Xlib may have read events off the connection already, so we have to empty its queue
+            // before blocking or they would sit there until the next time the fd wakes us up.
+            // This also flushes anything we drew last time around.
+            unsafe { self.handle_events() };
+
+            // Modules that are due are updated before we go back to sleep.
+            let changed = self.modules.update(Instant::now());
+            if !changed.is_empty() {
+                self.load_modules(changed);
+                unsafe { self.draw_all() };
+            }
+
+            // Only wake up on a timer while something is scrolling or a module is waiting.
+            let tick = self.marquee_tick();
+            let mut timeout = match tick {
+                Some(tick) => {
+                    let now = Instant::now();
+                    if next_tick <= now {
+                        unsafe { self.draw_marquees() };
+                        next_tick = now + tick;
+                        continue;
+                    }
+                    (next_tick - now).as_millis() as i32
+                }
+                None => -1,
+            };
+            if let Some(wait) = self.modules.timeout(Instant::now()) {
+                // Round up, waking early would only spin until the module is due.
+                let wait = wait.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+                timeout = if timeout < 0 { wait } else { timeout.min(wait) };
+            }
+
+            // A reload can start or stop watching the config file, or change the modules.
+            fds[5].fd = self.watch.as_ref().map_or(-1, |w| w.fd);
+            fds.truncate(6);
+            fds.extend(self.modules.fds().into_iter().map(|fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            }));
+            // Clients of the control socket come after the modules.
+            let client_fds = fds.len();
+            fds.extend(self.clients.iter().map(|c| libc::pollfd {
+                fd: c.stream.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            }));
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err.into());
+            }
+
             // Check signals.
             // All of the signals basically tell the program to shutdown, so we just get ahead and
             // make sure that we clean up properly.
-            if signals.pending().count() > 0 {
+            if fds[2].revents != 0 {
+                let _ = (&signal_read).read(&mut [0u8; 32]);
                 self.close(1);
             }
 
-            // Check the input thread.
-            if let Ok(string) = rx.try_recv() {
-                // Small kill marker for when I can't click.
-                if string == "QUIT NOW" {
-                    break;
+            // Modules driven by events are updated next time around.
+            let now = Instant::now();
+            for pfd in fds[6..client_fds].iter().filter(|p| p.revents != 0) {
+                self.modules.wake(pfd.fd, now);
+            }
+
+            // Check for SIGHUP or the config file being saved, either one loads it again.
+            let hup = fds[4].revents != 0;
+            if hup {
+                let _ = (&hup_read).read(&mut [0u8; 32]);
+            }
+            let saved = fds[5].revents != 0 && self.watch.as_ref().is_some_and(|w| w.changed());
+            if hup || saved {
+                self.reload_config();
+            }
+
+            // Check the control socket. New clients are polled next time around.
+            let ready: Vec<bool> = fds[client_fds..].iter().map(|p| p.revents != 0).collect();
+            if self.serve_clients(&ready) {
+                return Ok(());
+            }
+            if fds[3].revents != 0 {
+                self.accept_clients();
+            }
+
+            // Check stdin.
+            if fds[1].revents != 0 {
+                // Once stdin is closed a negative fd tells poll to skip it, the bar stays up with
+                // whatever it last showed.
+                if !stdin.fill(fds[1].fd) {
+                    fds[1].fd = -1;
                 }
+                let mut changed = false;
+                for string in stdin.lines() {
+                    // Small kill marker for when I can't click.
+                    if string == "QUIT NOW" {
+                        return Ok(());
+                    }
 
-                // messy way to check if kill me option is enabled
-                if string.starts_with("PLEASE KILL:") {
-                    if let Some(kill_me) = self.kill_me.as_mut() {
-                        if let Some(id_str) = string.split(':').nth(1) {
-                            if let Ok(id) = id_str.parse::<u32>() {
-                                kill_me.push(id);
+                    // messy way to check if kill me option is enabled
+                    if string.starts_with("PLEASE KILL:") {
+                        if let Some(kill_me) = self.kill_me.as_mut() {
+                            if let Some(id_str) = string.split(':').nth(1) {
+                                if let Ok(id) = id_str.parse::<u32>() {
+                                    kill_me.push(id);
+                                }
                             }
                         }
+                        continue;
                     }
-                    continue;
+
+                    self.update(&string);
+                    changed = true;
+                }
+                // Several lines can come in at once, but only the last one needs to be drawn.
+                if changed {
+                    unsafe { self.draw_all() };
                 }
+            }
+        }
+    }
 
-                let split: Vec<String> = string.split("<|>").map(|s| s.to_owned()).collect();
-                unsafe {
-                    match split.len() {
-                        // If there are no seperators then we assign the whole string to the left
-                        // bar section.
-                        1 => {
-                            self.left_string.parse_string(
-                                &self.xft,
-                                self.display,
-                                &self.fonts,
-                                &mut self.font_map,
-                                &self.palette,
-                                &split[0],
-                            )?;
-                            self.center_string.clear();
-                            self.right_string.clear();
-                        }
-                        // If there is only one seperator we assign the first bit to the left and
-                        // the second to the right.
-                        2 => {
-                            self.left_string.parse_string(
-                                &self.xft,
-                                self.display,
-                                &self.fonts,
-                                &mut self.font_map,
-                                &self.palette,
-                                &split[0],
-                            )?;
-                            self.center_string.clear();
-                            self.right_string.parse_string(
-                                &self.xft,
-                                self.display,
-                                &self.fonts,
-                                &mut self.font_map,
-                                &self.palette,
-                                &split[1],
-                            )?;
-                        }
-                        // If there are two or more seperators then we are only gonna use the first
-                        // three, assign the first to left, second to center, and third to right.
-                        _ => {
-                            self.left_string.parse_string(
-                                &self.xft,
-                                self.display,
-                                &self.fonts,
-                                &mut self.font_map,
-                                &self.palette,
-                                &split[0],
-                            )?;
-                            self.center_string.parse_string(
-                                &self.xft,
-                                self.display,
-                                &self.fonts,
-                                &mut self.font_map,
-                                &self.palette,
-                                &split[1],
-                            )?;
-                            self.right_string.parse_string(
-                                &self.xft,
-                                self.display,
-                                &self.fonts,
-                                &mut self.font_map,
-                                &self.palette,
-                                &split[2],
-                            )?;
-                        }
-                    }
-                    self.draw_display();
+    /// Listen for commands on $XDG_RUNTIME_DIR/unibar/<name>.sock. A socket left behind by a
+    /// bar that died is replaced, but one that still answers belongs to a bar with the same name.
+    fn open_socket(&self) -> Result<(UnixListener, PathBuf)> {
+        let path = control::socket_path(&self.name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(Error::SocketInUse(path.display().to_string()).into());
+            }
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        // Poll tells us when there is someone to accept, so we never want to wait on it.
+        listener.set_nonblocking(true)?;
+        Ok((listener, path))
+    }
+
+    /// Take everyone waiting on the control socket. They are only read from once poll says they
+    /// sent something, so a client that is slow to finish its command can't hold up the bar.
+    fn accept_clients(&mut self) {
+        if let Some((listener, _)) = &self.control {
+            while let Ok((stream, _)) = listener.accept() {
+                // Accepted sockets don't keep the listener's non-blocking flag.
+                if stream.set_nonblocking(true).is_ok() {
+                    self.clients.push(Client {
+                        stream,
+                        buf: LineBuffer::default(),
+                    });
                 }
             }
+        }
+    }
 
-            unsafe {
-                // Check events.
-                if self.poll_events() {
-                    #[allow(clippy::single_match)]
-                    match self.event.assume_init_ref().get_type() {
-                        // if the bar is show on the screen we draw content.
-                        xlib::Expose => self.draw_display(),
-                        // ignore all other events
-                        _ => (),
+    /// Answer the clients that sent something. Each command is one line and gets back one reply,
+    /// and a client is dropped once it closes its end.
+    ///
+    /// # Arguments
+    /// * ready: -> whether poll woke up for each client, in order.
+    ///
+    /// # Output
+    /// True if one of them told the bar to quit.
+    fn serve_clients(&mut self, ready: &[bool]) -> bool {
+        let mut quit = false;
+        let clients = mem::take(&mut self.clients);
+        for (mut client, ready) in clients.into_iter().zip(ready.iter().copied()) {
+            let mut open = !ready || client.buf.fill(client.stream.as_raw_fd());
+            for line in client.buf.lines() {
+                let reply = match ControlCommand::parse(&line) {
+                    Ok(ControlCommand::Quit) => {
+                        quit = true;
+                        Ok(String::new())
                     }
+                    Ok(cmd) => self.run_control(cmd),
+                    Err(e) => Err(e.into()),
+                };
+                let reply = match reply {
+                    Ok(s) if s.is_empty() => "ok\n".to_string(),
+                    Ok(s) => s,
+                    Err(e) => format!("error: {}\n", e),
+                };
+                if !send_reply(&client.stream, &reply) {
+                    open = false;
+                    break;
                 }
             }
+            if open {
+                self.clients.push(client);
+            }
+        }
+        quit
+    }
+
+    /// Do what a command from the control socket asks.
+    ///
+    /// # Arguments
+    /// * cmd: -> the command, anything but quit.
+    ///
+    /// # Output
+    /// What to send back, empty if there is nothing to say but ok.
+    fn run_control(&mut self, cmd: ControlCommand) -> Result<String> {
+        match cmd {
+            ControlCommand::Hide => unsafe { self.set_visible(false) },
+            ControlCommand::Show => unsafe { self.set_visible(true) },
+            ControlCommand::Toggle => unsafe { self.set_visible(!self.visible) },
+            ControlCommand::Reload => self.reload()?,
+            ControlCommand::Set { region, markup } => return self.set_region(&region, &markup),
+            ControlCommand::Get => {
+                // Every window has the same text unless lines were sent to just one monitor, so
+                // the first one speaks for them all.
+                let texts = self.windows.first().map(|w| &w.inputs);
+                return Ok(self
+                    .regions
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, r)| {
+                        let text = texts.map(|i| i[idx].text()).unwrap_or_default();
+                        format!("{}: {}\n", r.name, text)
+                    })
+                    .collect());
+            }
+            ControlCommand::Palette {
+                kind,
+                index,
+                colour,
+            } => unsafe { self.set_colour(kind, index, &colour)? },
+            ControlCommand::Quit => (),
+        }
+        Ok(String::new())
+    }
 
-            thread::sleep(time::Duration::from_millis(100));
+    /// Map or unmap every window, leaving the ones without a monitor alone.
+    unsafe fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        for win in self.windows.iter().filter(|w| !w.hidden) {
+            if visible {
+                (self.xlib.XMapWindow)(self.display, win.id);
+            } else {
+                (self.xlib.XUnmapWindow)(self.display, win.id);
+            }
         }
+    }
+
+    /// Replace what one region shows, on every window.
+    ///
+    /// # Arguments
+    /// * name:   -> name of the region.
+    /// * markup: -> new contents, in unibar markup whatever the input format is.
+    ///
+    /// # Output
+    /// Problems with the markup, one per line.
+    fn set_region(&mut self, name: &str, markup: &str) -> Result<String> {
+        let idx = self
+            .regions
+            .iter()
+            .position(|r| r.name == name)
+            .ok_or_else(|| Error::NoRegion(name.to_string()))?;
+        // Like lines of input, or the next module update would just write over it.
+        if self.modules.owns(name) {
+            return Err(Error::ModuleRegion(name.to_string()).into());
+        }
+        let limits = self.palette.limits(self.fonts.len());
+        let markup = markup::parse(markup, &limits);
+        let problems: String = markup
+            .diagnostics
+            .iter()
+            .map(|d| format!("    {}\n", d))
+            .collect();
+        if !problems.is_empty() && self.strict {
+            return Err(anyhow::anyhow!(
+                "Strict mode is on, keeping the previous input.\n{}",
+                problems
+            ));
+        }
+        let mut markups: Vec<Option<Markup>> = self.regions.iter().map(|_| None).collect();
+        markups[idx] = Some(markup);
+        self.load_markups(markups, None);
+        unsafe { self.draw_all() };
+        Ok(problems)
+    }
+
+    /// Swap one of the colours in the palette for another, and redraw with it.
+    ///
+    /// # Arguments
+    /// * kind:   -> which list of colours.
+    /// * index:  -> the colour in that list, it has to be set already.
+    /// * colour: -> the new colour, anything X can parse.
+    unsafe fn set_colour(&mut self, kind: PaletteKind, index: usize, colour: &str) -> Result<()> {
+        let new = self.get_xft_colour(colour)?;
+        let list = palette_list(&mut self.palette, kind);
+        let len = list.len();
+        let old = match list.get_mut(index) {
+            Some(old) => old,
+            None => {
+                let mut new = new;
+                (self.xft.XftColorFree)(self.display, self.visual, self.cmap, &mut new);
+                return Err(Error::NoColour(kind, index, len).into());
+            }
+        };
+        let mut old = mem::replace(old, new);
+        (self.xft.XftColorFree)(self.display, self.visual, self.cmap, &mut old);
+        self.draw_all();
         Ok(())
     }
 
-    unsafe fn clear_display(&self) {
-        (self.xlib.XClearWindow)(self.display, self.window_id);
+    /// Parse a line of input in whichever format the bar is set to use, and load the results
+    /// into the inputs of the regions they are meant for. Problems with the markup are logged
+    /// along with the line, and in strict mode the line is thrown out. A line starting with
+    /// {>name} only goes to the window on that monitor.
+    ///
+    /// # Arguments
+    /// * line: -> line of input read from stdin.
+    fn update(&mut self, line: &str) {
+        if self.format == InputFormat::I3bar {
+            return self.update_i3bar(line);
+        }
+        let (target, rest) = markup::monitor_target(line);
+        let limits = self.palette.limits(self.fonts.len());
+        let (markups, unknown) = match self.format {
+            InputFormat::Lemonbar => (
+                region::assign_aligned(&self.regions, markup::lemonbar::parse(rest, &limits)),
+                Vec::new(),
+            ),
+            _ => region::assign(&self.regions, markup::parse_line(rest, &limits)),
+        };
+        // The offsets are into the line after the monitor, move them back to the full line.
+        let (bytes, chars) = (
+            line.len() - rest.len(),
+            line.chars().count() - rest.chars().count(),
+        );
+        let mut diagnostics: Vec<Diagnostic> = markups
+            .iter()
+            .flatten()
+            .flat_map(|m| m.diagnostics.iter())
+            .map(|d| Diagnostic {
+                offset: d.offset + chars,
+                byte: d.byte + bytes,
+                ..*d
+            })
+            .collect();
+        let missing = target.filter(|&t| self.windows.iter().all(|w| w.monitor != t));
+        if !diagnostics.is_empty() || !unknown.is_empty() || missing.is_some() {
+            diagnostics.sort_by_key(|d| d.offset);
+            eprintln!("Problems with input line -> {} <-", line);
+            diagnostics.iter().for_each(|d| eprintln!("    {}", d));
+            unknown
+                .iter()
+                .for_each(|name| eprintln!("    There is no region named {}.", name));
+            if let Some(name) = missing {
+                eprintln!("    There is no bar on monitor {}.", name);
+            }
+            // Strict mode would rather show the last good line than a broken one.
+            if self.strict {
+                eprintln!("Strict mode is on, keeping the previous input.");
+                return;
+            }
+        }
+        self.load_markups(self.skip_module_regions(markups), target);
     }
 
-    unsafe fn draw_display(&self) {
-        // clear display before we redraw
-        self.clear_display();
-        // left string.
-        self.left_string.draw(
-            &self.xft,
+    /// Read a line of the i3bar protocol. Only complete updates are drawn, and like i3bar all of
+    /// the blocks are put on the right of every window.
+    ///
+    /// # Arguments
+    /// * line: -> line of input read from stdin.
+    fn update_i3bar(&mut self, line: &str) {
+        let mut status = None;
+        for item in self.i3bar.push(line) {
+            match item {
+                Ok(Item::Header(header)) => self.click_events = header.click_events,
+                Ok(Item::Status(markup)) => status = Some(markup),
+                Err(e) => eprintln!("Problems with input line -> {} <-\n    {}.", line, e),
+            }
+        }
+        // Several updates can come in at once, but only the last one needs to be drawn.
+        if let Some(markup) = status {
+            let aligned = [Markup::default(), Markup::default(), markup];
+            let markups = region::assign_aligned(&self.regions, aligned);
+            self.load_markups(self.skip_module_regions(markups), None);
+        }
+    }
+
+    /// Leave the regions filled by modules alone, so lines of input don't clear them.
+    ///
+    /// # Arguments
+    /// * markups: -> markup for each region, from a line of input.
+    fn skip_module_regions(&self, markups: Vec<Option<Markup>>) -> Vec<Option<Markup>> {
+        markups
+            .into_iter()
+            .zip(self.regions.iter())
+            .map(|(markup, r)| markup.filter(|_| !self.modules.owns(&r.name)))
+            .collect()
+    }
+
+    /// Load the output of the modules into the regions they go in. Problems with the markup are
+    /// logged, but it is shown anyway.
+    ///
+    /// # Arguments
+    /// * changed: -> new markup by region name, see Modules::update.
+    fn load_modules(&mut self, changed: Vec<(String, String)>) {
+        let limits = self.palette.limits(self.fonts.len());
+        let mut markups: Vec<Option<Markup>> = self.regions.iter().map(|_| None).collect();
+        for (region, text) in changed {
+            let idx = match self.regions.iter().position(|r| r.name == region) {
+                Some(idx) => idx,
+                None => continue,
+            };
+            let markup = markup::parse_modules(&text, &limits);
+            if !markup.diagnostics.is_empty() {
+                eprintln!("Problems with the modules in region {}:", region);
+                markup
+                    .diagnostics
+                    .iter()
+                    .for_each(|d| eprintln!("    {}", d));
+            }
+            markups[idx] = Some(markup);
+        }
+        self.load_markups(markups, None);
+    }
+
+    /// Load each markup into the input of its region and fit them all on the bar again. The
+    /// layout is only done once and shared by every window it goes to.
+    ///
+    /// # Arguments
+    /// * markups: -> markup for each region, None for regions that keep what they had.
+    /// * target:  -> name of the only monitor to update, otherwise all of them are.
+    fn load_markups(&mut self, markups: Vec<Option<Markup>>, target: Option<&str>) {
+        for (idx, markup) in markups.into_iter().enumerate() {
+            // Regions the line didn't mention keep what they had.
+            let markup = match markup {
+                Some(markup) => markup,
+                None => continue,
+            };
+            let mut input = Input::empty();
+            input.load(
+                &self.xft,
+                self.display,
+                self.visual,
+                self.cmap,
+                &self.fonts,
+                &mut self.font_map,
+                &mut self.glyph_widths,
+                &mut self.palette,
+                markup,
+            );
+            for win in self.windows.iter_mut() {
+                if target.is_none_or(|t| t == win.monitor) {
+                    win.inputs[idx] = input.clone();
+                }
+            }
+        }
+        self.prune_colours();
+        // Any change in width can change how much room every other region has.
+        self.fit_regions();
+    }
+
+    /// Figure out where each of the inputs go on each window, and cut down the ones that would
+    /// run into each other.
+    fn fit_regions(&mut self) {
+        for win in self.windows.iter_mut() {
+            let regions: Vec<(Align, u32, RegionOptions)> = self
+                .regions
+                .iter()
+                .zip(win.inputs.iter())
+                .zip(self.region_options.iter())
+                .map(|((r, input), &opts)| (r.align, input.len(), opts))
+                .collect();
+            win.placements = region::fit(&regions, win.width, self.region_gap);
+            for ((input, &(_, width)), opts) in win
+                .inputs
+                .iter_mut()
+                .zip(win.placements.iter())
+                .zip(self.region_options.iter())
+            {
+                input.fit(
+                    &self.xft,
+                    self.display,
+                    &self.fonts,
+                    &mut self.font_map,
+                    &mut self.glyph_widths,
+                    width,
+                    opts.overflow,
+                );
+            }
+        }
+    }
+
+    /// Run the command for a clickable area if the click landed on one. With the i3bar protocol
+    /// the click is written to stdout instead, if the generator asked for them.
+    ///
+    /// # Arguments
+    /// * event: -> the button press, with the x-value relative to the window clicked.
+    fn handle_click(&mut self, event: &xlib::XButtonEvent) {
+        let scroll = self.scroll();
+        let win = match self.windows.iter().find(|w| w.id == event.window) {
+            Some(win) => win,
+            None => return,
+        };
+        // Regions never overlap once they are fit, so the first match is the only one.
+        let found =
+            win.inputs
+                .iter()
+                .zip(win.placements.iter())
+                .find_map(|(input, &(offset, _))| {
+                    input
+                        .area_at(event.x - offset, event.button, scroll)
+                        .map(|area| (area, offset))
+                });
+        let (area, offset) = match found {
+            Some(found) => found,
+            None => return,
+        };
+        let id = match &area.target {
+            Target::Command(command) => return run_command(command),
+            Target::Module(name) => {
+                let changed = self.modules.click(name, event.button, Instant::now());
+                if !changed.is_empty() {
+                    self.load_modules(changed);
+                    unsafe { self.draw_all() };
+                }
+                return;
+            }
+            Target::Block(_) if !self.click_events => return,
+            Target::Block(id) => id,
+        };
+        let click = Click {
+            id: serde_json::from_str::<BlockId>(id).unwrap_or_default(),
+            button: event.button,
+            modifiers: modifiers(event.state),
+            x: event.x_root,
+            y: event.y_root,
+            relative_x: event.x - offset - area.start as i32,
+            relative_y: event.y,
+            width: area.end - area.start,
+            height: self.height as u32,
+        };
+        let json = match serde_json::to_string(&click) {
+            Ok(json) => json,
+            Err(e) => return eprintln!("Failed to write click event: {}", e),
+        };
+        // The clicks are one more array that never ends, the same as the updates.
+        let sep = if self.clicks_sent { "," } else { "[\n" };
+        self.clicks_sent = true;
+        let mut stdout = io::stdout();
+        if let Err(e) = writeln!(stdout, "{}{}", sep, json).and_then(|_| stdout.flush()) {
+            eprintln!("Failed to write click event: {}", e);
+        }
+    }
+
+    /// Pixels the marquees have scrolled by now.
+    fn scroll(&self) -> u32 {
+        (self.started.elapsed().as_millis() * self.marquee_speed as u128 / 1000) as u32
+    }
+
+    /// How often the marquees need to be redrawn to move a pixel at a time, kept to a sane
+    /// range so a fast speed doesn't spin and a slow one still moves.
+    ///
+    /// # Output
+    /// None when nothing is scrolling.
+    fn marquee_tick(&self) -> Option<Duration> {
+        if self.marquee_speed == 0
+            || self
+                .windows
+                .iter()
+                .flat_map(|w| w.inputs.iter())
+                .all(|i| i.scrolling().is_empty())
+        {
+            return None;
+        }
+        let millis = (1000 / self.marquee_speed as u64).clamp(16, 1000);
+        Some(Duration::from_millis(millis))
+    }
+
+    /// Redraw just the marquees that scroll, leaving the rest of the bar alone.
+    unsafe fn draw_marquees(&self) {
+        let scroll = self.scroll();
+        for win in self.windows.iter() {
+            for (input, &(offset, _)) in win.inputs.iter().zip(win.placements.iter()) {
+                let windows = input.scrolling();
+                if windows.is_empty() {
+                    continue;
+                }
+                for &(x, width) in windows.iter() {
+                    (self.xlib.XFillRectangle)(
+                        self.display,
+                        win.pixmap,
+                        win.gc,
+                        offset + x as i32,
+                        0,
+                        width,
+                        self.height as u32,
+                    );
+                }
+                input.draw_marquees(
+                    &self.xft,
+                    win.draw,
+                    &self.palette,
+                    &self.fonts,
+                    offset,
+                    self.font_y,
+                    self.height as u32,
+                    self.underline_height as u32,
+                    self.overline_height as u32,
+                    scroll,
+                );
+                for &(x, width) in windows.iter() {
+                    self.copy_to_window(win, offset + x as i32, 0, width, self.height as u32);
+                }
+            }
+        }
+    }
+
+    /// Redraw every window.
+    unsafe fn draw_all(&self) {
+        self.windows.iter().for_each(|win| self.draw_display(win));
+    }
+
+    /// Draw everything onto the pixmap of a window and then put it on the window.
+    unsafe fn draw_display(&self, win: &BarWindow) {
+        let scroll = self.scroll();
+        // Paint over the last frame with the background before we redraw.
+        (self.xlib.XFillRectangle)(
             self.display,
-            self.draw,
-            &self.palette,
-            &self.fonts,
+            win.pixmap,
+            win.gc,
             0,
-            self.font_y,
+            0,
+            win.width as u32,
             self.height as u32,
-            self.underline_height as u32,
         );
+        for (input, &(offset, _)) in win.inputs.iter().zip(win.placements.iter()) {
+            input.draw(
+                &self.xft,
+                win.draw,
+                &self.palette,
+                &self.fonts,
+                offset,
+                self.font_y,
+                self.height as u32,
+                self.underline_height as u32,
+                self.overline_height as u32,
+                scroll,
+            );
+        }
 
-        // center string.
-        self.center_string.draw(
-            &self.xft,
-            self.display,
-            self.draw,
-            &self.palette,
-            &self.fonts,
-            (self.width - self.center_string.len(&self.xft, self.display, &self.fonts) as i32) / 2,
-            self.font_y,
-            self.height as u32,
-            self.underline_height as u32,
-        );
+        self.copy_to_window(win, 0, 0, win.width as u32, self.height as u32);
+    }
 
-        // right string.
-        self.right_string.draw(
-            &self.xft,
+    /// Copy part of the pixmap onto the window. The pixmap always has the last frame drawn, so
+    /// Expose events can use this without doing any of the layout again.
+    unsafe fn copy_to_window(&self, win: &BarWindow, x: i32, y: i32, width: u32, height: u32) {
+        (self.xlib.XCopyArea)(
             self.display,
-            self.draw,
-            &self.palette,
-            &self.fonts,
-            self.width - self.right_string.len(&self.xft, self.display, &self.fonts) as i32,
-            self.font_y,
-            self.height as u32,
-            self.underline_height as u32,
+            win.pixmap,
+            win.id,
+            win.gc,
+            x,
+            y,
+            width,
+            height,
+            x,
+            y,
         );
     }
 
     pub fn close(&mut self, code: i32) -> ! {
-        println!("\nShutting down...");
+        eprintln!("\nShutting down...");
+        if let Some((_, path)) = self.control.take() {
+            let _ = fs::remove_file(path);
+        }
         unsafe {
             self.palette
                 .destroy(&self.xft, self.display, self.cmap, self.visual);
-            (self.xft.XftDrawDestroy)(self.draw);
+            self.windows.iter().for_each(|win| self.destroy_window(win));
             self.fonts
                 .iter()
                 .for_each(|&f| (self.xft.XftFontClose)(self.display, f));
             (self.xlib.XFreeColormap)(self.display, self.cmap);
-            (self.xlib.XDestroyWindow)(self.display, self.window_id);
             (self.xlib.XCloseDisplay)(self.display);
         }
         if let Some(km) = self.kill_me.as_mut() {
@@ -569,16 +1661,16 @@ impl Bar {
         let name = CString::new(name)?;
         Ok((self.xlib.XInternAtom)(
             self.display,
-            name.as_ptr() as *const i8,
+            name.as_ptr(),
             xlib::False,
         ))
     }
 
     unsafe fn get_font(&self, name: &str) -> Result<*mut xft::XftFont> {
         let name = CString::new(name)?;
-        let tmp = (self.xft.XftFontOpenName)(self.display, self.screen, name.as_ptr() as *const i8);
+        let tmp = (self.xft.XftFontOpenName)(self.display, self.screen, name.as_ptr());
         if tmp.is_null() {
-            panic!("Font {} not found!!", name.to_str()?)
+            Err(Error::FontNotFound(name.to_string_lossy().into_owned()).into())
         } else {
             Ok(tmp)
         }
@@ -588,13 +1680,16 @@ impl Bar {
         let name = CString::new(name)?;
 
         let mut tmp: MaybeUninit<xft::XftColor> = MaybeUninit::uninit();
-        (self.xft.XftColorAllocName)(
+        if (self.xft.XftColorAllocName)(
             self.display,
             self.visual,
             self.cmap,
-            name.as_ptr() as *const i8,
+            name.as_ptr(),
             tmp.as_mut_ptr(),
-        );
+        ) == 0
+        {
+            return Err(Error::BadColour(name.to_string_lossy().into_owned()).into());
+        }
         let tmp = tmp.assume_init();
         Ok(tmp)
     }
@@ -608,41 +1703,75 @@ impl Bar {
         Ok(temp.pixel)
     }
 
-    unsafe fn poll_events(&mut self) -> bool {
-        (self.xlib.XCheckWindowEvent)(
-            self.display,
-            self.window_id,
-            xlib::ButtonPressMask | xlib::ExposureMask,
-            self.event.as_mut_ptr(),
-        ) == 1
+    /// Deal with every X event that is waiting in the queue.
+    unsafe fn handle_events(&mut self) {
+        let mut monitors_changed = false;
+        while (self.xlib.XPending)(self.display) > 0 {
+            (self.xlib.XNextEvent)(self.display, self.event.as_mut_ptr());
+            let event_type = self.event.assume_init_ref().get_type();
+            // XRandR events are numbered from wherever the extension was given, so they can't be
+            // matched on with the core events.
+            if let Some((xrr, base)) = &self.randr {
+                if event_type == base + xrandr::RRScreenChangeNotify
+                    || event_type == base + xrandr::RRNotify
+                {
+                    // Lets Xlib know the size of the XDisplay changed.
+                    (xrr.XRRUpdateConfiguration)(self.event.as_mut_ptr());
+                    monitors_changed = true;
+                    continue;
+                }
+            }
+            match event_type {
+                // if part of the bar is uncovered we put back that bit of the last frame.
+                xlib::Expose => {
+                    let e = self.event.assume_init_ref().expose;
+                    if let Some(win) = self.windows.iter().find(|w| w.id == e.window) {
+                        self.copy_to_window(win, e.x, e.y, e.width as u32, e.height as u32);
+                    }
+                }
+                // clicks get checked against the clickable areas of each input.
+                xlib::ButtonPress => {
+                    let button = self.event.assume_init_ref().button;
+                    self.handle_click(&button);
+                }
+                // ignore all other events
+                _ => (),
+            }
+        }
+        if monitors_changed {
+            if let Err(e) = self.update_monitors() {
+                eprintln!("Could not update the monitors: {}", e);
+            }
+        }
     }
 
-    unsafe fn set_atoms(&mut self) -> Result<()> {
+    unsafe fn set_atoms(&self, win: &BarWindow) -> Result<()> {
         // Set the WM_NAME.
         let name = format!("Unibar_{}", self.name);
         let title = CString::new(name)?;
-        (self.xlib.XStoreName)(self.display, self.window_id, title.as_ptr() as *mut i8);
+        (self.xlib.XStoreName)(self.display, win.id, title.as_ptr() as *mut i8);
         // Set WM_CLASS
         let class: *mut xlib::XClassHint = (self.xlib.XAllocClassHint)();
         let cl_names = [CString::new("unibar")?, CString::new("Unibar")?];
         (*class).res_name = cl_names[0].as_ptr() as *mut i8;
         (*class).res_class = cl_names[1].as_ptr() as *mut i8;
-        (self.xlib.XSetClassHint)(self.display, self.window_id, class);
+        (self.xlib.XSetClassHint)(self.display, win.id, class);
         // Set WM_CLIENT_MACHINE
         let hn_size = libc::sysconf(libc::_SC_HOST_NAME_MAX) as libc::size_t;
-        let hn_buffer: *mut i8 = vec![0i8; hn_size].as_mut_ptr();
+        let mut hn_vec = vec![0i8; hn_size];
+        let hn_buffer: *mut i8 = hn_vec.as_mut_ptr();
         libc::gethostname(hn_buffer, hn_size);
         let mut hn_list = [hn_buffer];
         let mut hn_text_prop: std::mem::MaybeUninit<xlib::XTextProperty> = MaybeUninit::uninit();
         (self.xlib.XStringListToTextProperty)(hn_list.as_mut_ptr(), 1, hn_text_prop.as_mut_ptr());
         let mut hn_text_prop = hn_text_prop.assume_init();
-        (self.xlib.XSetWMClientMachine)(self.display, self.window_id, &mut hn_text_prop);
+        (self.xlib.XSetWMClientMachine)(self.display, win.id, &mut hn_text_prop);
         // Set _NET_WM_PID
         let pid = [process::id()].as_ptr();
         let wm_pid_atom = self.get_atom("_NET_WM_PID")?;
         (self.xlib.XChangeProperty)(
             self.display,
-            self.window_id,
+            win.id,
             wm_pid_atom,
             xlib::XA_CARDINAL,
             32,
@@ -656,7 +1785,7 @@ impl Bar {
         let wm_dktp_atom = self.get_atom("_NET_WM_DESKTOP")?;
         (self.xlib.XChangeProperty)(
             self.display,
-            self.window_id,
+            win.id,
             wm_dktp_atom,
             xlib::XA_CARDINAL,
             32,
@@ -673,7 +1802,7 @@ impl Bar {
         ];
         (self.xlib.XChangeProperty)(
             self.display,
-            self.window_id,
+            win.id,
             wm_state_atom,
             xlib::XA_ATOM,
             32,
@@ -682,18 +1811,39 @@ impl Bar {
             2,
         );
 
+        self.set_struts(win)?;
+
+        // Set the _NET_WM_WINDOW_TYPE atom
+        let win_type_atom = self.get_atom("_NET_WM_WINDOW_TYPE")?;
+        let dock_atom = [self.get_atom("_NET_WM_WINDOW_TYPE_DOCK")?];
+        (self.xlib.XChangeProperty)(
+            self.display,
+            win.id,
+            win_type_atom,
+            xlib::XA_ATOM,
+            32,
+            xlib::PropModeReplace,
+            dock_atom.as_ptr() as *const u8,
+            1,
+        );
+        Ok(())
+    }
+
+    /// Reserve the space the window takes up, so other windows aren't put under it. Struts are
+    /// measured from the edge of the whole XDisplay, not the monitor.
+    unsafe fn set_struts(&self, win: &BarWindow) -> Result<()> {
         // Set the _NET_WM_STRUT[_PARTIAL]
         // TOP    = 2 -> height, 8 -> start x, 9 -> end x
         // BOTTOM = 3 -> height, 10 -> start x, 11 -> end x
         let mut strut: [i64; 12] = [0; 12];
         if self.top {
-            strut[2] = self.height as i64;
-            strut[8] = self.x as i64;
-            strut[9] = (self.x + self.width - 1) as i64;
+            strut[2] = (win.y + self.height) as i64;
+            strut[8] = win.x as i64;
+            strut[9] = (win.x + win.width - 1) as i64;
         } else {
-            strut[3] = self.height as i64;
-            strut[10] = self.x as i64;
-            strut[11] = (self.x + self.width - 1) as i64;
+            strut[3] = ((self.xlib.XDisplayHeight)(self.display, self.screen) - win.y) as i64;
+            strut[10] = win.x as i64;
+            strut[11] = (win.x + win.width - 1) as i64;
         }
         let strut_atoms = [
             self.get_atom("_NET_WM_STRUT_PARTIAL")?,
@@ -701,7 +1851,7 @@ impl Bar {
         ];
         (self.xlib.XChangeProperty)(
             self.display,
-            self.window_id,
+            win.id,
             strut_atoms[0],
             xlib::XA_CARDINAL,
             32,
@@ -711,7 +1861,7 @@ impl Bar {
         );
         (self.xlib.XChangeProperty)(
             self.display,
-            self.window_id,
+            win.id,
             strut_atoms[1],
             xlib::XA_CARDINAL,
             32,
@@ -719,20 +1869,6 @@ impl Bar {
             strut.as_ptr() as *const u8,
             4,
         );
-
-        // Set the _NET_WM_WINDOW_TYPE atom
-        let win_type_atom = self.get_atom("_NET_WM_WINDOW_TYPE")?;
-        let dock_atom = [self.get_atom("_NET_WM_WINDOW_TYPE_DOCK")?];
-        (self.xlib.XChangeProperty)(
-            self.display,
-            self.window_id,
-            win_type_atom,
-            xlib::XA_ATOM,
-            32,
-            xlib::PropModeReplace,
-            dock_atom.as_ptr() as *const u8,
-            1,
-        );
         Ok(())
     }
 }
@@ -0,0 +1,57 @@
+// Send commands to a running bar over its unix socket.
+// By: Curtis Jones <mail@curtisjones.ca>
+// Started on: October 18, 2026
+
+use anyhow::{Context, Result};
+use clap::clap_app;
+use std::{
+    io::{Read, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    process,
+};
+use unibar::control::{self, Command};
+
+fn main() -> Result<()> {
+    let matches = clap_app!(unibar_msg =>
+    (version: env!("CARGO_PKG_VERSION"))
+    (author: "Curtis Jones <mail@curtisjones.ca>")
+    (about: "Send a command to a running Unibar.")
+    (@setting TrailingVarArg)
+    (@setting ColoredHelp)
+    (@arg NAME:    *     +takes_value "Name of the bar, the same one it was started with")
+    (@arg COMMAND: * ... +takes_value +allow_hyphen_values "hide, show, toggle, reload, set <region> <markup>, get, palette <index> <colour>, or quit")
+    )
+    .get_matches();
+    let name = matches
+        .value_of("NAME")
+        .expect("Clap verifies this arguement is present before we get to this point.");
+    let line = matches
+        .values_of("COMMAND")
+        .expect("Clap verifies this arguement is present before we get to this point.")
+        .collect::<Vec<&str>>()
+        .join(" ");
+    // Catch typos before bothering the bar with them.
+    if let Err(e) = Command::parse(&line) {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+
+    let path = control::socket_path(name)?;
+    let mut stream = UnixStream::connect(&path).with_context(|| {
+        format!(
+            "Could not connect to {}, is the bar running?",
+            path.display()
+        )
+    })?;
+    writeln!(stream, "{}", line)?;
+    // Closing our end tells the bar there are no more commands coming.
+    stream.shutdown(Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    print!("{}", reply);
+    if reply.lines().any(|l| l.starts_with("error:")) {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -4,31 +4,104 @@
 
 // gonna start by implementing the loading from file bits.
 
+use super::monitor::Fallback;
+use super::optional::{ModuleConfig, ModuleOptions};
+use super::region::{RegionConfig, RegionOptions};
 use anyhow::Result;
 use clap::clap_app;
 use dirs::config_dir;
+use std::collections::HashMap;
 use std::fs::read_to_string;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, thiserror::Error)]
 #[error("[{0}] is not a valid option!")]
 pub struct Error(String);
 
+/// Which markup the lines of input are written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// Our own {F1}..{/F} blocks with <|> splitters.
+    Unibar,
+    /// Lemonbar style %{F#fff} blocks with %{l}, %{c}, and %{r} for alignment.
+    Lemonbar,
+    /// The i3bar JSON protocol, with clicks written back to stdout when asked for.
+    I3bar,
+}
+
 #[derive(Debug)]
 pub struct Config {
-    pub name: String,                // name of the bar
-    pub top: bool,                   // top or bottom
-    pub monitor: String,             // xinerama montior list index for monitor
-    pub height: i32,                 // width or height of bar depending on pos.
-    pub width: Option<i32>,          // width or height of bar depending on pos.
-    pub ul_height: i32,              // width or height of bar depending on pos.
-    pub fonts: Vec<String>,          // Vec of strings listing the fonts in FcLookup form.
-    pub font_y: i32,                 // pixel offset from the top of bar to bottom font.
-    pub back_color: String,          // String of the hex color.
-    pub ft_clrs: Vec<String>,        // String of the hex color.
-    pub bg_clrs: Vec<String>,        // String of the hex color.
-    pub ul_clrs: Vec<String>,        // String of the hex color.
+    pub name: String,                                   // name of the bar
+    pub top: bool,                                      // top or bottom
+    pub format: InputFormat,                            // markup used by the input.
+    pub strict: bool,                                   // throw out lines with bad markup.
+    pub monitor: String,                                // xinerama montior list index for monitor
+    pub monitor_fallback: Fallback,                     // hide or move when monitor unplugged.
+    pub height: i32,                                    // width or height of bar depending on pos.
+    pub width: Option<i32>,                             // width or height of bar depending on pos.
+    pub ul_height: i32,                                 // width or height of bar depending on pos.
+    pub ol_height: i32,                                 // height of the overline highlights.
+    pub fonts: Vec<String>, // Vec of strings listing the fonts in FcLookup form.
+    pub font_y: i32,        // pixel offset from the top of bar to bottom font.
+    pub back_color: String, // String of the hex color.
+    pub ft_clrs: Vec<String>, // String of the hex color.
+    pub bg_clrs: Vec<String>, // String of the hex color.
+    pub ul_clrs: Vec<String>, // String of the hex color.
+    pub ol_clrs: Vec<String>, // String of the hex color.
+    pub regions: Vec<RegionConfig>, // Named regions, left, center, and right if empty.
+    pub region_gap: i32,    // pixels between regions on the same side.
+    pub region_options: HashMap<String, RegionOptions>, // priority and size of regions by name.
+    pub templates: HashMap<String, String>, // where module output goes in a region, by name.
+    pub modules: Vec<ModuleConfig>, // Built-in modules to run, in order.
+    pub module_options: HashMap<String, ModuleOptions>, // options of each module by name.
+    pub marquee_speed: u32, // pixels per second marquees scroll, 0 to stop them.
     pub kill_me_cmd: Option<String>, // Command to run on each pid for kill_me module.
+    pub watch_config: bool, // reload when the config file is saved.
+    pub source: Option<Source>, // where the config came from, to load it again.
+}
+
+/// Where a Config came from, the file and the CLI args laid on top of it, so the same Config can
+/// be loaded again after the file changes.
+#[derive(Clone, Debug)]
+pub struct Source {
+    name: String,
+    file: Option<PathBuf>,
+    strict: bool,
+    // Options that take one value and the ones that take many, in the order they are applied.
+    single: Vec<(&'static str, String)>,
+    multi: Vec<(&'static str, Vec<String>)>,
+}
+
+impl Source {
+    /// The config file, None when it was skipped with --noconfig.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Read the config file again and apply the same CLI args over it.
+    ///
+    /// # Output
+    /// A fresh Config, holding onto this Source.
+    pub fn load(&self) -> Result<Config> {
+        let mut tmp = match &self.file {
+            Some(file) => Config::from_file(file.clone())?,
+            None => Config::default(),
+        };
+        // Set the name first as we got it earlier.
+        tmp.change_option("NAME", &self.name)?;
+        // The only flag that is also a config option.
+        if self.strict {
+            tmp.strict = true;
+        }
+        for (opt, val) in self.single.iter() {
+            tmp.change_option(opt, val)?;
+        }
+        for (opt, vals) in self.multi.iter() {
+            tmp.replace_opt(opt, vals.clone())?;
+        }
+        tmp.source = Some(self.clone());
+        Ok(tmp)
+    }
 }
 
 impl Config {
@@ -36,17 +109,31 @@ impl Config {
         Config {
             name: String::new(),
             top: true,
+            format: InputFormat::Unibar,
+            strict: false,
             monitor: String::new(),
+            monitor_fallback: Fallback::Hide,
             height: 32,
             width: None,
             ul_height: 4,
+            ol_height: 4,
             fonts: vec![String::from("mono:size=12")],
             font_y: 20,
             back_color: String::from("#000000"),
             ft_clrs: vec![String::from("#FFFFFF")],
             bg_clrs: vec![String::from("#0000FF")],
             ul_clrs: vec![String::from("#FF0000")],
+            ol_clrs: vec![String::from("#FF0000")],
+            regions: Vec::new(),
+            region_gap: 0,
+            region_options: HashMap::new(),
+            templates: HashMap::new(),
+            modules: Vec::new(),
+            module_options: HashMap::new(),
+            marquee_speed: 30,
             kill_me_cmd: None,
+            watch_config: false,
+            source: None,
         }
     }
 
@@ -63,19 +150,26 @@ impl Config {
         (author: "Curtis Jones <mail@curtisjones.ca>")
         (about: "Simple Xorg display bar!")
         (@arg NO_CONFIG:      -C --noconfig                   "Tells Unibar to skip loading a config file.")
+        (@arg STRICT:            --strict                     "Ignore input lines with bad markup instead of drawing what we can.")
         (@arg CONFIG:         -c --config        +takes_value "Sets a custom config file")
         (@arg NAME:           *                  +takes_value "Sets name and is required")
         (@arg POSITION:       -p --position      +takes_value "overrides config file position option")
+        (@arg FORMAT:            --format        +takes_value "sets the input markup, either unibar, lemonbar, or i3bar")
+        (@arg PROTOCOL:          --protocol      +takes_value "same as --format, i3bar reads the i3bar JSON protocol")
         (@arg MONITOR:        -m --monitor       +takes_value "sets the monitor number to use. starts at 1")
         (@arg DEF_BACKGROUND: -b --background    +takes_value "overrides config file default background")
         (@arg HEIGHT:         -h --height        +takes_value "overrides config file bar height option")
         (@arg WIDTH:          -w --width         +takes_value "overrides config file bar width option")
         (@arg UNDERLINE:      -u --underline     +takes_value "overrides config file underline height option")
+        (@arg OVERLINE:       -o --overline      +takes_value "overrides config file overline height option")
         (@arg FONT_Y:         -y --fonty         +takes_value "overrides config file font y offset option")
         (@arg FONTS:          -f --fonts     ... +takes_value "overrides config file font options")
         (@arg FT_COLOURS:     -F --ftcolours ... +takes_value "overrides config file font colours")
         (@arg BG_COLOURS:     -B --bgcolours ... +takes_value "overrides config file background highlight colours")
         (@arg UL_COLOURS:     -U --ulcolours ... +takes_value "overrides config file underline highlight colours")
+        (@arg OL_COLOURS:     -O --olcolours ... +takes_value "overrides config file overline highlight colours")
+        (@arg REGIONS:        -r --regions   ... +takes_value "overrides config file regions, each as name:align")
+        (@arg REGION_GAP:     -g --gap           +takes_value "overrides config file gap between regions option")
         (@arg KILL_ME_CMD:    -k --killme        +takes_value "Enabled kill_me module and set the command to use.")
         )
         .help_short("H") // We are using the lowercase h to set height.
@@ -106,37 +200,50 @@ impl Config {
         // Whatever we chose in the previous step we now try to load that config file.
         // IF we are loading a config file then we use the value generated from bar name, if not we use
         // the default Config.
-        let mut tmp = if matches.is_present("NO_CONFIG") {
-            Config::default()
-        } else {
-            Config::from_file(conf_opt)?
+        let mut source = Source {
+            name: name.to_string(),
+            file: Some(conf_opt).filter(|_| !matches.is_present("NO_CONFIG")),
+            strict: matches.is_present("STRICT"),
+            single: Vec::new(),
+            multi: Vec::new(),
         };
-        // Set the name first as we got it earlier.
-        tmp.change_option("NAME", name)?;
         // Now we alter the loaded Config object with the CLI args.
         // First we check all of the options that only take one val.
         for opt in &[
             "MONITOR",
             "POSITION",
+            "FORMAT",
+            "PROTOCOL",
             "DEF_BACKGROUND",
             "HEIGHT",
             "WIDTH",
             "UNDERLINE",
+            "OVERLINE",
             "FONT_Y",
+            "REGION_GAP",
             "KILL_ME_CMD",
         ] {
             if let Some(s) = matches.value_of(opt) {
-                tmp.change_option(opt, s)?;
+                source.single.push((opt, s.to_string()));
             }
         }
         // Next we check all of the options that take multiple vals.
-        for opt in &["FONTS", "FT_COLOURS", "BG_COLOURS", "UL_COLOURS"] {
+        for opt in &[
+            "FONTS",
+            "FT_COLOURS",
+            "BG_COLOURS",
+            "UL_COLOURS",
+            "OL_COLOURS",
+            "REGIONS",
+        ] {
             if let Some(strs) = matches.values_of(opt) {
-                tmp.replace_opt(opt, strs.map(|s| s.to_string()).collect())?;
+                source
+                    .multi
+                    .push((opt, strs.map(|s| s.to_string()).collect()));
             }
         }
         // Return the final Config to be used.
-        Ok(tmp)
+        source.load()
     }
 
     pub fn from_file(file: PathBuf) -> Result<Config> {
@@ -181,6 +288,9 @@ impl Config {
         if tmp.ul_clrs.len() > 1 {
             tmp.ul_clrs.remove(0);
         }
+        if tmp.ol_clrs.len() > 1 {
+            tmp.ol_clrs.remove(0);
+        }
 
         // Return our temp variable.
         Ok(tmp)
@@ -203,7 +313,22 @@ impl Config {
                 "bottom" => self.top = false,
                 _ => eprintln!("Invaild position option!"),
             },
+            "format" | "protocol" => match &val.to_lowercase()[..] {
+                "unibar" => self.format = InputFormat::Unibar,
+                "lemonbar" => self.format = InputFormat::Lemonbar,
+                "i3bar" | "swaybar" => self.format = InputFormat::I3bar,
+                _ => eprintln!("Invaild format option! Needs to be unibar, lemonbar, or i3bar."),
+            },
+            "strict" => match &val.to_lowercase()[..] {
+                "true" => self.strict = true,
+                "false" => self.strict = false,
+                _ => eprintln!("Invaild strict option! Needs to be true or false."),
+            },
             "monitor" => self.monitor = val,
+            "monitor_fallback" => match Fallback::from_str(&val) {
+                Some(f) => self.monitor_fallback = f,
+                None => eprintln!("Invaild monitor_fallback option! Needs to be hide or primary."),
+            },
             "width" => {
                 if let Ok(s) = val.parse::<i32>() {
                     self.width = Some(s);
@@ -225,6 +350,13 @@ impl Config {
                     eprintln!("Invaild highlight_size option! Needs to be a digit representable by a 32-bit integer.");
                 }
             }
+            "overline_height" => {
+                if let Ok(s) = val.parse::<i32>() {
+                    self.ol_height = s;
+                } else {
+                    eprintln!("Invaild overline_height option! Needs to be a digit representable by a 32-bit integer.");
+                }
+            }
             "font" => self.fonts.push(val),
             "font_y" => {
                 if let Ok(y) = val.parse::<i32>() {
@@ -237,7 +369,60 @@ impl Config {
             "ft_colour" => self.ft_clrs.push(val),
             "background_colour" => self.bg_clrs.push(val),
             "highlight_colour" => self.ul_clrs.push(val),
+            "overline_colour" => self.ol_clrs.push(val),
+            "region" => self.push_region(&val),
+            // Options for a single region look like region.name.option, the keys are lowercased
+            // so the names are matched without case.
+            _ if opt.starts_with("region.") => {
+                let mut split = opt["region.".len()..].rsplitn(2, '.');
+                let (key, name) = (split.next().unwrap_or(""), split.next().unwrap_or(""));
+                // Templates hold markup, so they are kept apart from the options that are numbers.
+                if key == "template" && !name.is_empty() {
+                    self.templates.insert(name.to_string(), val);
+                } else if name.is_empty()
+                    || !self
+                        .region_options
+                        .entry(name.to_string())
+                        .or_default()
+                        .set(key, &val)
+                {
+                    eprintln!("Invaild {} option! Needs to be priority, max_width, or overflow with a valid value.", opt);
+                }
+            }
+            "module" => self.push_module(&val),
+            // Module options are only checked once the module is made, since each kind has its own.
+            _ if opt.starts_with("module.") => {
+                let mut split = opt["module.".len()..].rsplitn(2, '.');
+                let (key, name) = (split.next().unwrap_or(""), split.next().unwrap_or(""));
+                if name.is_empty() || key.is_empty() {
+                    eprintln!("Invaild {} option! Needs to be module.name.option.", opt);
+                } else {
+                    self.module_options
+                        .entry(name.to_string())
+                        .or_default()
+                        .insert(key.to_string(), val);
+                }
+            }
+            "region_gap" => {
+                if let Ok(s) = val.parse::<i32>() {
+                    self.region_gap = s;
+                } else {
+                    eprintln!("Invaild region_gap option! Needs to be a digit representable by a 32-bit integer.");
+                }
+            }
+            "marquee_speed" => {
+                if let Ok(s) = val.parse::<u32>() {
+                    self.marquee_speed = s;
+                } else {
+                    eprintln!("Invaild marquee_speed option! Needs to be a positive digit representable by a 32-bit integer.");
+                }
+            }
             "kill_me_cmd" => self.kill_me_cmd = Some(val),
+            "watch_config" => match &val.to_lowercase()[..] {
+                "true" => self.watch_config = true,
+                "false" => self.watch_config = false,
+                _ => eprintln!("Invaild watch_config option! Needs to be true or false."),
+            },
             _ => return Err(Error(opt.into())),
         }
         Ok(())
@@ -250,8 +435,45 @@ impl Config {
             "ft_colours" => self.ft_clrs = vals,
             "bg_colours" => self.bg_clrs = vals,
             "ul_colours" => self.ul_clrs = vals,
+            "ol_colours" => self.ol_clrs = vals,
+            "regions" => {
+                self.regions.clear();
+                vals.iter().for_each(|v| self.push_region(v));
+            }
             _ => return Err(Error(opt.into())),
         }
         Ok(())
     }
+
+    /// Add a module from its name or name:kind value, skipping it with a warning if it isn't
+    /// valid or the name is already taken.
+    fn push_module(&mut self, val: &str) {
+        match ModuleConfig::parse(val) {
+            Some(m) if self.modules.iter().any(|o| o.name == m.name) => {
+                eprintln!(
+                    "Module {} is set more than once, only the first is used.",
+                    m.name
+                )
+            }
+            Some(m) => self.modules.push(m),
+            None => eprintln!("Invaild module option! Needs to be name or name:kind."),
+        }
+    }
+
+    /// Add a region from its name:align value, skipping it with a warning if it isn't valid or
+    /// the name is already taken.
+    fn push_region(&mut self, val: &str) {
+        match RegionConfig::parse(val) {
+            Some(r) if self.regions.iter().any(|o| o.name == r.name) => {
+                eprintln!(
+                    "Region {} is set more than once, only the first is used.",
+                    r.name
+                )
+            }
+            Some(r) => self.regions.push(r),
+            None => eprintln!(
+                "Invaild region option! Needs to be name:left, name:center, or name:right."
+            ),
+        }
+    }
 }
@@ -0,0 +1,219 @@
+// Commands sent to a running bar over its unix socket, by unibar-msg or anything else.
+// By: Curtis Jones <mail@curtisjones.ca>
+// Started on: October 18, 2026
+//
+
+use dirs::runtime_dir;
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("No command given")]
+    Empty,
+    #[error("Unknown command -> {0} <-")]
+    Unknown(String),
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("[{0}] is not a valid colour index, needs to be like F1, B0, H2, or O1")]
+    Index(String),
+    #[error("XDG_RUNTIME_DIR is not set, so there is nowhere to put the socket")]
+    NoRuntimeDir,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Which list of colours in the palette to change.
+pub enum PaletteKind {
+    Font,
+    Background,
+    Underline,
+    Overline,
+}
+
+impl PaletteKind {
+    /// Letter used for this kind of colour in markup.
+    pub fn letter(self) -> char {
+        match self {
+            PaletteKind::Font => 'F',
+            PaletteKind::Background => 'B',
+            PaletteKind::Underline => 'H',
+            PaletteKind::Overline => 'O',
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// One line sent to the socket.
+pub enum Command {
+    /// Unmap every window.
+    Hide,
+    /// Map the windows again.
+    Show,
+    /// Hide if shown, show if hidden.
+    Toggle,
+    /// Read the config file again.
+    Reload,
+    /// Replace the markup of one region, written in unibar markup.
+    Set { region: String, markup: String },
+    /// Send back the text of every region.
+    Get,
+    /// Change a colour in the palette, used by the {F1} style blocks.
+    Palette {
+        kind: PaletteKind,
+        index: usize,
+        colour: String,
+    },
+    /// Shut the bar down.
+    Quit,
+}
+
+impl Command {
+    /// Read a command from a line sent to the socket. The command is the first word, the rest
+    /// are its arguments.
+    ///
+    /// # Arguments
+    /// * line: -> line read from the socket, without the newline.
+    pub fn parse(line: &str) -> Result<Command, Error> {
+        let line = line.trim();
+        let (cmd, rest) = match line.find(char::is_whitespace) {
+            Some(idx) => (&line[..idx], line[idx..].trim_start()),
+            None => (line, ""),
+        };
+        let cmd = match &cmd.to_lowercase()[..] {
+            "" => return Err(Error::Empty),
+            "hide" => Command::Hide,
+            "show" => Command::Show,
+            "toggle" => Command::Toggle,
+            "reload" => Command::Reload,
+            "get" => Command::Get,
+            "quit" => Command::Quit,
+            "set" => {
+                // The markup is everything after the region name, spaces and all.
+                let (region, markup) = match rest.find(char::is_whitespace) {
+                    Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+                    None => (rest, ""),
+                };
+                if region.is_empty() {
+                    return Err(Error::Usage("set <region> <markup>"));
+                }
+                Command::Set {
+                    region: region.to_string(),
+                    markup: markup.to_string(),
+                }
+            }
+            "palette" => {
+                let mut args = rest.split_whitespace();
+                let (index, colour) = match (args.next(), args.next(), args.next()) {
+                    (Some(index), Some(colour), None) => (index, colour),
+                    _ => return Err(Error::Usage("palette <index> <colour>")),
+                };
+                let (kind, index) = parse_index(index)?;
+                Command::Palette {
+                    kind,
+                    index,
+                    colour: colour.to_string(),
+                }
+            }
+            _ => return Err(Error::Unknown(cmd.to_string())),
+        };
+        Ok(cmd)
+    }
+}
+
+/// Read a palette index the way it is written in markup, F1 or B0 and so on. A plain number is a
+/// font colour, and U works for underlines too like the -U flag.
+pub fn parse_index(s: &str) -> Result<(PaletteKind, usize), Error> {
+    let (kind, num) = match s.chars().next() {
+        Some('F') | Some('f') => (PaletteKind::Font, &s[1..]),
+        Some('B') | Some('b') => (PaletteKind::Background, &s[1..]),
+        Some('H') | Some('h') | Some('U') | Some('u') => (PaletteKind::Underline, &s[1..]),
+        Some('O') | Some('o') => (PaletteKind::Overline, &s[1..]),
+        _ => (PaletteKind::Font, s),
+    };
+    num.parse::<usize>()
+        .map(|idx| (kind, idx))
+        .map_err(|_| Error::Index(s.to_string()))
+}
+
+/// Where the socket for a bar lives, $XDG_RUNTIME_DIR/unibar/<name>.sock.
+///
+/// # Arguments
+/// * name: -> name of the bar, the same one it was started with.
+pub fn socket_path(name: &str) -> Result<PathBuf, Error> {
+    let mut path = runtime_dir().ok_or(Error::NoRuntimeDir)?;
+    path.push("unibar");
+    path.push(format!("{}.sock", name));
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_commands() {
+        assert_eq!(Command::parse("hide"), Ok(Command::Hide));
+        assert_eq!(Command::parse(" Toggle \n"), Ok(Command::Toggle));
+        assert_eq!(Command::parse("QUIT"), Ok(Command::Quit));
+        assert_eq!(Command::parse(""), Err(Error::Empty));
+        assert_eq!(
+            Command::parse("explode now"),
+            Err(Error::Unknown("explode".to_string()))
+        );
+    }
+
+    #[test]
+    fn set_keeps_the_markup_whole() {
+        assert_eq!(
+            Command::parse("set clock {F1}12:00  {B2}pm"),
+            Ok(Command::Set {
+                region: "clock".to_string(),
+                markup: "{F1}12:00  {B2}pm".to_string(),
+            })
+        );
+        // Setting nothing clears the region.
+        assert_eq!(
+            Command::parse("set clock"),
+            Ok(Command::Set {
+                region: "clock".to_string(),
+                markup: String::new(),
+            })
+        );
+        assert_eq!(
+            Command::parse("set"),
+            Err(Error::Usage("set <region> <markup>"))
+        );
+    }
+
+    #[test]
+    fn palette_indexes() {
+        let palette = |kind, index| Command::Palette {
+            kind,
+            index,
+            colour: "#FF0000".to_string(),
+        };
+        assert_eq!(
+            Command::parse("palette 2 #FF0000"),
+            Ok(palette(PaletteKind::Font, 2))
+        );
+        assert_eq!(
+            Command::parse("palette b0 #FF0000"),
+            Ok(palette(PaletteKind::Background, 0))
+        );
+        assert_eq!(
+            Command::parse("palette H3 #FF0000"),
+            Ok(palette(PaletteKind::Underline, 3))
+        );
+        assert_eq!(
+            Command::parse("palette O1 #FF0000"),
+            Ok(palette(PaletteKind::Overline, 1))
+        );
+        assert_eq!(
+            Command::parse("palette X1 #FF0000"),
+            Err(Error::Index("X1".to_string()))
+        );
+        assert_eq!(
+            Command::parse("palette 1"),
+            Err(Error::Usage("palette <index> <colour>"))
+        );
+    }
+}
@@ -3,12 +3,20 @@
 // Started on: September 07, 2020
 //
 
-use anyhow::Result;
+use super::{
+    layout::{self, ActionArea, FontMetrics, Layout, MarqueeArea},
+    markup::{Colour, Limits, Markup},
+    region::Overflow,
+};
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
     mem::MaybeUninit,
+    ptr,
+};
+use x11_dl::{
+    xft, xlib,
+    xrender::{XGlyphInfo, XRenderColor},
 };
-use x11_dl::{xft, xlib, xrender::XGlyphInfo};
 
 /// Utility funtion so get the index of the first font that has a glyph for the provided char.
 ///
@@ -34,42 +42,44 @@ unsafe fn default_font_idx(
         .unwrap_or(0)
 }
 
-/// Utility funtion so get the pixel width of a chunk of characters in a given font.
+/// Utility funtion so get the pixel advance of a character in a given font, how far the next
+/// character starts from this one.
 ///
 /// # Arguments
 /// * xft:   -> reference to the xft lib.
 /// * dpy:   -> pointer to the XDisplay object.
-/// * font   -> pointer to the font we are checking the width of the string in.
-/// * string -> refernce to the string we want the width of.
+/// * font   -> pointer to the font we are checking the width of the char in.
+/// * chr    -> character we want the advance of.
 ///
 /// # Output
-/// Returns a c_uint representing the pixel with of the <string> arg.
-unsafe fn string_pixel_width(
+/// Returns a c_uint representing the pixel advance of the <chr> arg.
+unsafe fn char_pixel_advance(
     xft: &xft::Xft,
     dpy: *mut xlib::Display,
     font: *mut xft::XftFont,
-    string: &str,
+    chr: char,
 ) -> u32 {
     // Rust gets mad if you don't initialize a variable before providing it as a function arg so we
     // lie to the rust compiler.
     let mut extents: MaybeUninit<XGlyphInfo> = MaybeUninit::uninit();
-    // let mut extents: XGlyphInfo = init!();
+    let mut buf = [0u8; 4];
+    let bytes = chr.encode_utf8(&mut buf).as_bytes();
 
-    // Getting just so much info about the glyphs to be printed for the string arg when using the
+    // Getting just so much info about the glyph to be printed for the char arg when using the
     // font provided.
     (xft.XftTextExtentsUtf8)(
         dpy,
         font,
-        string.as_bytes().as_ptr() as *mut u8,
-        string.as_bytes().len() as i32,
-        // &mut extents,
+        bytes.as_ptr() as *mut u8,
+        bytes.len() as i32,
         extents.as_mut_ptr(),
     );
 
     let extents = extents.assume_init();
 
-    // All that nice info and we just need the width.
-    extents.width as u32
+    // All that nice info and we just need the advance. Adding up advances gives the same
+    // positions Xft uses when it draws a whole string.
+    extents.xOff.max(0) as u32
 }
 
 /// Private struct to contain colour information for the status bar.
@@ -79,8 +89,12 @@ pub struct ColourPalette {
     pub background: Vec<xft::XftColor>,
     /// Colours for the underline highlight.
     pub underline: Vec<xft::XftColor>,
+    /// Colours for the overline highlight.
+    pub overline: Vec<xft::XftColor>,
     /// Colours for the fonts.
     pub font: Vec<xft::XftColor>,
+    /// Cache of the literal colours used in inputs, allocated the first time we see them.
+    literal: HashMap<u32, xft::XftColor>,
 }
 
 impl ColourPalette {
@@ -92,7 +106,138 @@ impl ColourPalette {
         ColourPalette {
             background: Vec::new(),
             underline: Vec::new(),
+            overline: Vec::new(),
             font: Vec::new(),
+            literal: HashMap::new(),
+        }
+    }
+
+    /// Make sure a colour can be drawn with, allocating literal colours into the cache if we
+    /// haven't seen them before. Indexes were already checked when the input was parsed.
+    ///
+    /// # Arguments
+    /// * xft:    -> Reference to the Xft library for XftColorAllocValue.
+    /// * dpy:    -> Pointer to the Display that the bar is using.
+    /// * visual: -> Pointer to the Visual for the active Display.
+    /// * cmap:   -> Pointer to the Colormap for the active Display.
+    /// * colour: -> Colour to check.
+    ///
+    /// # Output
+    /// True if the colour is ready to be used.
+    fn ensure(
+        &mut self,
+        xft: &xft::Xft,
+        dpy: *mut xlib::Display,
+        visual: *mut xlib::Visual,
+        cmap: xlib::Colormap,
+        colour: Colour,
+    ) -> bool {
+        let argb = match colour {
+            Colour::Index(_) => return true,
+            Colour::Literal(argb) => argb,
+        };
+        if let Entry::Vacant(v) = self.literal.entry(argb) {
+            // XRender wants 16 bit channels, so we just repeat the byte to fill it out.
+            let channel = |shift: u32| ((argb >> shift) & 0xFF) as u16 * 0x101;
+            let render = XRenderColor {
+                red: channel(16),
+                green: channel(8),
+                blue: channel(0),
+                alpha: channel(24),
+            };
+            let mut tmp: MaybeUninit<xft::XftColor> = MaybeUninit::uninit();
+            unsafe {
+                if (xft.XftColorAllocValue)(dpy, visual, cmap, &render, tmp.as_mut_ptr()) == 0 {
+                    eprintln!("Failed to allocate colour -- #{:08X}.", argb);
+                    return false;
+                }
+                v.insert(tmp.assume_init());
+            }
+        }
+        true
+    }
+
+    /// Free the literal colours that none of the inputs use any more, so inputs that change
+    /// colour on every update don't keep allocating new ones.
+    ///
+    /// # Arguments
+    /// * xft:    -> Reference to the Xft library for XftColorFree.
+    /// * dpy:    -> Pointer to the Display that the bar is using.
+    /// * visual: -> Pointer to the Visual for the active Display.
+    /// * cmap:   -> Pointer to the Colormap for the active Display.
+    /// * inputs: -> every input that is on the bar now.
+    pub fn prune<'a>(
+        &mut self,
+        xft: &xft::Xft,
+        dpy: *mut xlib::Display,
+        visual: *mut xlib::Visual,
+        cmap: xlib::Colormap,
+        inputs: impl Iterator<Item = &'a Input>,
+    ) {
+        let used: HashSet<u32> = inputs
+            .flat_map(|input| input.markup.runs.iter())
+            .flat_map(|run| {
+                let style = run.style;
+                [
+                    Some(style.colour),
+                    style.background,
+                    style.underline,
+                    style.overline,
+                ]
+            })
+            .filter_map(|colour| match colour {
+                Some(Colour::Literal(argb)) => Some(argb),
+                _ => None,
+            })
+            .collect();
+        self.literal.retain(|argb, col| {
+            if !used.contains(argb) {
+                unsafe { (xft.XftColorFree)(dpy, visual, cmap, col) };
+            }
+            used.contains(argb)
+        });
+    }
+
+    /// How many colours of each type are available, for checking indexes while parsing.
+    ///
+    /// # Arguments
+    /// * fonts: -> number of fonts the bar has open.
+    pub fn limits(&self, fonts: usize) -> Limits {
+        Limits {
+            fonts,
+            font_colours: self.font.len(),
+            background_colours: self.background.len(),
+            underline_colours: self.underline.len(),
+            overline_colours: self.overline.len(),
+        }
+    }
+
+    /// Helpers to find the actual XftColor to draw with for each type of colour.
+    fn font_colour(&self, colour: Colour) -> &xft::XftColor {
+        match colour {
+            Colour::Index(i) => &self.font[i],
+            Colour::Literal(argb) => &self.literal[&argb],
+        }
+    }
+
+    fn background_colour(&self, colour: Colour) -> &xft::XftColor {
+        match colour {
+            Colour::Index(i) => &self.background[i],
+            Colour::Literal(argb) => &self.literal[&argb],
+        }
+    }
+
+    fn underline_colour(&self, colour: Colour) -> &xft::XftColor {
+        match colour {
+            Colour::Index(i) => &self.underline[i],
+            Colour::Literal(argb) => &self.literal[&argb],
+        }
+    }
+
+    fn overline_colour(&self, colour: Colour) -> &xft::XftColor {
+        match colour {
+            Colour::Index(i) => &self.overline[i],
+            Colour::Literal(argb) => &self.literal[&argb],
         }
     }
 
@@ -114,323 +259,207 @@ impl ColourPalette {
         self.background
             .drain(..)
             .for_each(|mut col| (xft.XftColorFree)(dpy, visual, cmap, &mut col));
-        // Next the underline highlight colours.
+        // Next the underline and overline highlight colours.
         self.underline
             .drain(..)
             .for_each(|mut col| (xft.XftColorFree)(dpy, visual, cmap, &mut col));
-        // Finally we free our font colours.
+        self.overline
+            .drain(..)
+            .for_each(|mut col| (xft.XftColorFree)(dpy, visual, cmap, &mut col));
+        // Then we free our font colours.
         self.font
             .drain(..)
             .for_each(|mut col| (xft.XftColorFree)(dpy, visual, cmap, &mut col));
+        // Finally any literal colours that were cached.
+        self.literal
+            .drain()
+            .for_each(|(_, mut col)| (xft.XftColorFree)(dpy, visual, cmap, &mut col));
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-/// Private struct to use instead of a tuple of usize vals. Just helps to make things more explicit
-/// during parsing of inputs. Used for font colours and faces, as well as background and underline
-/// highlights.
-struct DisplayTemp {
-    /// Index into the vector of available fonts or colours.
-    idx: usize,
-    /// Start character within the parsed input string.
-    start: usize,
-    /// End character within the parsed input string.
-    end: usize,
+/// Font metrics for the layout step, backed by the Xft fonts the bar has open.
+struct XftMetrics<'a> {
+    xft: &'a xft::Xft,
+    dpy: *mut xlib::Display,
+    fonts: &'a [*mut xft::XftFont],
+    /// Lookup table for the default font of each char, shared between all the inputs.
+    font_map: &'a mut HashMap<char, usize>,
+    /// Lookup table for the advance of each char in each font, so we only ask the X server once.
+    glyph_widths: &'a mut HashMap<(usize, char), u32>,
 }
 
-impl DisplayTemp {
-    /// Some pathological need I have for constructors is being filled here I guess.
-    ///
-    /// # Arguments
-    /// * idx:   -> see struct def.
-    /// * start: -> see struct def.
-    /// * end:   -> see struct def.
-    ///
-    /// # Output
-    /// DisplayTemp that has the values provided in the arguements.
-    fn from(idx: usize, start: usize, end: usize) -> DisplayTemp {
-        DisplayTemp { idx, start, end }
+impl FontMetrics for XftMetrics<'_> {
+    fn default_font(&mut self, ch: char) -> usize {
+        match self.font_map.entry(ch) {
+            Entry::Occupied(o) => *o.get(),
+            Entry::Vacant(v) => {
+                *v.insert(unsafe { default_font_idx(self.xft, self.dpy, self.fonts, ch) })
+            }
+        }
     }
 
-    /// The parsing function when reading in font faces doesn't check for the default font that
-    /// each char belongs to. So this function sorts through and checks for only the sections that
-    /// were not previously explicitly set.
-    ///
-    /// # Arguments
-    /// def: -> reference to a HashMap that is acting like a lookup table for the default face
-    /// index for each char.
-    /// expl: -> reference to the explicitly set font face values from the parse. With usize::MAX
-    /// set for the sections to be filled in.
-    /// text: -> the actual printed text for the Input to be made.
-    ///
-    /// # Output
-    /// A vec of DisplayTemp representing the font_faces for the Input.
-    fn default_font_faces(
-        def: &HashMap<char, usize>,
-        expl: &[DisplayTemp],
-        text: &str,
-    ) -> Result<Vec<DisplayTemp>> {
-        expl.iter()
-            .try_fold(Vec::new(), |mut acc, dt| -> Result<Vec<DisplayTemp>> {
-                // usize::MAX is our key value to mean use default here.
-                if dt.idx == usize::MAX {
-                    // Get the parts of the string within the DisplayTemp start and end.
-                    let chunk: String = text.chars().take(dt.end).skip(dt.start).collect();
-
-                    // Temp value to use while counting.
-                    let mut tmp = DisplayTemp::from(0, dt.start, dt.start);
-
-                    // Generating the Vec of DisplayTemp values by folding over the chars.
-                    let mut res = chunk.chars().fold(Vec::new(), |mut ac, ch| {
-                        // Get a copy of the default index for the char
-                        let ch_idx = *def.get(&ch).expect("cant fail.");
-
-                        // If the default index is different from the tmp val index then we push our
-                        // tmp and start the new count.
-                        if ch_idx != tmp.idx {
-                            // We only do the push if we actually counted something. Otherwise just
-                            // start over by changing the font index.
-                            if tmp.start != tmp.end {
-                                ac.push(tmp);
-                                tmp.start = tmp.end;
-                                tmp.end = tmp.start + 1;
-                            }
-                            tmp.idx = ch_idx;
-                        } else {
-                            // If the default is the same as the idx for our tmp val then we just increment
-                            // the end value.
-                            tmp.end += 1;
-                        }
-
-                        // Gotta return something every loop.
-                        ac
-                    });
-
-                    // Once the loop is done we push the tmp value on the end if it counted and
-                    // characters.
-                    if tmp.start != tmp.end {
-                        res.push(tmp);
-                    }
-
-                    // We use append here because it is possible to have generated multiple
-                    // DisplayTemps when using the default indexes.
-                    acc.append(&mut res);
-                } else {
-                    // If we are not using the defaults then we just push the value.
-                    acc.push(*dt);
-                }
-
-                // Once we pushed the old DisplayTemp or generated default ones we return our acc Vec.
-                Ok(acc)
+    fn text_width(&mut self, font: usize, text: &str) -> u32 {
+        let (xft, dpy, xft_font) = (self.xft, self.dpy, self.fonts[font]);
+        text.chars()
+            .map(|ch| {
+                *self
+                    .glyph_widths
+                    .entry((font, ch))
+                    .or_insert_with(|| unsafe { char_pixel_advance(xft, dpy, xft_font, ch) })
             })
+            .sum()
     }
 }
 
-#[derive(Debug)]
-/// Private struct to contain display info for the underline and background highlight objects.
-/// No reason to have different structs as they would just end up repeating code.
-struct RectDisplayInfo {
-    /// Index into the ColourPalette.{background or underline} vectors of colours for this section.
-    idx: usize,
-    /// Pixel x-value to start.
-    start: usize,
-    /// Pixel x-value to end.
-    end: usize,
-}
+/// Pixels left between the end of scrolling text and its start coming around again.
+const MARQUEE_GAP: u32 = 32;
 
-impl RectDisplayInfo {
-    /// Function to generate a list of background or underline highlight instructions from the text
-    /// and the text_display instructions.
-    ///
-    /// # Arguments
-    /// * xft: -> Reference to the xft library for the string_pixel_width function.
-    /// * dpy: -> Pointer to the xlib Display for the string_pixel_width function.
-    /// * fonts: -> List of fonts available to use, for the string_pixel_width function.
-    /// * rect_display_types: -> List of DisplayTemps for the backgrounds/highlights we are
-    /// converting.
-    /// * text_display: -> List of reference FontDisplayInfo objects used to get the string chunks.
-    /// * text: -> Actual chars that will be displayed.
-    ///
-    /// # Output
-    /// Returns the converted list of RectDisplayInfo objects for the highlights or backgrounds.
-    unsafe fn gen_list(
-        xft: &xft::Xft,
-        dpy: *mut xlib::Display,
-        fonts: &[*mut xft::XftFont],
-        rect_display_types: &[DisplayTemp],
-        text_display: &[FontDisplayInfo],
-        text: &str,
-    ) -> Vec<RectDisplayInfo> {
-        // Only need one loop, going over the chars and converting from char indexes to pixel vals.
-        rect_display_types
-            .iter()
-            .fold(Vec::new(), |mut acc, rect_oj| {
-                // Generate the start pixel val.
-                let mut start = 0;
-                for font_display in text_display.iter() {
-                    if font_display.start == rect_oj.start {
-                        // If the starts are equal we are done.
-                        break;
-                    } else if font_display.end > rect_oj.start {
-                        // If the end is larger than our start we can get the width up to our start
-                        // and we are done.
-                        let chunk: String = text
-                            .chars()
-                            .skip(font_display.start)
-                            .take(rect_oj.start - font_display.start)
-                            .collect();
-                        start += string_pixel_width(xft, dpy, fonts[font_display.face_idx], &chunk);
-                        break;
-                    } else {
-                        // Other wise just add in the pixel withd of the whole font display.
-                        let chunk: String = text
-                            .chars()
-                            .take(font_display.end)
-                            .skip(font_display.start)
-                            .collect();
-                        start += string_pixel_width(xft, dpy, fonts[font_display.face_idx], &chunk);
-                    }
-                }
-                // Generate the end pixel val.
-                let mut end = 0;
-                for font_display in text_display.iter() {
-                    if font_display.start == rect_oj.end {
-                        // If the start matches our end we grab one char width and we are done.
-                        let chunk: String = text.chars().skip(font_display.start).take(1).collect();
-                        end += string_pixel_width(xft, dpy, fonts[font_display.face_idx], &chunk);
-                        break;
-                    } else if font_display.end <= rect_oj.end {
-                        // If the ends match or the objects is less than ours we get the width of
-                        // the whole font object in pixels.
-                        let chunk: String = text
-                            .chars()
-                            .take(font_display.end)
-                            .skip(font_display.start)
-                            .collect();
-                        end += string_pixel_width(xft, dpy, fonts[font_display.face_idx], &chunk);
-                    } else {
-                        // Otherwise we just get the width up to the end value our our object and
-                        // convert it to pixels and finish up.
-                        let chunk: String = text
-                            .chars()
-                            .skip(font_display.start)
-                            .take(rect_oj.end - font_display.start)
-                            .collect();
-                        end += string_pixel_width(xft, dpy, fonts[font_display.face_idx], &chunk);
-                        break;
-                    }
-                }
-                // Once we have our start and end vals moved to pixels we can make the
-                // RectDisplayInfo object with the idx from the current ref val.
-                acc.push(RectDisplayInfo {
-                    idx: rect_oj.idx,
-                    start: start as usize,
-                    end: end as usize,
-                });
-                acc
-            })
-    }
+/// Only allow drawing inside of the rectangle given, until the clip is reset.
+unsafe fn set_clip(
+    xft: &xft::Xft,
+    draw: *mut xft::XftDraw,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+) {
+    let rect = xlib::XRectangle {
+        x: 0,
+        y: 0,
+        width: width as u16,
+        height: height as u16,
+    };
+    (xft.XftDrawSetClipRectangles)(draw, x, y, &rect, 1);
 }
 
-#[derive(Debug)]
-/// Private struct used to hold the data for drawing the text of a Input to the display.
-struct FontDisplayInfo {
-    /// Index in the faces field of a Bar struct to be used.
-    face_idx: usize,
-    /// Index in the palette.font field of a Bar struct to be used.
-    col_idx: usize,
-    /// First char to include in formatting.
-    start: usize,
-    /// Include upto but not this value.
-    end: usize,
+/// Draw a layout, starting with the background rectangles and then finishing with the text.
+///
+/// # Arguments
+/// * layout:  -> positions of everything to draw, relative to start_x.
+/// * The rest are the same as Input::draw.
+#[allow(clippy::too_many_arguments)]
+unsafe fn draw_layout(
+    xft: &xft::Xft,
+    draw: *mut xft::XftDraw,
+    colours: &ColourPalette,
+    fonts: &[*mut xft::XftFont],
+    layout: &Layout,
+    start_x: i32,
+    font_y: i32,
+    height: u32,
+    hlt_hgt: u32,
+    ovl_hgt: u32,
+) {
+    // Displaying the backgrounds first.
+    layout.backgrounds.iter().for_each(|b| {
+        (xft.XftDrawRect)(
+            draw,
+            colours.background_colour(b.colour),
+            start_x + b.start as i32,
+            0,
+            b.end - b.start,
+            height,
+        );
+    });
+
+    // Display the highlights next.
+    layout.underlines.iter().for_each(|h| {
+        (xft.XftDrawRect)(
+            draw,
+            colours.underline_colour(h.colour),
+            start_x + h.start as i32,
+            (height - hlt_hgt) as i32,
+            h.end - h.start,
+            hlt_hgt,
+        );
+    });
+
+    // Overlines sit at the very top of the bar.
+    layout.overlines.iter().for_each(|o| {
+        (xft.XftDrawRect)(
+            draw,
+            colours.overline_colour(o.colour),
+            start_x + o.start as i32,
+            0,
+            o.end - o.start,
+            ovl_hgt,
+        );
+    });
+
+    // Do the font bits last.
+    layout.chunks.iter().for_each(|c| {
+        (xft.XftDrawStringUtf8)(
+            draw,
+            colours.font_colour(c.colour),
+            fonts[c.font],
+            start_x + c.x as i32,
+            font_y,
+            c.text.as_ptr(),
+            c.text.len() as i32,
+        );
+    });
 }
 
-impl FontDisplayInfo {
-    /// General helper function to transition from our basic tuples into info we can use for
-    /// font display.
-    ///
-    /// # Arguments
-    /// * col_objs:  -> The instructions for the different font colors to use on different sections
-    /// of the text.
-    /// * face_objs: -> The instructions for different font faces to use on different sections of
-    /// the text.
-    ///
-    /// # Output
-    /// List of instructions for both font colour and font face to use. Seperated into the minimum
-    /// different sets of instructions to use.
-    fn generate_list(col_objs: &[DisplayTemp], face_objs: &[DisplayTemp]) -> Vec<FontDisplayInfo> {
-        let mut strt_idx = 0;
-        // Loop through the color objects and for each one generate the mixed FontDisplayInfo
-        // object until the start of the face object is larger than the start of the colour object.
-        col_objs.iter().fold(Vec::new(), |mut acc, cl_oj| {
-            // Temp var is a vector because sometimes we create multiple objects.
-            let mut tmp: Vec<FontDisplayInfo> = Vec::new();
-            // We skip until the end of the previous iteration to try and save compute.
-            for (i, fc_oj) in face_objs.iter().enumerate().skip(strt_idx) {
-                let start = if fc_oj.start <= cl_oj.start {
-                    cl_oj.start
-                } else {
-                    fc_oj.start
-                };
-                // The end is either the end of the face val if we need more loops or the end of
-                // the colour val if we are gonna be done.
-                let end = if fc_oj.end >= cl_oj.end {
-                    cl_oj.end
-                } else {
-                    fc_oj.end
-                };
-                // If start and end are the same then there is no point in pushing.
-                if start != end {
-                    tmp.push(FontDisplayInfo {
-                        face_idx: fc_oj.idx,
-                        col_idx: cl_oj.idx,
-                        start,
-                        end,
-                    });
-                }
-                // If we are at the end of the color object then we can break the face object loop
-                // and set the start val for next iteration.
-                if fc_oj.end >= cl_oj.end {
-                    strt_idx = i;
-                    break;
-                }
-            }
-            // Appending is just push for another vec of similar objects.
-            acc.append(&mut tmp);
-            acc
-        })
+/// Draw the text of a marquee clipped to its window. Text that scrolls wraps around, with a gap
+/// so the end and the start aren't run together.
+///
+/// # Arguments
+/// * m:      -> the marquee to draw.
+/// * window: -> visible part of the marquee window, relative to start_x.
+/// * scroll: -> pixels the marquee has scrolled by.
+/// * The rest are the same as Input::draw.
+#[allow(clippy::too_many_arguments)]
+unsafe fn draw_marquee(
+    xft: &xft::Xft,
+    draw: *mut xft::XftDraw,
+    colours: &ColourPalette,
+    fonts: &[*mut xft::XftFont],
+    m: &MarqueeArea,
+    (x, width): (u32, u32),
+    start_x: i32,
+    font_y: i32,
+    height: u32,
+    hlt_hgt: u32,
+    ovl_hgt: u32,
+    scroll: u32,
+) {
+    if width == 0 {
+        return;
     }
+    set_clip(xft, draw, start_x + x as i32, 0, width, height);
+    let (offset, copies) = if m.scrolls() {
+        (scroll % (m.content.width + MARQUEE_GAP), 2)
+    } else {
+        (0, 1)
+    };
+    for copy in 0..copies {
+        let content_x =
+            start_x + (m.x + copy * (m.content.width + MARQUEE_GAP)) as i32 - offset as i32;
+        draw_layout(
+            xft, draw, colours, fonts, &m.content, content_x, font_y, height, hlt_hgt, ovl_hgt,
+        );
+    }
+    (xft.XftDrawSetClip)(draw, ptr::null_mut());
 }
 
-/// Small private enum to help when parsing the inital input.
-enum IndexType {
-    BackgroundColour,
-    HighlightColour,
-    FontColour,
-    FontFace,
-}
-
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 /// Main struct to hold display info for text on the bar.
-/// Has references needed to display the text, backgrounds, and underlines.
+/// Has references needed to display the text, backgrounds, underlines, and overlines.
 pub struct Input {
-    // The actual text to be drawn.
-    text: String,
-    // Reference for which chars to display in which font or colour.
-    text_display: Vec<FontDisplayInfo>,
-    // Reference for background highlights to draw with pixel val start and ends.
-    backgrounds: Vec<RectDisplayInfo>,
-    // Reference for underline highlights to draw with pixel val start and ends.
-    underlines: Vec<RectDisplayInfo>,
+    // The parsed input, kept so it can be cut down again when the room it has changes.
+    markup: Markup,
+    // Positions of the text, highlights, and clickable areas.
+    layout: Layout,
+    // Layout cut short with an ellipsis, drawn instead when there isn't room for the full one.
+    cut: Option<Layout>,
+    // Pixels past this are cut off when drawing.
+    clip: Option<u32>,
 }
 
 impl Input {
     /// Helper to clear
     pub fn clear(&mut self) {
-        self.text.clear();
-        self.text_display.clear();
-        self.backgrounds.clear();
-        self.underlines.clear();
+        *self = Input::empty();
     }
     /// Small helper function to generate an emply Input.
     ///
@@ -438,19 +467,33 @@ impl Input {
     /// Empty Input object to use as placeholder.
     pub fn empty() -> Input {
         Input {
-            text: String::new(),
-            text_display: Vec::new(),
-            backgrounds: Vec::new(),
-            underlines: Vec::new(),
+            markup: Markup::default(),
+            layout: Layout::default(),
+            cut: None,
+            clip: None,
         }
     }
 
+    /// The parsed input, to load again after the fonts or colours change.
+    pub fn markup(&self) -> &Markup {
+        &self.markup
+    }
+
+    /// All of the text without any formatting.
+    pub fn text(&self) -> String {
+        self.markup.text()
+    }
+
+    /// The layout that actually gets drawn.
+    fn shown(&self) -> &Layout {
+        self.cut.as_ref().unwrap_or(&self.layout)
+    }
+
     /// Draw the valid string onto the XftDraw object using the different struct fields. Starting
     /// with the background rectangles and then finishing with the text.
     ///
     /// # Arguments
     /// * xft:     -> Reference to the xft library and it's functions.
-    /// * dpy:     -> Pointer to the XDisplay we are drawing to.
     /// * draw:    -> Pointer to the XftDraw object we are drawing to.
     /// * colours: -> Reference to the ColourPalette object holding the colours available.
     /// * fonts:   -> Reference to the list of fonts available.
@@ -458,12 +501,13 @@ impl Input {
     /// * font_y:  -> Y-value to draw the text at.
     /// * height:  -> Height of the bar.
     /// * hlt_hgt: -> Height of the underline highlights.
+    /// * ovl_hgt: -> Height of the overline highlights.
+    /// * scroll:  -> Pixels the marquees have scrolled by.
     ///
     #[allow(clippy::too_many_arguments)]
     pub unsafe fn draw(
         &self,
         xft: &xft::Xft,
-        dpy: *mut xlib::Display,
         draw: *mut xft::XftDraw,
         colours: &ColourPalette,
         fonts: &[*mut xft::XftFont],
@@ -471,305 +515,247 @@ impl Input {
         font_y: i32,
         height: u32,
         hlt_hgt: u32,
+        ovl_hgt: u32,
+        scroll: u32,
     ) {
-        // Displaying the backgrounds first.
-        self.backgrounds.iter().for_each(|b| {
-            (xft.XftDrawRect)(
-                draw,
-                &colours.background[b.idx],
-                start_x + b.start as i32,
-                0,
-                (b.end - b.start) as u32,
-                height,
-            );
-        });
-
-        // Display the highlights next.
-        self.underlines.iter().for_each(|h| {
-            (xft.XftDrawRect)(
-                draw,
-                &colours.underline[h.idx],
-                start_x + h.start as i32,
-                (height - hlt_hgt) as i32,
-                (h.end - h.start) as u32,
-                hlt_hgt,
+        if let Some(width) = self.clip {
+            set_clip(xft, draw, start_x, 0, width, height);
+        }
+        draw_layout(
+            xft,
+            draw,
+            colours,
+            fonts,
+            self.shown(),
+            start_x,
+            font_y,
+            height,
+            hlt_hgt,
+            ovl_hgt,
+        );
+        for (m, window) in self.shown().marquees.iter().zip(self.windows()) {
+            draw_marquee(
+                xft, draw, colours, fonts, m, window, start_x, font_y, height, hlt_hgt, ovl_hgt,
+                scroll,
             );
-        });
+        }
 
-        // Do the font bits last.
-        self.text_display.iter().fold(0, |acc, td| {
-            let chunk: String = self.text.chars().take(td.end).skip(td.start).collect();
-            (xft.XftDrawStringUtf8)(
-                draw,
-                &colours.font[td.col_idx],
-                fonts[td.face_idx],
-                start_x + acc,
-                font_y,
-                chunk.as_bytes().as_ptr() as *const u8,
-                chunk.as_bytes().len() as i32,
-            );
-            acc + string_pixel_width(xft, dpy, fonts[td.face_idx], &chunk) as i32
-        });
+        // Put the clip back so the next Input can draw anywhere.
+        if self.clip.is_some() {
+            (xft.XftDrawSetClip)(draw, ptr::null_mut());
+        }
     }
 
-    /// Small helper function to get the pixel length of a Input object.
+    /// Draw only the marquees whose text scrolls. Their windows need to be cleared first when
+    /// redrawing for a new scroll position, the highlights of the blocks around them are drawn
+    /// again here so they look the same as a full redraw.
     ///
     /// # Arguments
-    /// * xft:   -> reference to the link to the Xft library.
-    /// * dpy:   -> pointer to the XDisplay object we are displaying to.
-    /// * fonts: -> list of pointers to our XftFont objects available to use.
-    ///
-    /// # Output
-    /// c_uint representing the pixel length of the self Input.
-    pub unsafe fn len(
+    /// * Same as draw.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn draw_marquees(
         &self,
         xft: &xft::Xft,
-        dpy: *mut xlib::Display,
+        draw: *mut xft::XftDraw,
+        colours: &ColourPalette,
         fonts: &[*mut xft::XftFont],
-    ) -> u32 {
-        self.text_display.iter().fold(0, |acc, fd| {
-            let chunk: String = self.text.chars().take(fd.end).skip(fd.start).collect();
-            acc + string_pixel_width(xft, dpy, fonts[fd.face_idx], &chunk)
+        start_x: i32,
+        font_y: i32,
+        height: u32,
+        hlt_hgt: u32,
+        ovl_hgt: u32,
+        scroll: u32,
+    ) {
+        for (m, window) in self.shown().marquees.iter().zip(self.windows()) {
+            if m.scrolls() && window.1 > 0 {
+                set_clip(xft, draw, start_x + window.0 as i32, 0, window.1, height);
+                draw_layout(
+                    xft,
+                    draw,
+                    colours,
+                    fonts,
+                    self.shown(),
+                    start_x,
+                    font_y,
+                    height,
+                    hlt_hgt,
+                    ovl_hgt,
+                );
+                draw_marquee(
+                    xft, draw, colours, fonts, m, window, start_x, font_y, height, hlt_hgt,
+                    ovl_hgt, scroll,
+                );
+            }
+        }
+    }
+
+    /// Visible part of each marquee window, as the x-value relative to the Input and the width.
+    fn windows(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let limit = self.clip.unwrap_or(u32::MAX);
+        self.shown().marquees.iter().map(move |m| {
+            let end = (m.x + m.width).min(limit);
+            (m.x, end.saturating_sub(m.x))
         })
     }
 
-    /// Function to parse a string and develop a Input.
-    /// Tries to do most of it's work in one loop over the input.
+    /// Windows of the marquees whose text scrolls, these are the only parts that need to be
+    /// redrawn as time goes on.
+    ///
+    /// # Output
+    /// X-value relative to the Input and width of each window.
+    pub fn scrolling(&self) -> Vec<(u32, u32)> {
+        self.shown()
+            .marquees
+            .iter()
+            .zip(self.windows())
+            .filter(|(m, (_, width))| m.scrolls() && *width > 0)
+            .map(|(_, window)| window)
+            .collect()
+    }
+
+    /// Small helper function to get the pixel length of a Input object, before it is cut down
+    /// to fit.
+    ///
+    /// # Output
+    /// c_uint representing the pixel length of the self Input.
+    pub fn len(&self) -> u32 {
+        self.layout.width
+    }
+
+    /// Find the innermost clickable area under a click.
     ///
     /// # Arguments
-    /// * xft:     -> reference to the link to the Xft library.
-    /// * dpy:     -> pointer to the XDisplay object we are displaying to.
-    /// * fonts:   -> list of pointers to our XftFont objects available to use.
-    /// * colours: -> reference to the ColourPalette available to use.
-    /// * input:   -> the string we are reading from to develop a Input.
+    /// * x:      -> Pixel x-value of the click, relative to where the Input was drawn.
+    /// * button: -> Mouse button that was pressed.
+    /// * scroll: -> Pixels the marquees have scrolled by.
     ///
     /// # Output
-    /// Input made based on the input String object.
-    pub fn parse_string(
+    /// The area, with its x-values relative to where the Input was drawn or to the start of the
+    /// marquee text it is in.
+    pub fn area_at(&self, x: i32, button: u32, scroll: u32) -> Option<&ActionArea> {
+        if x < 0 || self.clip.is_some_and(|w| x as u32 >= w) {
+            return None;
+        }
+        let x = x as u32;
+        // Actions are stored in the order they are closed, so the first match is the innermost.
+        fn find(actions: &[ActionArea], x: u32, button: u32) -> Option<&ActionArea> {
+            actions
+                .iter()
+                .find(|a| a.button.is_none_or(|b| b == button) && a.start <= x && x < a.end)
+        }
+        // Clicks in a marquee go to the text scrolled under them first.
+        let layout = self.shown();
+        layout
+            .marquees
+            .iter()
+            .find(|m| m.x <= x && x < m.x + m.width)
+            .and_then(|m| {
+                let mut x = x - m.x;
+                if m.scrolls() {
+                    x = (x + scroll) % (m.content.width + MARQUEE_GAP);
+                }
+                find(&m.content.actions, x, button)
+            })
+            .or_else(|| find(&layout.actions, x, button))
+    }
+
+    /// Function to take parsed markup and develop a Input.
+    /// Any literal colours are allocated first, then the layout is done with the Xft fonts.
+    ///
+    /// # Arguments
+    /// * xft:          -> reference to the link to the Xft library.
+    /// * dpy:          -> pointer to the XDisplay object we are displaying to.
+    /// * visual:       -> pointer to the Visual used to allocate literal colours.
+    /// * cmap:         -> Colormap used to allocate literal colours.
+    /// * fonts:        -> list of pointers to our XftFont objects available to use.
+    /// * font_map:     -> lookup table of the default font for each char.
+    /// * glyph_widths: -> lookup table of the advance for each char in each font.
+    /// * colours:      -> reference to the ColourPalette available to use, literal colours are
+    ///   added to its cache as we find them.
+    /// * markup:       -> the parsed input we are developing a Input from.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load(
         &mut self,
         xft: &xft::Xft,
         dpy: *mut xlib::Display,
+        visual: *mut xlib::Visual,
+        cmap: xlib::Colormap,
         fonts: &[*mut xft::XftFont],
-        def_font_map: &mut HashMap<char, usize>,
-        colours: &ColourPalette,
-        input: &str,
-    ) -> Result<()> {
-        // clear self to start.
-        self.clear();
-
-        // Loop vars.
-        let mut in_format_block = false;
-        let mut next_is_index = false;
-        let mut closing_block = false;
-        let mut index_type = IndexType::FontColour;
-
-        // Result vars.
-        let mut text = String::new();
-        let mut background_vec: Vec<DisplayTemp> = Vec::new();
-        let mut underline_vec: Vec<DisplayTemp> = Vec::new();
-        let mut font_colour_vec: Vec<DisplayTemp> = Vec::new();
-        let mut font_face_vec: Vec<DisplayTemp> = Vec::new();
-
-        // Temp vars.
-        let mut count: usize = 0;
-        let mut bckgrnd_tmp: DisplayTemp = DisplayTemp::from(usize::MAX, 0, 0);
-        let mut underln_tmp: DisplayTemp = DisplayTemp::from(usize::MAX, 0, 0);
-        let mut fcol_tmp: DisplayTemp = DisplayTemp::from(0, 0, 0);
-        let mut fface_tmp: DisplayTemp = DisplayTemp::from(usize::MAX, 0, 0);
-
-        // Big ass loop to proces the input.
-        for ch in input.chars() {
-            if in_format_block {
-                if closing_block {
-                    match ch {
-                        // B is the marker for the background highlight.
-                        'B' => {
-                            bckgrnd_tmp.end = count;
-                            background_vec.push(bckgrnd_tmp);
-                            bckgrnd_tmp = DisplayTemp::from(usize::MAX, count, 0);
-                        }
-                        // H is the marker for the underline highlight.
-                        'H' => {
-                            underln_tmp.end = count;
-                            underline_vec.push(underln_tmp);
-                            underln_tmp = DisplayTemp::from(usize::MAX, count, 0);
-                        }
-                        // F is the marker for the font colour.
-                        'F' => {
-                            fcol_tmp.end = count;
-                            font_colour_vec.push(fcol_tmp);
-                            fcol_tmp = DisplayTemp::from(0, count, 0);
-                        }
-                        // F is the marker for the font face.
-                        'f' => {
-                            fface_tmp.end = count;
-                            font_face_vec.push(fface_tmp);
-                            fface_tmp = DisplayTemp::from(usize::MAX, count, 0);
-                        }
-                        // End the block if we hit a close bracket.
-                        '}' => {
-                            in_format_block = false;
-                            closing_block = false;
-                        }
-                        _ => (),
-                    }
-                // When we hit any of the markers the next char will be an index val so we
-                // start to process it.
-                } else if next_is_index {
-                    // Converting to a base 10 digit creates a nice limit of 10 fonts,
-                    // font-colours, background-colours, and highlight-colours.
-                    if let Some(d) = ch.to_digit(10) {
-                        // All four index types are basically the same, check to make sure the
-                        // index is valid & if it is we push our current count onto the vec and
-                        // start a new tmp count.
-                        match index_type {
-                            IndexType::BackgroundColour => {
-                                if d > (colours.background.len() - 1) as u32 {
-                                    eprintln!("Invalid background colour index -- TOO LARGE.");
-                                } else {
-                                    bckgrnd_tmp.end = count;
-                                    background_vec.push(bckgrnd_tmp);
-                                    bckgrnd_tmp = DisplayTemp::from(d as usize, count, 0);
-                                }
-                            }
-                            IndexType::HighlightColour => {
-                                if d > (colours.underline.len() - 1) as u32 {
-                                    eprintln!("Invalid underline colour index -- TOO LARGE.");
-                                } else {
-                                    underln_tmp.end = count;
-                                    underline_vec.push(underln_tmp);
-                                    underln_tmp = DisplayTemp::from(d as usize, count, 0);
-                                }
-                            }
-                            IndexType::FontColour => {
-                                if d > (colours.font.len() - 1) as u32 {
-                                    eprintln!("Invalid font colour index -- TOO LARGE.");
-                                } else {
-                                    fcol_tmp.end = count;
-                                    font_colour_vec.push(fcol_tmp);
-                                    fcol_tmp = DisplayTemp::from(d as usize, count, 0);
-                                }
-                            }
-                            IndexType::FontFace => {
-                                if d > (fonts.len() - 1) as u32 {
-                                    eprintln!("Invalid font face index -- TOO LARGE.");
-                                } else {
-                                    fface_tmp.end = count;
-                                    font_face_vec.push(fface_tmp);
-                                    fface_tmp = DisplayTemp::from(d as usize, count, 0);
-                                }
-                            }
-                        }
-                        next_is_index = false;
-                    }
-                } else {
-                    // If we are in a format block and have no other info we sort through and
-                    // determine if we are in a closing or opening block. And what kind of format
-                    // specifically.
-                    match ch {
-                        '/' => closing_block = true,
-                        'B' => {
-                            next_is_index = true;
-                            index_type = IndexType::BackgroundColour;
-                        }
-                        'H' => {
-                            next_is_index = true;
-                            index_type = IndexType::HighlightColour;
-                        }
-                        'F' => {
-                            next_is_index = true;
-                            index_type = IndexType::FontColour;
-                        }
-                        'f' => {
-                            next_is_index = true;
-                            index_type = IndexType::FontFace;
-                        }
-                        '}' => in_format_block = false,
-                        _ => (),
-                    }
-                }
-            } else {
-                // If we are not in a format block either it's a valid char or the beginning of a
-                // new format block. We also take the chance to get the default valid font for the
-                // char.
-                match ch {
-                    '{' => in_format_block = true,
-                    _ => {
-                        count += 1;
-                        text.push(ch);
-                        if let Entry::Vacant(v) = def_font_map.entry(ch) {
-                            v.insert(unsafe { default_font_idx(xft, dpy, fonts, ch) });
-                        }
-                    }
-                }
+        font_map: &mut HashMap<char, usize>,
+        glyph_widths: &mut HashMap<(usize, char), u32>,
+        colours: &mut ColourPalette,
+        mut markup: Markup,
+    ) {
+        // Literal colours need to be allocated before we can draw with them. If that fails we
+        // fall back on the defaults for that type of colour.
+        for run in markup.runs.iter_mut() {
+            let style = &mut run.style;
+            if !colours.ensure(xft, dpy, visual, cmap, style.colour) {
+                style.colour = Colour::Index(0);
             }
+            style.background = style
+                .background
+                .filter(|&c| colours.ensure(xft, dpy, visual, cmap, c));
+            style.underline = style
+                .underline
+                .filter(|&c| colours.ensure(xft, dpy, visual, cmap, c));
+            style.overline = style
+                .overline
+                .filter(|&c| colours.ensure(xft, dpy, visual, cmap, c));
         }
 
-        // Set the end of the tmp var to the end count to finish off the tmp vars.
-        bckgrnd_tmp.end = count;
-        underln_tmp.end = count;
-        fcol_tmp.end = count;
-        fface_tmp.end = count;
+        let mut metrics = XftMetrics {
+            xft,
+            dpy,
+            fonts,
+            font_map,
+            glyph_widths,
+        };
+        self.layout = layout::layout(&markup, &mut metrics);
+        self.markup = markup;
+        self.cut = None;
+        self.clip = None;
+    }
 
-        // Push the last val onto all of our count vecs.
-        if bckgrnd_tmp.end != bckgrnd_tmp.start {
-            background_vec.push(bckgrnd_tmp);
-        }
-        if underln_tmp.end != underln_tmp.start {
-            underline_vec.push(underln_tmp);
-        }
-        if fcol_tmp.end != fcol_tmp.start {
-            font_colour_vec.push(fcol_tmp);
+    /// Cut the Input down to fit in the room it has on the bar. Giving it enough room for the full
+    /// layout puts it back the way it was.
+    ///
+    /// # Arguments
+    /// * xft:          -> reference to the link to the Xft library.
+    /// * dpy:          -> pointer to the XDisplay object we are displaying to.
+    /// * fonts:        -> list of pointers to our XftFont objects available to use.
+    /// * font_map:     -> lookup table of the default font for each char.
+    /// * glyph_widths: -> lookup table of the advance for each char in each font.
+    /// * width:        -> pixels the Input can take up.
+    /// * overflow:     -> whether to end with an ellipsis or just clip the text.
+    #[allow(clippy::too_many_arguments)]
+    pub fn fit(
+        &mut self,
+        xft: &xft::Xft,
+        dpy: *mut xlib::Display,
+        fonts: &[*mut xft::XftFont],
+        font_map: &mut HashMap<char, usize>,
+        glyph_widths: &mut HashMap<(usize, char), u32>,
+        width: u32,
+        overflow: Overflow,
+    ) {
+        self.cut = None;
+        self.clip = None;
+        if width >= self.layout.width {
+            return;
         }
-        if fface_tmp.end != fface_tmp.start {
-            font_face_vec.push(fface_tmp);
+        match overflow {
+            Overflow::Ellipsis => {
+                let mut metrics = XftMetrics {
+                    xft,
+                    dpy,
+                    fonts,
+                    font_map,
+                    glyph_widths,
+                };
+                self.cut = Some(layout::ellipsize(&self.markup, &mut metrics, width));
+            }
+            Overflow::Clip => self.clip = Some(width),
         }
-
-        // usize::MAX is our default value we need to get rid of it from background vec.
-        let background_vec: Vec<DisplayTemp> = background_vec
-            .iter()
-            .filter_map(|&bk_oj| {
-                if bk_oj.idx != usize::MAX {
-                    Some(bk_oj)
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        // usize::MAX is our default value we need to get rid of it from highlight vec.
-        let underline_vec: Vec<DisplayTemp> = underline_vec
-            .iter()
-            .filter_map(|&ul_oj| {
-                if ul_oj.idx != usize::MAX {
-                    Some(ul_oj)
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        // Fill in the default font faces.
-        let merg_fcs = DisplayTemp::default_font_faces(def_font_map, &font_face_vec, &text)?;
-
-        // Gen the final FontDisplayInfo objects.
-        let text_display = FontDisplayInfo::generate_list(&font_colour_vec, &merg_fcs);
-
-        // Gen the final underline RectDisplayInfo objects.
-        let underlines = unsafe {
-            RectDisplayInfo::gen_list(xft, dpy, fonts, &underline_vec, &text_display, &text)
-        };
-
-        // Gen the final background RectDisplayInfo objects.
-        let backgrounds = unsafe {
-            RectDisplayInfo::gen_list(xft, dpy, fonts, &background_vec, &text_display, &text)
-        };
-
-        // Return our valid string using the objects we generated previously.
-        self.text = text;
-        self.text_display = text_display;
-        self.underlines = underlines;
-        self.backgrounds = backgrounds;
-        Ok(())
     }
 }
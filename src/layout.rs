@@ -0,0 +1,817 @@
+// Turning parsed markup into pixel positions, using whatever can measure the fonts.
+// By: Curtis Jones <mail@curtisjones.ca>
+// Started on: September 07, 2020
+//
+
+use super::{
+    markup::{Action, Colour, Markup, Marquee, MinWidth, Run, Style, Target, Width},
+    region::Align,
+};
+use std::{iter::Peekable, slice};
+
+/// Anything that can tell us which font to use for a char and how wide text is when drawn.
+/// The bar implements this with Xft, the tests with made up numbers.
+pub trait FontMetrics {
+    /// Index of the first font that has a glyph for the char.
+    fn default_font(&mut self, ch: char) -> usize;
+    /// Pixel width of the text when drawn with the font at the index.
+    fn text_width(&mut self, font: usize, text: &str) -> u32;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Text that is drawn in one call, same font and colour all the way through.
+pub struct Chunk {
+    pub text: String,
+    /// Index of the font to draw with.
+    pub font: usize,
+    /// Colour from the font palette to draw with.
+    pub colour: Colour,
+    /// Pixel x-value to start at, relative to the start of the Input.
+    pub x: u32,
+    /// Pixel width of the text.
+    pub width: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Background, underline, or overline highlight, start and end are pixel x-values.
+pub struct Rect {
+    pub colour: Colour,
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Clickable area, start and end are pixel x-values.
+pub struct ActionArea {
+    /// Mouse button it is for, None for any.
+    pub button: Option<u32>,
+    pub target: Target,
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Window that text scrolls through. The content is laid out on its own, starting from 0.
+pub struct MarqueeArea {
+    /// Pixel x-value the window starts at.
+    pub x: u32,
+    /// Pixel width of the window.
+    pub width: u32,
+    pub content: Layout,
+}
+
+impl MarqueeArea {
+    /// Only text that doesn't fit in the window needs to scroll.
+    pub fn scrolls(&self) -> bool {
+        self.content.width > self.width
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// Everything needed to draw a parsed input.
+pub struct Layout {
+    pub chunks: Vec<Chunk>,
+    pub backgrounds: Vec<Rect>,
+    pub underlines: Vec<Rect>,
+    pub overlines: Vec<Rect>,
+    pub actions: Vec<ActionArea>,
+    pub marquees: Vec<MarqueeArea>,
+    /// Full pixel width of the text.
+    pub width: u32,
+}
+
+/// Add the pixel span for a highlight, joining it onto the last one if they touch and match.
+fn push_rect(rects: &mut Vec<Rect>, colour: Option<Colour>, start: u32, end: u32) {
+    if let Some(colour) = colour {
+        match rects.last_mut() {
+            Some(last) if last.colour == colour && last.end == start => last.end = end,
+            _ => rects.push(Rect { colour, start, end }),
+        }
+    }
+}
+
+/// A highlight that every run of the markup has, which is one set by a block around all of it.
+fn shared(markup: &Markup, pick: fn(&Style) -> Option<Colour>) -> Option<Colour> {
+    let mut colours = markup.runs.iter().map(|r| pick(&r.style));
+    let first = colours.next().flatten();
+    first.filter(|_| colours.all(|c| c == first))
+}
+
+/// Work out where everything in the markup goes.
+/// Marquees take up the width of their window, with their text laid out separately. Highlights
+/// from the blocks around a marquee are drawn across its whole window.
+///
+/// # Arguments
+/// * markup:  -> parsed input.
+/// * metrics: -> used to pick default fonts and measure the text.
+///
+/// # Output
+/// Layout with every pixel position calculated, relative to the start of the input.
+pub fn layout(markup: &Markup, metrics: &mut impl FontMetrics) -> Layout {
+    let mut res = Layout::default();
+    // Char offset and pixel x-value at the start of each run, used to place the actions.
+    let mut bounds = Vec::with_capacity(markup.runs.len() + 1);
+    let mut count = 0;
+    let mut x = 0;
+    let mut marquees = markup.marquees.iter().peekable();
+    let mut padder = Padder {
+        spans: markup.min_widths.iter().peekable(),
+        open: None,
+    };
+    let mut idx = 0;
+
+    loop {
+        padder.at(
+            &mut res,
+            &mut bounds,
+            metrics,
+            markup.runs.get(idx),
+            count,
+            &mut x,
+        );
+        while let Some(m) = marquees.next_if(|m| m.start <= count) {
+            let (inner, taken) = marquee_markup(markup, idx, m);
+            bounds.push((count, x));
+            let (start, end) = (x, x + m.width);
+            push_rect(
+                &mut res.backgrounds,
+                shared(&inner, |s| s.background),
+                start,
+                end,
+            );
+            push_rect(
+                &mut res.underlines,
+                shared(&inner, |s| s.underline),
+                start,
+                end,
+            );
+            push_rect(
+                &mut res.overlines,
+                shared(&inner, |s| s.overline),
+                start,
+                end,
+            );
+            res.marquees.push(MarqueeArea {
+                x,
+                width: m.width,
+                content: layout(&inner, metrics),
+            });
+            idx += taken;
+            count += inner
+                .runs
+                .iter()
+                .map(|r| r.text.chars().count())
+                .sum::<usize>();
+            x += m.width;
+        }
+        let run = match markup.runs.get(idx) {
+            Some(run) => run,
+            None => break,
+        };
+        idx += 1;
+
+        bounds.push((count, x));
+        let start_x = x;
+        // Without an explicit font the run gets split up every time the default font changes.
+        let mut chunk = String::new();
+        let mut chunk_font = None;
+        for ch in run.text.chars() {
+            let font = run.style.font.unwrap_or_else(|| metrics.default_font(ch));
+            if chunk_font.is_some() && chunk_font != Some(font) {
+                let font = chunk_font.take().expect("checked above");
+                x += push_chunk(&mut res, metrics, &mut chunk, font, run.style.colour, x);
+            }
+            chunk_font = Some(font);
+            chunk.push(ch);
+            count += 1;
+        }
+        if let Some(font) = chunk_font {
+            x += push_chunk(&mut res, metrics, &mut chunk, font, run.style.colour, x);
+        }
+        push_rect(&mut res.backgrounds, run.style.background, start_x, x);
+        push_rect(&mut res.underlines, run.style.underline, start_x, x);
+        push_rect(&mut res.overlines, run.style.overline, start_x, x);
+    }
+    padder.at(&mut res, &mut bounds, metrics, None, count, &mut x);
+    bounds.push((count, x));
+
+    // Actions always start and end on a run boundary, so the lookup is exact.
+    let x_at = |offset: usize| {
+        bounds
+            .iter()
+            .find(|&&(c, _)| c >= offset)
+            .map_or(x, |&(_, bx)| bx)
+    };
+    res.actions = markup
+        .actions
+        .iter()
+        // Actions inside of a marquee scroll with its text, so they were laid out with it.
+        .filter(|a| !markup.marquees.iter().any(|m| inside(a, m)))
+        .map(|a| ActionArea {
+            button: a.button,
+            target: a.target.clone(),
+            start: x_at(a.start),
+            end: x_at(a.end),
+        })
+        .collect();
+    res.width = x;
+    res
+}
+
+/// How much of the layout had been done when a span of padded text started, so everything in
+/// the span can be moved over once we know how much padding it needs.
+struct Mark {
+    x: u32,
+    chunks: usize,
+    backgrounds: usize,
+    underlines: usize,
+    overlines: usize,
+    marquees: usize,
+    bounds: usize,
+    /// Background of the first run, the padding is filled in with it.
+    background: Option<Colour>,
+}
+
+/// Pads out text to its minimum width as the layout reaches the start and end of each span.
+struct Padder<'a> {
+    spans: Peekable<slice::Iter<'a, MinWidth>>,
+    open: Option<(&'a MinWidth, Mark)>,
+}
+
+impl<'a> Padder<'a> {
+    /// Close the open span if it ends here, and open any that start here.
+    ///
+    /// # Arguments
+    /// * res:     -> layout done so far.
+    /// * bounds:  -> char offset and x-value at the start of each run so far.
+    /// * metrics: -> used to measure spans that are as wide as some text.
+    /// * next:    -> the run that starts here, if there is one.
+    /// * count:   -> chars laid out so far.
+    /// * x:       -> pixel x-value laid out to so far, moved past any padding.
+    fn at(
+        &mut self,
+        res: &mut Layout,
+        bounds: &mut Vec<(usize, u32)>,
+        metrics: &mut impl FontMetrics,
+        next: Option<&Run>,
+        count: usize,
+        x: &mut u32,
+    ) {
+        if self
+            .open
+            .as_ref()
+            .is_some_and(|(span, _)| span.end <= count)
+        {
+            self.close(res, bounds, metrics, count, x);
+        }
+        while let Some(span) = self.spans.next_if(|s| s.start <= count) {
+            // The start of the span is pinned, so clicks on it cover the padding too.
+            bounds.push((count, *x));
+            let mark = Mark {
+                x: *x,
+                chunks: res.chunks.len(),
+                backgrounds: res.backgrounds.len(),
+                underlines: res.underlines.len(),
+                overlines: res.overlines.len(),
+                marquees: res.marquees.len(),
+                bounds: bounds.len(),
+                background: next
+                    .and_then(|r| r.style.background)
+                    .filter(|_| span.end > count),
+            };
+            self.open = Some((span, mark));
+            if span.end <= count {
+                self.close(res, bounds, metrics, count, x);
+            }
+        }
+    }
+
+    fn close(
+        &mut self,
+        res: &mut Layout,
+        bounds: &mut Vec<(usize, u32)>,
+        metrics: &mut impl FontMetrics,
+        count: usize,
+        x: &mut u32,
+    ) {
+        let (span, mark) = match self.open.take() {
+            Some(open) => open,
+            None => return,
+        };
+        let want = match &span.width {
+            Width::Pixels(px) => *px,
+            Width::Text(text) => text
+                .chars()
+                .map(|ch| {
+                    let font = metrics.default_font(ch);
+                    metrics.text_width(font, ch.encode_utf8(&mut [0; 4]))
+                })
+                .sum(),
+        };
+        let used = *x - mark.x;
+        if want > used {
+            let pad = want - used;
+            let shift = match span.align {
+                Align::Left => 0,
+                Align::Center => pad / 2,
+                Align::Right => pad,
+            };
+            res.chunks[mark.chunks..]
+                .iter_mut()
+                .for_each(|c| c.x += shift);
+            for (rects, from) in [
+                (&mut res.backgrounds, mark.backgrounds),
+                (&mut res.underlines, mark.underlines),
+                (&mut res.overlines, mark.overlines),
+            ] {
+                // Highlights that ran into the span from before it were merged, split them back up.
+                if from > 0 && rects[from - 1].end > mark.x {
+                    let end = rects[from - 1].end;
+                    rects[from - 1].end = mark.x;
+                    let colour = rects[from - 1].colour;
+                    rects.insert(
+                        from,
+                        Rect {
+                            colour,
+                            start: mark.x,
+                            end,
+                        },
+                    );
+                }
+                rects[from..].iter_mut().for_each(|r| {
+                    r.start += shift;
+                    r.end += shift;
+                });
+            }
+            res.marquees[mark.marquees..]
+                .iter_mut()
+                .for_each(|m| m.x += shift);
+            bounds[mark.bounds..].iter_mut().for_each(|b| b.1 += shift);
+            *x += pad;
+            if let Some(colour) = mark.background {
+                res.backgrounds.insert(
+                    mark.backgrounds,
+                    Rect {
+                        colour,
+                        start: mark.x,
+                        end: *x,
+                    },
+                );
+            }
+        }
+        bounds.push((count, *x));
+    }
+}
+
+/// Whether an action only covers part of the text of a marquee.
+fn inside(action: &Action, marquee: &Marquee) -> bool {
+    marquee.start <= action.start
+        && action.end <= marquee.end
+        && (action.start, action.end) != (marquee.start, marquee.end)
+}
+
+/// Pull the text of a marquee out into its own markup, with offsets from the start of the
+/// marquee.
+///
+/// # Arguments
+/// * markup: -> parsed input the marquee is in.
+/// * first:  -> index of the first run of the marquee.
+/// * m:      -> the marquee.
+///
+/// # Output
+/// The markup of the marquee and how many runs it took up.
+fn marquee_markup(markup: &Markup, first: usize, m: &Marquee) -> (Markup, usize) {
+    let mut inner = Markup::default();
+    let mut count = m.start;
+    for run in markup.runs[first.min(markup.runs.len())..].iter() {
+        if count >= m.end {
+            break;
+        }
+        count += run.text.chars().count();
+        inner.runs.push(run.clone());
+    }
+    inner.actions = markup
+        .actions
+        .iter()
+        .filter(|a| inside(a, m))
+        .map(|a| Action {
+            start: a.start - m.start,
+            end: a.end - m.start,
+            ..a.clone()
+        })
+        .collect();
+    let taken = inner.runs.len();
+    (inner, taken)
+}
+
+/// Lay out the markup cut short with an ellipsis, so it fits in the width given. Markup that
+/// already fits is laid out as is. Marquees are kept or cut as a whole, and text that was padded
+/// out to a minimum width loses its padding.
+///
+/// # Arguments
+/// * markup:  -> parsed input.
+/// * metrics: -> used to pick default fonts and measure the text.
+/// * max:     -> pixels the layout can take up.
+///
+/// # Output
+/// Layout no wider than max, empty if not even the ellipsis fits.
+pub fn ellipsize(markup: &Markup, metrics: &mut impl FontMetrics, max: u32) -> Layout {
+    let full = layout(markup, metrics);
+    if full.width <= max {
+        return full;
+    }
+    let mut cut = Markup::default();
+    let mut count = 0;
+    let mut x = 0;
+    let mut marquees = markup.marquees.iter().peekable();
+    let mut idx = 0;
+    // The ellipsis takes on the style of the text it replaces.
+    let ellipsis = |style: Style| Run {
+        text: ELLIPSIS.to_string(),
+        style,
+    };
+
+    'runs: loop {
+        if let Some(m) = marquees.next_if(|m| m.start <= count) {
+            let (inner, taken) = marquee_markup(markup, idx, m);
+            let style = inner.runs.first().map_or_else(Style::default, |r| r.style);
+            let ell_width = ellipsis_width(metrics, style);
+            if x + m.width + ell_width > max {
+                if x + ell_width > max {
+                    return Layout::default();
+                }
+                cut.runs.push(ellipsis(style));
+                break;
+            }
+            idx += taken;
+            count += inner
+                .runs
+                .iter()
+                .map(|r| r.text.chars().count())
+                .sum::<usize>();
+            x += m.width;
+            cut.runs.extend(inner.runs);
+            cut.marquees.push(*m);
+            continue;
+        }
+        let run = match markup.runs.get(idx) {
+            Some(run) => run,
+            None => break,
+        };
+        idx += 1;
+
+        let mut text = String::new();
+        for ch in run.text.chars() {
+            let font = run.style.font.unwrap_or_else(|| metrics.default_font(ch));
+            let width = metrics.text_width(font, ch.encode_utf8(&mut [0; 4]));
+            let ell_width = ellipsis_width(metrics, run.style);
+            // Stop at the first char that wouldn't leave room for the ellipsis after it.
+            if x + width + ell_width > max {
+                if x + ell_width > max {
+                    return Layout::default();
+                }
+                cut.runs.push(Run {
+                    text,
+                    style: run.style,
+                });
+                cut.runs.push(ellipsis(run.style));
+                break 'runs;
+            }
+            x += width;
+            count += 1;
+            text.push(ch);
+        }
+        cut.runs.push(Run {
+            text,
+            style: run.style,
+        });
+    }
+    // Clickable areas that got cut off are gone, the ones cut in half end with the ellipsis.
+    cut.actions = markup
+        .actions
+        .iter()
+        .filter(|a| a.start < count)
+        .cloned()
+        .map(|mut a| {
+            a.end = a.end.min(count + 1);
+            a
+        })
+        .collect();
+    layout(&cut, metrics)
+}
+
+/// Pixel width of the ellipsis drawn in the style given.
+fn ellipsis_width(metrics: &mut impl FontMetrics, style: Style) -> u32 {
+    let font = style.font.unwrap_or_else(|| metrics.default_font(ELLIPSIS));
+    metrics.text_width(font, &ELLIPSIS.to_string())
+}
+
+/// Char put on the end of text that has been cut short.
+const ELLIPSIS: char = '\u{2026}';
+
+/// Measure and push a chunk of text, leaving the string empty for the next one.
+///
+/// # Output
+/// Pixel width of the chunk.
+fn push_chunk(
+    res: &mut Layout,
+    metrics: &mut impl FontMetrics,
+    text: &mut String,
+    font: usize,
+    colour: Colour,
+    x: u32,
+) -> u32 {
+    let width = metrics.text_width(font, text);
+    res.chunks.push(Chunk {
+        text: std::mem::take(text),
+        font,
+        colour,
+        x,
+        width,
+    });
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markup::{parse, Limits};
+
+    /// Every char is 10 pixels in font 0 and 20 in font 1. Only ascii is in font 0.
+    struct Fixed;
+
+    impl FontMetrics for Fixed {
+        fn default_font(&mut self, ch: char) -> usize {
+            if ch.is_ascii() {
+                0
+            } else {
+                1
+            }
+        }
+
+        fn text_width(&mut self, font: usize, text: &str) -> u32 {
+            text.chars().count() as u32 * if font == 0 { 10 } else { 20 }
+        }
+    }
+
+    const LIMITS: Limits = Limits {
+        fonts: 2,
+        font_colours: 4,
+        background_colours: 4,
+        underline_colours: 4,
+        overline_colours: 4,
+    };
+
+    fn lay(input: &str) -> Layout {
+        layout(&parse(input, &LIMITS), &mut Fixed)
+    }
+
+    /// The command of an area, they are all commands in these tests.
+    fn command(area: &ActionArea) -> &str {
+        match &area.target {
+            Target::Command(command) => command,
+            _ => "",
+        }
+    }
+
+    #[test]
+    fn empty_input_has_no_width() {
+        assert_eq!(lay(""), Layout::default());
+    }
+
+    #[test]
+    fn chunks_are_placed_one_after_the_other() {
+        let l = lay("ab{F1}cd");
+        let xs: Vec<(u32, u32)> = l.chunks.iter().map(|c| (c.x, c.width)).collect();
+        assert_eq!(xs, vec![(0, 20), (20, 20)]);
+        assert_eq!(l.width, 40);
+    }
+
+    #[test]
+    fn default_fonts_split_chunks() {
+        let l = lay("ab\u{f001}c");
+        let fonts: Vec<(&str, usize, u32)> = l
+            .chunks
+            .iter()
+            .map(|c| (c.text.as_str(), c.font, c.x))
+            .collect();
+        assert_eq!(fonts, vec![("ab", 0, 0), ("\u{f001}", 1, 20), ("c", 0, 40)]);
+        assert_eq!(l.width, 50);
+    }
+
+    #[test]
+    fn explicit_font_is_not_split() {
+        let l = lay("{f1}a\u{f001}");
+        assert_eq!(l.chunks.len(), 1);
+        assert_eq!(l.chunks[0].width, 40);
+    }
+
+    #[test]
+    fn highlights_cover_their_text() {
+        let l = lay("a{B1}bc{/B}d{H2}e");
+        assert_eq!(
+            l.backgrounds,
+            vec![Rect {
+                colour: Colour::Index(1),
+                start: 10,
+                end: 30,
+            }]
+        );
+        assert_eq!(
+            l.underlines,
+            vec![Rect {
+                colour: Colour::Index(2),
+                start: 40,
+                end: 50,
+            }]
+        );
+    }
+
+    #[test]
+    fn overlines_are_separate_from_underlines() {
+        let l = lay("{O1}ab{H2}c{/O}d");
+        assert_eq!((l.overlines[0].start, l.overlines[0].end), (0, 30));
+        assert_eq!((l.underlines[0].start, l.underlines[0].end), (20, 40));
+    }
+
+    #[test]
+    fn highlight_spans_colour_changes() {
+        let l = lay("{B1}a{F1}b{F2}c");
+        assert_eq!(l.backgrounds.len(), 1);
+        assert_eq!((l.backgrounds[0].start, l.backgrounds[0].end), (0, 30));
+    }
+
+    #[test]
+    fn ellipsize_leaves_short_text() {
+        let m = parse("abc", &LIMITS);
+        assert_eq!(ellipsize(&m, &mut Fixed, 30), layout(&m, &mut Fixed));
+    }
+
+    #[test]
+    fn ellipsize_cuts_long_text() {
+        // The ellipsis isn't ascii, so it is 20 pixels wide.
+        let m = parse("ab{B1}cdef", &LIMITS);
+        let l = ellipsize(&m, &mut Fixed, 45);
+        let texts: Vec<&str> = l.chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["ab", "\u{2026}"]);
+        assert_eq!(l.width, 40);
+        // The ellipsis has the background of the text it replaced.
+        assert_eq!((l.backgrounds[0].start, l.backgrounds[0].end), (20, 40));
+        let l = ellipsize(&m, &mut Fixed, 50);
+        let texts: Vec<&str> = l.chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["ab", "c", "\u{2026}"]);
+        assert_eq!((l.backgrounds[0].start, l.backgrounds[0].end), (20, 50));
+    }
+
+    #[test]
+    fn ellipsize_too_small() {
+        let m = parse("abc", &LIMITS);
+        assert_eq!(ellipsize(&m, &mut Fixed, 15), Layout::default());
+    }
+
+    #[test]
+    fn ellipsize_trims_actions() {
+        let m = parse("{A:one}ab{/A}{A:two}cd{/A}{A:three}ef", &LIMITS);
+        let l = ellipsize(&m, &mut Fixed, 50);
+        let areas: Vec<(&str, u32, u32)> = l
+            .actions
+            .iter()
+            .map(|a| (command(a), a.start, a.end))
+            .collect();
+        assert_eq!(areas, vec![("one", 0, 20), ("two", 20, 50)]);
+    }
+
+    #[test]
+    fn action_areas() {
+        let l = lay("xx{A1:one}a\u{f001}{A3:two}b{/A}{/A}y");
+        let areas: Vec<(Option<u32>, u32, u32)> = l
+            .actions
+            .iter()
+            .map(|a| (a.button, a.start, a.end))
+            .collect();
+        assert_eq!(areas, vec![(Some(3), 50, 60), (Some(1), 20, 60)]);
+    }
+
+    #[test]
+    fn marquees_take_up_their_width() {
+        let l = lay("a{M25}bcdef{/M}g");
+        let texts: Vec<(&str, u32)> = l.chunks.iter().map(|c| (c.text.as_str(), c.x)).collect();
+        assert_eq!(texts, vec![("a", 0), ("g", 35)]);
+        assert_eq!(l.width, 45);
+        assert_eq!(l.marquees.len(), 1);
+        let m = &l.marquees[0];
+        assert_eq!((m.x, m.width, m.content.width), (10, 25, 50));
+        assert!(m.scrolls());
+        assert_eq!(m.content.chunks[0].text, "bcdef");
+    }
+
+    #[test]
+    fn marquee_windows_get_the_highlights_around_them() {
+        let l = lay("{B1}a{M25}b{H1}cdef{/H}{/M}g{/B}");
+        assert_eq!(
+            l.backgrounds,
+            vec![Rect {
+                colour: Colour::Index(1),
+                start: 0,
+                end: 45
+            }]
+        );
+        // Only part of the text has the underline, so it scrolls with it.
+        assert!(l.underlines.is_empty());
+        assert_eq!(l.marquees[0].content.underlines.len(), 1);
+    }
+
+    #[test]
+    fn short_marquees_do_not_scroll() {
+        let l = lay("{M30}ab{/M}");
+        assert_eq!(l.width, 30);
+        assert!(!l.marquees[0].scrolls());
+    }
+
+    #[test]
+    fn marquee_actions() {
+        let l = lay("{A:all}{M20}a{A:part}bc{/A}{/M}{/A}");
+        let outer: Vec<(&str, u32, u32)> = l
+            .actions
+            .iter()
+            .map(|a| (command(a), a.start, a.end))
+            .collect();
+        assert_eq!(outer, vec![("all", 0, 20)]);
+        let inner = &l.marquees[0].content.actions[0];
+        assert_eq!((command(inner), inner.start, inner.end), ("part", 10, 30));
+    }
+
+    fn padded(input: &str, spans: &[(Width, Align, usize, usize)]) -> Layout {
+        let mut m = parse(input, &LIMITS);
+        m.min_widths = spans
+            .iter()
+            .map(|(width, align, start, end)| MinWidth {
+                width: width.clone(),
+                align: *align,
+                start: *start,
+                end: *end,
+            })
+            .collect();
+        // Spans always fall on run boundaries, the parser splits runs for them.
+        m.runs = m
+            .text()
+            .chars()
+            .map(|ch| Run {
+                text: ch.to_string(),
+                style: m.runs[0].style,
+            })
+            .collect();
+        layout(&m, &mut Fixed)
+    }
+
+    #[test]
+    fn min_widths_pad_text() {
+        let l = padded(
+            "{B1}abc",
+            &[
+                (Width::Pixels(40), Align::Right, 0, 2),
+                (Width::Pixels(5), Align::Center, 2, 2),
+                (Width::Text("\u{f001}".to_string()), Align::Center, 2, 3),
+            ],
+        );
+        let xs: Vec<u32> = l.chunks.iter().map(|c| c.x).collect();
+        assert_eq!(xs, vec![20, 30, 50]);
+        assert_eq!(l.width, 65);
+        // The padding takes on the background of the text, the empty gap has none.
+        let bgs: Vec<(u32, u32)> = l.backgrounds.iter().map(|r| (r.start, r.end)).collect();
+        assert_eq!(bgs, vec![(0, 40), (20, 40), (45, 65), (50, 60)]);
+    }
+
+    #[test]
+    fn min_widths_never_shrink() {
+        let l = padded("abc", &[(Width::Pixels(10), Align::Left, 0, 3)]);
+        assert_eq!(l.width, 30);
+    }
+
+    #[test]
+    fn min_width_actions_cover_the_padding() {
+        let mut m = parse("{A:a}b{/A}{A:c}d{/A}", &LIMITS);
+        m.min_widths = vec![MinWidth {
+            width: Width::Pixels(30),
+            align: Align::Center,
+            start: 0,
+            end: 1,
+        }];
+        let l = layout(&m, &mut Fixed);
+        let areas: Vec<(u32, u32)> = l.actions.iter().map(|a| (a.start, a.end)).collect();
+        assert_eq!(areas, vec![(0, 30), (30, 40)]);
+        assert_eq!(l.chunks[0].x, 10);
+    }
+
+    #[test]
+    fn ellipsize_keeps_marquees_whole() {
+        let m = parse("a{M30}bcdef{/M}gh", &LIMITS);
+        let l = ellipsize(&m, &mut Fixed, 60);
+        assert_eq!(l.marquees.len(), 1);
+        assert_eq!(l.width, 60);
+        let l = ellipsize(&m, &mut Fixed, 55);
+        assert!(l.marquees.is_empty());
+        let texts: Vec<&str> = l.chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["a", "\u{2026}"]);
+    }
+}
@@ -1,7 +1,8 @@
 #![allow(
     clippy::missing_safety_doc,
     clippy::len_without_is_empty,
-    clippy::not_unsafe_ptr_arg_deref
+    clippy::not_unsafe_ptr_arg_deref,
+    clippy::should_implement_trait
 )]
 // this file is basically just to weave together the seperate mod files.
 // also the pub use statements bring objects over to our main file.
@@ -15,10 +16,25 @@ pub mod bar;
 /// Parsing the config file and adjusting based on command line args provided.
 pub mod config;
 
+/// Commands sent to a running bar over its unix socket.
+pub mod control;
+
 /// Turning basic random characters into Input struct that the Bar struct can display to the
 /// screen.
 pub mod input;
 
+/// Placing parsed text on the bar, using anything that can measure fonts.
+pub mod layout;
+
+/// Parsing the formatting blocks in the input, without touching Xlib.
+pub mod markup;
+
+/// Finding the monitors to put the bar on.
+pub mod monitor;
+
+/// Named regions of the bar, which section of input goes to each and where they are drawn.
+pub mod region;
+
 /// Module containing optional additions to the bar.
 pub mod optional;
 
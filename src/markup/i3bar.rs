@@ -0,0 +1,423 @@
+// Reading the i3bar JSON protocol, so generators like i3status, i3status-rust, and i3blocks can
+// drive the bar directly.
+// By: Curtis Jones <mail@curtisjones.ca>
+// Started on: October 18, 2026
+//
+// The stream is a header object followed by an array that never ends, holding one array of
+// blocks for each update:
+//  {"version": 1, "click_events": true}
+//  [
+//  [{"full_text": "a"}, {"full_text": "b"}],
+//  [{"full_text": "a"}, {"full_text": "c"}],
+//
+// Supported block fields:
+//  full_text                     -> the text, drawn as is.
+//  color, background, border     -> #RRGGBB or #RRGGBBAA colours, the border is drawn as an
+//                                   overline and an underline.
+//  border_top, border_bottom     -> 0 turns off that side of the border.
+//  min_width, align              -> pixels or text to pad the block out to, and where it sits.
+//  separator,                    -> a line and gap after the block, or just the gap when the
+//  separator_block_width            separator is off.
+//  name, instance                -> sent back with click events.
+// Anything else, like short_text or pango markup, is read and skipped.
+
+use super::{Builder, Colour, Markup, Style, Target, Width};
+use crate::region::Align;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("expected {0} but found '{1}'")]
+    Unexpected(&'static str, char),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+/// First thing sent by the generator.
+pub struct Header {
+    pub version: u32,
+    /// Whether the generator wants clicks written back to it.
+    #[serde(default)]
+    pub click_events: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Which block was clicked, kept as the command of the clickable area over each block.
+pub struct BlockId {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+/// Click event written back to the generator.
+pub struct Click {
+    #[serde(flatten)]
+    pub id: BlockId,
+    pub button: u32,
+    pub modifiers: Vec<&'static str>,
+    /// Position of the click on the screen.
+    pub x: i32,
+    pub y: i32,
+    /// Position of the click inside of the block.
+    pub relative_x: i32,
+    pub relative_y: i32,
+    /// Size of the block.
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MinWidth {
+    Pixels(u32),
+    Text(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Block {
+    full_text: String,
+    color: Option<String>,
+    background: Option<String>,
+    border: Option<String>,
+    border_top: Option<u32>,
+    border_bottom: Option<u32>,
+    min_width: Option<MinWidth>,
+    align: Option<String>,
+    separator: Option<bool>,
+    separator_block_width: Option<u32>,
+    #[serde(flatten)]
+    id: BlockId,
+}
+
+/// Gap i3bar leaves after a block when it doesn't say.
+const SEPARATOR_BLOCK_WIDTH: u32 = 9;
+
+/// Something complete read off of the stream.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Item {
+    Header(Header),
+    /// All of the blocks of an update, in one markup.
+    Status(Markup),
+}
+
+#[derive(Debug, Default)]
+/// Reads the stream a line at a time. Values can be split over lines or share one, anything that
+/// isn't complete yet is kept until the rest of it comes in.
+pub struct Stream {
+    buf: String,
+    header: bool,
+    /// Inside of the array that holds the updates.
+    updates: bool,
+}
+
+impl Stream {
+    /// Add a line to the stream and read whatever is complete.
+    ///
+    /// # Arguments
+    /// * line: -> line read from stdin.
+    ///
+    /// # Output
+    /// Everything complete in the order it came in, along with any problems.
+    pub fn push(&mut self, line: &str) -> Vec<Result<Item, Error>> {
+        self.buf.push_str(line);
+        self.buf.push('\n');
+        let mut res = Vec::new();
+        loop {
+            // Updates are seperated by commas, we don't need them to tell where one ends.
+            let rest = self
+                .buf
+                .trim_start_matches(|c: char| c.is_whitespace() || (self.updates && c == ','));
+            let skip = self.buf.len() - rest.len();
+            self.buf.drain(..skip);
+            let first = match self.buf.chars().next() {
+                Some(c) => c,
+                None => break,
+            };
+            if self.header && !self.updates {
+                if first == '[' {
+                    self.buf.drain(..1);
+                    self.updates = true;
+                } else {
+                    res.push(Err(Error::Unexpected("'['", first)));
+                    self.skip_line();
+                }
+                continue;
+            }
+            // The end of the array that never ends, a new one would have to be opened.
+            if self.updates && first == ']' {
+                self.buf.drain(..1);
+                self.updates = false;
+                continue;
+            }
+            let want = if self.header { '[' } else { '{' };
+            if first != want {
+                let expected = if self.header {
+                    "an array of blocks"
+                } else {
+                    "the header"
+                };
+                res.push(Err(Error::Unexpected(expected, first)));
+                self.skip_line();
+                continue;
+            }
+            let end = match value_end(&self.buf) {
+                Some(end) => end,
+                None => break,
+            };
+            let value: String = self.buf.drain(..end).collect();
+            if self.header {
+                res.push(
+                    serde_json::from_str::<Vec<Block>>(&value)
+                        .map(|blocks| Item::Status(to_markup(&blocks)))
+                        .map_err(Error::from),
+                );
+            } else {
+                self.header = true;
+                res.push(
+                    serde_json::from_str(&value)
+                        .map(Item::Header)
+                        .map_err(Error::from),
+                );
+            }
+        }
+        res
+    }
+
+    /// Throw out the rest of the line after something we couldn't read.
+    fn skip_line(&mut self) {
+        let end = self.buf.find('\n').map_or(self.buf.len(), |i| i + 1);
+        self.buf.drain(..end);
+    }
+}
+
+/// Find where the JSON object or array at the start of the text ends.
+///
+/// # Output
+/// Byte offset just past the closing bracket, None if it isn't closed yet.
+fn value_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut string = false;
+    let mut escaped = false;
+    for (idx, ch) in text.char_indices() {
+        if string {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match ch {
+            '"' => string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Read a #RRGGBB or #RRGGBBAA colour.
+///
+/// # Output
+/// The colour packed as 0xAARRGGBB, None if it isn't valid.
+fn colour(text: &str) -> Option<Colour> {
+    let hex = text.strip_prefix('#')?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex.get(..6)?, 16).ok()?;
+    let alpha = match hex.len() {
+        6 => 0xFF,
+        8 => u32::from_str_radix(&hex[6..], 16).ok()?,
+        _ => return None,
+    };
+    Some(Colour::Literal(alpha << 24 | rgb))
+}
+
+/// Turn the blocks of an update into markup. Each block is a clickable area for every button,
+/// with the command holding the name and instance to send back.
+fn to_markup(blocks: &[Block]) -> Markup {
+    let mut out = Builder::default();
+    for (idx, block) in blocks.iter().enumerate() {
+        let border = block.border.as_deref().and_then(colour);
+        let style = Style {
+            colour: block
+                .color
+                .as_deref()
+                .and_then(colour)
+                .unwrap_or(Colour::Index(0)),
+            background: block.background.as_deref().and_then(colour),
+            underline: border.filter(|_| block.border_bottom != Some(0)),
+            overline: border.filter(|_| block.border_top != Some(0)),
+            ..Style::default()
+        };
+        let id = serde_json::to_string(&block.id).unwrap_or_default();
+        out.open_action(None, Target::Block(id));
+        if let Some(min_width) = &block.min_width {
+            let width = match min_width {
+                MinWidth::Pixels(px) => Width::Pixels(*px),
+                MinWidth::Text(text) => Width::Text(text.clone()),
+            };
+            let align = block
+                .align
+                .as_deref()
+                .and_then(Align::from_str)
+                .unwrap_or(Align::Left);
+            out.open_min_width(width, align);
+        }
+        block
+            .full_text
+            .chars()
+            .for_each(|ch| out.push_char(ch, style));
+        out.close_min_width();
+        out.close_action();
+
+        // i3bar doesn't put a separator after the last block.
+        if idx + 1 < blocks.len() {
+            let width = block.separator_block_width.unwrap_or(SEPARATOR_BLOCK_WIDTH);
+            out.open_min_width(Width::Pixels(width), Align::Center);
+            if block.separator != Some(false) {
+                out.push_char('|', Style::default());
+            }
+            out.close_min_width();
+        }
+    }
+    out.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markup::MinWidth as Span;
+
+    fn status(items: Vec<Result<Item, Error>>) -> Vec<Markup> {
+        items
+            .into_iter()
+            .map(|item| match item {
+                Ok(Item::Status(markup)) => markup,
+                other => panic!("expected a status, got {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn header_then_updates() {
+        let mut stream = Stream::default();
+        assert_eq!(
+            stream.push(r#"{"version": 1, "click_events": true}"#)[0]
+                .as_ref()
+                .ok(),
+            Some(&Item::Header(Header {
+                version: 1,
+                click_events: true,
+            }))
+        );
+        assert!(stream.push("[").is_empty());
+        let first = status(stream.push(r#"[{"full_text": "a"}]"#));
+        assert_eq!(first[0].text(), "a");
+        let second = status(stream.push(r#",[{"full_text": "b]"}]"#));
+        assert_eq!(second[0].text(), "b]");
+    }
+
+    #[test]
+    fn values_split_over_lines() {
+        let mut stream = Stream::default();
+        assert!(stream.push(r#"{"version":"#).is_empty());
+        assert_eq!(stream.push("1} [ [").len(), 1);
+        let updates = status(stream.push(r#"{"full_text": "a"}],[{"full_text": "b"}]"#));
+        let texts: Vec<String> = updates.iter().map(|m| m.text()).collect();
+        assert_eq!(texts, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let mut stream = Stream::default();
+        stream.push(r#"{"version": 1}"#);
+        assert!(matches!(
+            stream.push("oops")[..],
+            [Err(Error::Unexpected("'['", 'o'))]
+        ));
+        stream.push("[");
+        assert!(matches!(
+            stream.push(r#"[{"text": "a"}]"#)[..],
+            [Err(Error::Json(_))]
+        ));
+        assert_eq!(status(stream.push(r#"[{"full_text": "a"}]"#)).len(), 1);
+    }
+
+    #[test]
+    fn block_styles() {
+        let blocks = serde_json::from_str::<Vec<Block>>(
+            r##"[{"full_text": "a", "color": "#ff0000", "background": "#00ff0080",
+                  "border": "#0000ff", "border_top": 0, "urgent": true}]"##,
+        )
+        .unwrap();
+        let style = to_markup(&blocks).runs[0].style;
+        assert_eq!(style.colour, Colour::Literal(0xFFFF0000));
+        assert_eq!(style.background, Some(Colour::Literal(0x8000FF00)));
+        assert_eq!(style.underline, Some(Colour::Literal(0xFF0000FF)));
+        assert_eq!(style.overline, None);
+    }
+
+    #[test]
+    fn separators_and_min_widths() {
+        let blocks = serde_json::from_str::<Vec<Block>>(
+            r#"[{"full_text": "a", "min_width": 50, "align": "right"},
+                {"full_text": "b", "min_width": "100%", "separator": false,
+                 "separator_block_width": 4},
+                {"full_text": "c"}]"#,
+        )
+        .unwrap();
+        let markup = to_markup(&blocks);
+        assert_eq!(markup.text(), "a|bc");
+        let spans: Vec<(Width, Align, usize, usize)> = markup
+            .min_widths
+            .iter()
+            .map(|s: &Span| (s.width.clone(), s.align, s.start, s.end))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (Width::Pixels(50), Align::Right, 0, 1),
+                (Width::Pixels(9), Align::Center, 1, 2),
+                (Width::Text("100%".to_string()), Align::Left, 2, 3),
+                (Width::Pixels(4), Align::Center, 3, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks_are_clickable() {
+        let blocks = serde_json::from_str::<Vec<Block>>(
+            r#"[{"full_text": "ab", "name": "cpu", "instance": "0"}, {"full_text": "c"}]"#,
+        )
+        .unwrap();
+        let actions = to_markup(&blocks).actions;
+        assert_eq!(actions[0].button, None);
+        assert_eq!(
+            actions[0].target,
+            Target::Block(r#"{"name":"cpu","instance":"0"}"#.to_string())
+        );
+        assert_eq!((actions[0].start, actions[0].end), (0, 2));
+        assert_eq!(actions[1].target, Target::Block("{}".to_string()));
+        assert_eq!((actions[1].start, actions[1].end), (3, 4));
+    }
+
+    #[test]
+    fn colours() {
+        assert_eq!(colour("#123456"), Some(Colour::Literal(0xFF123456)));
+        assert_eq!(colour("#12345678"), Some(Colour::Literal(0x78123456)));
+        assert_eq!(colour("#fff"), None);
+        assert_eq!(colour("123456"), None);
+    }
+}
@@ -0,0 +1,379 @@
+// Parsing for lemonbar style markup, so scripts written for lemonbar work without any changes.
+// By: Curtis Jones <mail@curtisjones.ca>
+// Started on: September 07, 2020
+//
+// Supported:
+//  %{l} %{c} %{r}       -> alignment.
+//  %{F#..} %{F-}        -> font colour, palette indexes like %{F1} also work.
+//  %{B#..} %{B-}        -> background colour.
+//  %{U#..} %{U-}        -> underline colour, only drawn while underline is on.
+//  %{+u} %{-u} %{!u}    -> underline on, off, or toggle.
+//  %{+o} %{-o} %{!o}    -> overline on, off, or toggle, drawn with the underline colour.
+//  %{T1} %{T-}          -> font face, counted from 1 like lemonbar.
+//  %{A3:cmd:} %{A}      -> clickable areas, \: for a colon in the command.
+//  %{R}                 -> swap font and background colours.
+// Offsets and monitors are read and skipped.
+
+use super::{locate, Builder, Colour, Cursor, DiagnosticKind, Limits, Markup, Style, Target};
+
+/// Parse a full line of lemonbar style input. Unlike our own markup the alignment is set inside
+/// the line instead of with splitters, so we hand back all three sections at once.
+///
+/// # Arguments
+/// * input:  -> the full line read from stdin.
+/// * limits: -> number of fonts and colours available for checking indexes.
+///
+/// # Output
+/// Markup for the left, center, and right sections of the bar, in that order.
+pub fn parse(input: &str, limits: &Limits) -> [Markup; 3] {
+    let mut parser = Parser {
+        chars: Cursor::new(input),
+        limits,
+        style: Style::default(),
+        underline: Colour::Index(0),
+        underline_on: false,
+        overline_on: false,
+        align: 0,
+        out: Default::default(),
+    };
+    parser.run();
+    let [left, center, right] = parser.out;
+    let mut res = [left.finish(), center.finish(), right.finish()];
+    res.iter_mut()
+        .for_each(|markup| locate(input, &mut markup.diagnostics));
+    res
+}
+
+/// State for a single parse.
+struct Parser<'a> {
+    chars: Cursor<'a>,
+    limits: &'a Limits,
+    /// Style to use for the next char, without the underline or overline.
+    style: Style,
+    /// Lemonbar keeps the underline colour even while the underline is turned off.
+    underline: Colour,
+    underline_on: bool,
+    overline_on: bool,
+    /// Which of the three sections we are adding to.
+    align: usize,
+    out: [Builder; 3],
+}
+
+impl Parser<'_> {
+    fn run(&mut self) {
+        while let Some((offset, ch)) = self.chars.next() {
+            match (ch, self.chars.peek()) {
+                ('%', Some('{')) => {
+                    self.chars.next();
+                    self.block(offset);
+                }
+                // %% is a literal percent sign, a % by itself is just text anyway.
+                ('%', Some('%')) => {
+                    self.chars.next();
+                    self.push_char('%');
+                }
+                _ => self.push_char(ch),
+            }
+        }
+    }
+
+    fn push_char(&mut self, ch: char) {
+        let style = Style {
+            underline: Some(self.underline).filter(|_| self.underline_on),
+            overline: Some(self.underline).filter(|_| self.overline_on),
+            ..self.style
+        };
+        self.out[self.align].push_char(ch, style);
+    }
+
+    fn diagnostic(&mut self, offset: usize, kind: DiagnosticKind) {
+        self.out[self.align].diagnostic(offset, kind);
+    }
+
+    /// Parse a format block, the %{ has already been read.
+    fn block(&mut self, start: usize) {
+        loop {
+            match self.chars.next() {
+                None => return self.diagnostic(start, DiagnosticKind::UnterminatedBlock),
+                Some((_, '}')) => return,
+                Some((offset, cmd)) => match cmd {
+                    'l' => self.align = 0,
+                    'c' => self.align = 1,
+                    'r' => self.align = 2,
+                    'F' | 'B' | 'U' => self.colour(offset, cmd),
+                    'T' => self.font(offset),
+                    '+' | '-' | '!' => {
+                        let line = match self.chars.peek() {
+                            Some('u') => &mut self.underline_on,
+                            Some('o') => &mut self.overline_on,
+                            _ => continue,
+                        };
+                        *line = match cmd {
+                            '+' => true,
+                            '-' => false,
+                            _ => !*line,
+                        };
+                        self.chars.next();
+                    }
+                    // Swapping only makes sense with a background set. Lemonbar only has literal
+                    // colours, palette indexes would point into the other palette.
+                    'R' => {
+                        if let Some(bg) = self.style.background {
+                            self.style.background = Some(self.style.colour);
+                            self.style.colour = bg;
+                        }
+                    }
+                    'A' => self.action(offset),
+                    // Pixel offsets aren't supported, skip the width.
+                    'O' => {
+                        self.chars.index();
+                    }
+                    // We pick the monitor with the config, skip the value.
+                    'S' => {
+                        if let Some('+' | '-' | 'f' | 'l') = self.chars.peek() {
+                            self.chars.next();
+                        } else {
+                            self.chars.index();
+                        }
+                    }
+                    // Spaces seperate commands, anything else is skipped but reported.
+                    _ if cmd.is_whitespace() => (),
+                    _ => self.diagnostic(offset, DiagnosticKind::UnknownTag),
+                },
+            }
+        }
+    }
+
+    /// Set or reset one of the colours.
+    fn colour(&mut self, offset: usize, cmd: char) {
+        if self.chars.peek() == Some('-') {
+            self.chars.next();
+            match cmd {
+                'F' => self.style.colour = Colour::Index(0),
+                'B' => self.style.background = None,
+                _ => self.underline = Colour::Index(0),
+            }
+            return;
+        }
+        let len = match cmd {
+            'F' => self.limits.font_colours,
+            'B' => self.limits.background_colours,
+            _ => self.limits.underline_colours,
+        };
+        // Lemonbar commands are seperated with spaces, so a literal takes all of its digits.
+        match self.chars.colour(len, "") {
+            Ok(colour) => match cmd {
+                'F' => self.style.colour = colour,
+                'B' => self.style.background = Some(colour),
+                _ => self.underline = colour,
+            },
+            Err(kind) => self.diagnostic(offset, kind),
+        }
+    }
+
+    /// Set or reset the font. Lemonbar counts fonts from 1.
+    fn font(&mut self, offset: usize) {
+        if self.chars.peek() == Some('-') {
+            self.chars.next();
+            self.style.font = None;
+            return;
+        }
+        match self.chars.index() {
+            Some(idx) if idx >= 1 && idx <= self.limits.fonts => self.style.font = Some(idx - 1),
+            Some(_) => self.diagnostic(offset, DiagnosticKind::IndexTooLarge),
+            None => self.diagnostic(offset, DiagnosticKind::MissingIndex),
+        }
+    }
+
+    /// Open a clickable area if there is a command, otherwise close the last one opened.
+    fn action(&mut self, offset: usize) {
+        // The button defaults to left click.
+        let button = self.chars.index().unwrap_or(1) as u32;
+        if self.chars.peek() != Some(':') {
+            return self.out[self.align].close_action();
+        }
+        self.chars.next();
+        // The command runs to the next colon that isn't escaped with a backslash.
+        let mut command = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '\\')) if self.chars.peek() == Some(':') => {
+                    self.chars.next();
+                    command.push(':');
+                }
+                Some((_, ':')) => break,
+                Some((_, c)) => command.push(c),
+                None => return self.diagnostic(offset, DiagnosticKind::UnterminatedBlock),
+            }
+        }
+        self.out[self.align].open_action(Some(button), Target::Command(command));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markup::{Action, Run};
+
+    const LIMITS: Limits = Limits {
+        fonts: 2,
+        font_colours: 2,
+        background_colours: 2,
+        underline_colours: 2,
+        overline_colours: 2,
+    };
+
+    #[test]
+    fn plain_text_is_left_aligned() {
+        let [l, c, r] = parse("hello", &LIMITS);
+        assert_eq!(l.text(), "hello");
+        assert!(c.runs.is_empty() && r.runs.is_empty());
+    }
+
+    #[test]
+    fn alignment_blocks() {
+        let [l, c, r] = parse("%{l}left%{c}center%{r}right%{l} more", &LIMITS);
+        assert_eq!(l.text(), "left more");
+        assert_eq!(c.text(), "center");
+        assert_eq!(r.text(), "right");
+    }
+
+    #[test]
+    fn colours_and_resets() {
+        let [l, _, _] = parse("%{F#fff B#000}a%{F- B-}b%{F1}c", &LIMITS);
+        assert_eq!(
+            l.runs,
+            vec![
+                Run {
+                    text: "a".to_string(),
+                    style: Style {
+                        colour: Colour::Literal(0xFFFFFFFF),
+                        background: Some(Colour::Literal(0xFF000000)),
+                        ..Style::default()
+                    },
+                },
+                Run {
+                    text: "b".to_string(),
+                    style: Style::default(),
+                },
+                Run {
+                    text: "c".to_string(),
+                    style: Style {
+                        colour: Colour::Index(1),
+                        ..Style::default()
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn style_carries_across_alignments() {
+        let [l, _, r] = parse("%{F#f00}a%{r}b", &LIMITS);
+        assert_eq!(l.runs[0].style, r.runs[0].style);
+    }
+
+    #[test]
+    fn underline_needs_to_be_on() {
+        let [l, _, _] = parse("%{U#f00}a%{+u}b%{!u}c%{!u U-}d%{-u}e", &LIMITS);
+        let uls: Vec<Option<Colour>> = l.runs.iter().map(|r| r.style.underline).collect();
+        assert_eq!(
+            uls,
+            vec![
+                None,
+                Some(Colour::Literal(0xFFFF0000)),
+                None,
+                Some(Colour::Index(0)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn overline_uses_the_underline_colour() {
+        let [l, _, _] = parse("%{U#f00 +o}a%{+u}b%{-o}c", &LIMITS);
+        let lines: Vec<(Option<Colour>, Option<Colour>)> = l
+            .runs
+            .iter()
+            .map(|r| (r.style.overline, r.style.underline))
+            .collect();
+        let red = Some(Colour::Literal(0xFFFF0000));
+        assert_eq!(lines, vec![(red, None), (red, red), (None, red)]);
+    }
+
+    #[test]
+    fn fonts_count_from_one() {
+        let [l, _, _] = parse("%{T2}a%{T-}b%{T3}c%{T0}d", &LIMITS);
+        assert_eq!(l.runs[0].style.font, Some(1));
+        assert_eq!(l.runs[1].style.font, None);
+        let kinds: Vec<DiagnosticKind> = l.diagnostics.iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![DiagnosticKind::IndexTooLarge, DiagnosticKind::IndexTooLarge]
+        );
+    }
+
+    #[test]
+    fn actions() {
+        let [l, _, _] = parse(
+            "%{A:bspc desktop -f 1:}1%{A}%{A3:echo a\\:b:}2%{A}",
+            &LIMITS,
+        );
+        assert_eq!(
+            l.actions,
+            vec![
+                Action {
+                    button: Some(1),
+                    target: Target::Command("bspc desktop -f 1".to_string()),
+                    start: 0,
+                    end: 1,
+                },
+                Action {
+                    button: Some(3),
+                    target: Target::Command("echo a:b".to_string()),
+                    start: 1,
+                    end: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn swap_colours() {
+        let [l, _, _] = parse("%{F#111 B#222 R}a", &LIMITS);
+        assert_eq!(l.runs[0].style.colour, Colour::Literal(0xFF222222));
+        assert_eq!(
+            l.runs[0].style.background,
+            Some(Colour::Literal(0xFF111111))
+        );
+    }
+
+    #[test]
+    fn unsupported_commands_are_skipped() {
+        let [l, _, _] = parse("%{O10 S+}a%{Sf S1}b", &LIMITS);
+        assert_eq!(l.text(), "ab");
+        assert!(l.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unknown_commands_are_reported() {
+        let [l, _, r] = parse("a%{r}b%{Z}c", &LIMITS);
+        assert!(l.diagnostics.is_empty());
+        assert_eq!(r.text(), "bc");
+        assert_eq!((r.diagnostics[0].offset, r.diagnostics[0].tag), (8, 'Z'));
+        assert_eq!(r.diagnostics[0].kind, DiagnosticKind::UnknownTag);
+    }
+
+    #[test]
+    fn percent_signs() {
+        let [l, _, _] = parse("50% 100%%{F1}", &LIMITS);
+        assert_eq!(l.text(), "50% 100%{F1}");
+    }
+
+    #[test]
+    fn unterminated_block() {
+        let [l, _, _] = parse("a%{F#fff", &LIMITS);
+        assert_eq!(l.diagnostics[0].kind, DiagnosticKind::UnterminatedBlock);
+        assert_eq!(l.text(), "a");
+    }
+}
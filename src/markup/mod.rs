@@ -0,0 +1,1307 @@
+// Pure parsing of the formatting blocks in a line of input, no Xlib anywhere in here.
+// By: Curtis Jones <mail@curtisjones.ca>
+// Started on: September 07, 2020
+//
+
+use crate::region::Align;
+use std::{
+    fmt,
+    iter::{Enumerate, Peekable},
+    str::CharIndices,
+};
+
+/// Reading the i3bar JSON protocol, for generators like i3status and i3blocks.
+pub mod i3bar;
+/// Parsing the lemonbar dialect of markup, for scripts written for lemonbar.
+pub mod lemonbar;
+
+/// Split a line of input into the sections for each part of the bar on the <|> splitter.
+/// A splitter with a backslash in front of it is left in the section, the parser turns it into
+/// literal text. The sections borrow from the input so offsets into the line can be worked out.
+///
+/// # Arguments
+/// * input: -> the full line read from stdin.
+///
+/// # Output
+/// List of the sections in the order they appear in the line.
+pub fn split_sections(input: &str) -> Vec<&str> {
+    let mut sections = Vec::new();
+    let mut start = 0;
+    let mut idx = 0;
+    while idx < input.len() {
+        let rest = &input[idx..];
+        if rest.starts_with("\\<|>") {
+            idx += 4;
+        } else if rest.starts_with("<|>") {
+            sections.push(&input[start..idx]);
+            idx += 3;
+            start = idx;
+        } else {
+            idx += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    sections.push(&input[start..]);
+    sections
+}
+
+/// Parse a full line of our own markup, splitting it into the sections for each part of the bar.
+/// A section that starts with {@name} is meant for the region with that name, the rest are
+/// handed out to the regions in order. Diagnostics are moved so their offsets point into the full
+/// line instead of the section.
+///
+/// # Arguments
+/// * input:  -> the full line read from stdin.
+/// * limits: -> number of fonts and colours available for checking indexes.
+///
+/// # Output
+/// Every section in the line, in the order they appear.
+pub fn parse_line(input: &str, limits: &Limits) -> Vec<Section> {
+    split_sections(input)
+        .into_iter()
+        .map(|section| {
+            let (region, section) = match region_name(section) {
+                Some((name, rest)) => (Some(name.to_string()), rest),
+                None => (None, section),
+            };
+            let mut markup = parse(section, limits);
+            // The sections are slices of the input, so the distance between them is the byte
+            // offset.
+            let byte = section.as_ptr() as usize - input.as_ptr() as usize;
+            let chars = input[..byte].chars().count();
+            markup.diagnostics.iter_mut().for_each(|d| {
+                d.offset += chars;
+                d.byte += byte;
+            });
+            Section { region, markup }
+        })
+        .collect()
+}
+
+/// Split the {@name} off the front of a section, if it has one.
+///
+/// # Output
+/// The name and the rest of the section after the block.
+fn region_name(section: &str) -> Option<(&str, &str)> {
+    let rest = section.strip_prefix("{@")?;
+    let end = rest.find('}')?;
+    Some((&rest[..end], &rest[end + 1..])).filter(|(name, _)| !name.is_empty())
+}
+
+/// Split the {>name} off the front of a line, which sends the line only to the bar on the monitor
+/// with that name.
+///
+/// # Output
+/// The monitor name if there is one, and the rest of the line.
+pub fn monitor_target(line: &str) -> (Option<&str>, &str) {
+    line.strip_prefix("{>")
+        .and_then(|rest| {
+            let end = rest.find('}')?;
+            Some((&rest[..end], &rest[end + 1..]))
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .map_or((None, line), |(name, rest)| (Some(name), rest))
+}
+
+/// Utility function to turn a hex colour literal into a packed ARGB value.
+/// Accepts the same lengths as most X programs, RGB, RRGGBB, and AARRGGBB.
+///
+/// # Arguments
+/// * hex: -> hex digits of the colour, without the leading #.
+///
+/// # Output
+/// The colour as 0xAARRGGBB, or None if the literal is not valid.
+pub fn parse_hex_colour(hex: &str) -> Option<u32> {
+    let val = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        // Short form has each digit doubled up, so #F0A is #FF00AA.
+        3 => {
+            let (r, g, b) = ((val >> 8) & 0xF, (val >> 4) & 0xF, val & 0xF);
+            Some(0xFF00_0000 | (r * 0x11) << 16 | (g * 0x11) << 8 | (b * 0x11))
+        }
+        6 => Some(0xFF00_0000 | val),
+        8 => Some(val),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Which colour to draw with. Either an index into one of the vecs in the ColourPalette or a
+/// literal colour given in the input.
+pub enum Colour {
+    /// Index into the palette vec for the type of colour.
+    Index(usize),
+    /// Packed 0xAARRGGBB value.
+    Literal(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Everything about how a run of text should be drawn.
+pub struct Style {
+    /// Font face explicitly set with {f}, otherwise each char uses the first font with a glyph.
+    pub font: Option<usize>,
+    /// Colour of the text.
+    pub colour: Colour,
+    /// Colour of the background highlight, if there is one.
+    pub background: Option<Colour>,
+    /// Colour of the underline highlight, if there is one.
+    pub underline: Option<Colour>,
+    /// Colour of the overline highlight, if there is one.
+    pub overline: Option<Colour>,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            font: None,
+            colour: Colour::Index(0),
+            background: None,
+            underline: None,
+            overline: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Chunk of text that is all drawn with the same style.
+pub struct Run {
+    pub text: String,
+    pub style: Style,
+}
+
+/// Tag of the clickable area put around the output of a module, followed by its name. It is
+/// only read by parse_modules, so input from anywhere else can't send clicks to a module.
+pub const MODULE_TAG: char = '\0';
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// What a click on an Action does.
+pub enum Target {
+    /// Shell command to run.
+    Command(String),
+    /// Name of the module whose output the area is around, the click goes back to it.
+    Module(String),
+    /// Id of an i3bar block, the click is written to stdout for the generator.
+    Block(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Clickable area of the text. Start and end are char offsets into the displayed text, and always
+/// fall on the boundary of a Run.
+pub struct Action {
+    /// Mouse button that triggers it (1 = left, 2 = middle, 3 = right, ...), None for any.
+    pub button: Option<u32>,
+    /// What happens when the area is clicked.
+    pub target: Target,
+    /// First char of the area.
+    pub start: usize,
+    /// Up to but not including this char.
+    pub end: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Text that scrolls through a window of fixed width. Start and end are char offsets into the
+/// displayed text, and always fall on the boundary of a Run.
+pub struct Marquee {
+    /// Pixel width of the window the text scrolls through.
+    pub width: u32,
+    /// First char of the text.
+    pub start: usize,
+    /// Up to but not including this char.
+    pub end: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// How wide a span of text has to be at the least.
+pub enum Width {
+    /// Exact number of pixels.
+    Pixels(u32),
+    /// As wide as this text would be drawn with the default fonts.
+    Text(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Text that takes up at least a minimum width, padded out on whichever side its alignment leaves
+/// free. Start and end are char offsets into the displayed text, and always fall on the boundary
+/// of a Run. The text can be empty to leave a gap.
+pub struct MinWidth {
+    pub width: Width,
+    /// Where the text sits inside of the width.
+    pub align: Align,
+    /// First char of the text.
+    pub start: usize,
+    /// Up to but not including this char.
+    pub end: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The different things that can be wrong with a format block.
+pub enum DiagnosticKind {
+    /// A tag that needs an index didn't have one.
+    MissingIndex,
+    /// The index is past the end of the fonts or colours available.
+    IndexTooLarge,
+    /// A hex colour that isn't 3, 6, or 8 digits long.
+    InvalidColour,
+    /// A hex colour that runs into a tag that is also a hex digit, and could be read more than
+    /// one way.
+    AmbiguousColour,
+    /// Action block without the colon and command after the button.
+    MissingCommand,
+    /// The input ended before the format block was closed with a bracket.
+    UnterminatedBlock,
+    /// A letter in a format block that isn't any of the tags we know.
+    UnknownTag,
+    /// A marquee block inside of another one.
+    NestedMarquee,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Problem found while parsing the input.
+pub struct Diagnostic {
+    /// Char offset into the input where the problem is.
+    pub offset: usize,
+    /// Byte offset of the same spot, for anything that slices the input.
+    pub byte: usize,
+    /// The char at the offset, the tag for most problems or the opening bracket of a block that
+    /// is never closed.
+    pub tag: char,
+    /// What the problem is.
+    pub kind: DiagnosticKind,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self.kind {
+            DiagnosticKind::MissingIndex => "missing index",
+            DiagnosticKind::IndexTooLarge => "index is TOO LARGE",
+            DiagnosticKind::InvalidColour => "invalid colour literal",
+            DiagnosticKind::AmbiguousColour => {
+                "colour literal runs into the next tag, end it with ';'"
+            }
+            DiagnosticKind::MissingCommand => "missing ':' before the command",
+            DiagnosticKind::UnterminatedBlock => "format block is never closed",
+            DiagnosticKind::UnknownTag => "unknown tag",
+            DiagnosticKind::NestedMarquee => "marquee blocks can't be nested",
+        };
+        write!(
+            f,
+            "Invalid format block at char {} (byte {}) on '{}' -- {}.",
+            self.offset, self.byte, self.tag, msg
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// How many of each thing the bar has available, so we can check the indexes while parsing.
+pub struct Limits {
+    pub fonts: usize,
+    pub font_colours: usize,
+    pub background_colours: usize,
+    pub underline_colours: usize,
+    pub overline_colours: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// Result of parsing one section of input.
+pub struct Markup {
+    /// Text of the input split up into the runs that share a style.
+    pub runs: Vec<Run>,
+    /// Clickable areas, in the order they were closed.
+    pub actions: Vec<Action>,
+    /// Scrolling text, in order and never overlapping.
+    pub marquees: Vec<Marquee>,
+    /// Text padded out to a minimum width, in order and never overlapping.
+    pub min_widths: Vec<MinWidth>,
+    /// Anything that was wrong with the input. Bad blocks are skipped, the rest is still parsed.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// One section of a line of input, split on the <|> splitters.
+pub struct Section {
+    /// Name of the region given with {@name} at the start, otherwise the section goes to the
+    /// region in the same position.
+    pub region: Option<String>,
+    pub markup: Markup,
+}
+
+impl Markup {
+    /// All of the text without any formatting.
+    pub fn text(&self) -> String {
+        self.runs.iter().map(|r| r.text.as_str()).collect()
+    }
+
+    /// Drop any font or colour index that is past what the bar has now, for markup parsed before
+    /// the config was reloaded with fewer of them. Fonts and text colours go back to the default,
+    /// highlights are taken off.
+    ///
+    /// # Arguments
+    /// * limits: -> number of fonts and colours available now.
+    pub fn clamp(&mut self, limits: &Limits) {
+        let keep = |colour: Option<Colour>, len: usize| match colour {
+            Some(Colour::Index(idx)) if idx >= len => None,
+            colour => colour,
+        };
+        for style in self.runs.iter_mut().map(|r| &mut r.style) {
+            style.font = style.font.filter(|&f| f < limits.fonts);
+            style.colour =
+                keep(Some(style.colour), limits.font_colours).unwrap_or(Style::default().colour);
+            style.background = keep(style.background, limits.background_colours);
+            style.underline = keep(style.underline, limits.underline_colours);
+            style.overline = keep(style.overline, limits.overline_colours);
+        }
+    }
+}
+
+/// Parse a section of input into the styled runs of text it describes.
+///
+/// # Arguments
+/// * input:  -> one section of a line of input, see split_sections.
+/// * limits: -> number of fonts and colours available for checking indexes.
+///
+/// # Output
+/// The runs, actions, and any diagnostics for the input.
+pub fn parse(input: &str, limits: &Limits) -> Markup {
+    parse_with(input, limits, false)
+}
+
+/// Parse the templates of the modules once their output is filled in, the same as parse but with
+/// the areas around each module's output read too.
+///
+/// # Arguments
+/// * input:  -> filled in template, see Modules::update.
+/// * limits: -> number of fonts and colours available for checking indexes.
+pub fn parse_modules(input: &str, limits: &Limits) -> Markup {
+    parse_with(input, limits, true)
+}
+
+fn parse_with(input: &str, limits: &Limits, modules: bool) -> Markup {
+    let mut parser = Parser {
+        chars: Cursor::new(input),
+        limits,
+        modules,
+        style: Style::default(),
+        saved: Saved::default(),
+        out: Builder::default(),
+    };
+    parser.run();
+    let mut markup = parser.out.finish();
+    locate(input, &mut markup.diagnostics);
+    markup
+}
+
+/// Fill in the byte offset and tag of diagnostics, the parsers only keep track of char offsets.
+fn locate(input: &str, diagnostics: &mut [Diagnostic]) {
+    for d in diagnostics.iter_mut() {
+        let (byte, tag) = input
+            .char_indices()
+            .nth(d.offset)
+            .unwrap_or((input.len(), '\0'));
+        d.byte = byte;
+        d.tag = tag;
+    }
+}
+
+/// Chars of the input along with their offsets, plus the small readers that every dialect of
+/// markup needs.
+struct Cursor<'a> {
+    input: &'a str,
+    chars: Peekable<Enumerate<CharIndices<'a>>>,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Cursor<'a> {
+        Cursor {
+            input,
+            chars: input.char_indices().enumerate().peekable(),
+        }
+    }
+
+    fn next(&mut self) -> Option<(usize, char)> {
+        self.chars.next().map(|(offset, (_, ch))| (offset, ch))
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, (_, ch))| ch)
+    }
+
+    /// Everything that hasn't been read yet.
+    fn rest(&mut self) -> &'a str {
+        let input = self.input;
+        self.chars
+            .peek()
+            .map_or("", |&(_, (byte, _))| &input[byte..])
+    }
+
+    /// Read a run of digits as an index.
+    fn index(&mut self) -> Option<usize> {
+        let mut idx: Option<usize> = None;
+        while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+            self.chars.next();
+            idx = Some(
+                idx.unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(d as usize),
+            );
+        }
+        idx
+    }
+
+    /// Read a colour, either an index or a # and hex literal. A literal can be ended with a ';',
+    /// otherwise it only takes the digits that leave a tag after it, since some tag letters are
+    /// hex digits too.
+    ///
+    /// # Arguments
+    /// * len:  -> number of colours in the palette the index is for.
+    /// * tags: -> letters of the tags that can follow a literal in the same block.
+    fn colour(&mut self, len: usize, tags: &str) -> Result<Colour, DiagnosticKind> {
+        if self.peek() == Some('#') {
+            self.chars.next();
+            let rest = self.rest();
+            let digits = rest.bytes().take_while(u8::is_ascii_hexdigit).count();
+            let separated = rest[digits..].starts_with(';');
+            // A tag is followed by its index, a literal, or for actions the command.
+            let leaves_tag = |n: usize| {
+                let mut after = rest[n..].chars();
+                after.next().is_some_and(|c| tags.contains(c))
+                    && after
+                        .next()
+                        .is_some_and(|c| c.is_ascii_digit() || c == '#' || c == ':')
+            };
+            let lengths = [3, 6, 8]
+                .iter()
+                .copied()
+                .filter(|&n| n == digits || (n < digits && !separated && leaves_tag(n)))
+                .collect::<Vec<usize>>();
+            let (used, colour) = match lengths[..] {
+                [n] => (
+                    n,
+                    parse_hex_colour(&rest[..n])
+                        .map(Colour::Literal)
+                        .ok_or(DiagnosticKind::InvalidColour),
+                ),
+                [] => (digits, Err(DiagnosticKind::InvalidColour)),
+                _ => (digits, Err(DiagnosticKind::AmbiguousColour)),
+            };
+            for _ in 0..used {
+                self.chars.next();
+            }
+            if used == digits && separated {
+                self.chars.next();
+            }
+            return colour;
+        }
+        match self.index() {
+            Some(idx) if idx < len => Ok(Colour::Index(idx)),
+            Some(_) => Err(DiagnosticKind::IndexTooLarge),
+            None => Err(DiagnosticKind::MissingIndex),
+        }
+    }
+}
+
+#[derive(Default)]
+/// Collects the runs and actions of a Markup as the text is read.
+struct Builder {
+    /// Number of chars of text so far.
+    count: usize,
+    /// Set when the next char has to start a new run even if the style is the same.
+    new_run: bool,
+    /// Actions can be nested so we keep a stack of the open ones.
+    open_actions: Vec<(Option<u32>, Target, usize)>,
+    /// Width and start of the marquee that is open, there can only be one.
+    open_marquee: Option<(u32, usize)>,
+    /// Width, alignment, and start of the padded text that is open, there can only be one.
+    open_min_width: Option<(Width, Align, usize)>,
+    markup: Markup,
+}
+
+impl Builder {
+    fn diagnostic(&mut self, offset: usize, kind: DiagnosticKind) {
+        // The byte offset and tag are filled in once the parse is done.
+        self.markup.diagnostics.push(Diagnostic {
+            offset,
+            byte: 0,
+            tag: '\0',
+            kind,
+        });
+    }
+
+    /// Add a char of text, starting a new run if the style changed.
+    fn push_char(&mut self, ch: char, style: Style) {
+        match self.markup.runs.last_mut() {
+            Some(run) if !self.new_run && run.style == style => run.text.push(ch),
+            _ => self.markup.runs.push(Run {
+                text: ch.to_string(),
+                style,
+            }),
+        }
+        self.new_run = false;
+        self.count += 1;
+    }
+
+    /// Start a clickable area at the current char.
+    fn open_action(&mut self, button: Option<u32>, target: Target) {
+        self.open_actions.push((button, target, self.count));
+        self.new_run = true;
+    }
+
+    /// End the last clickable area that was opened, if there is one.
+    fn close_action(&mut self) {
+        if let Some((button, target, start)) = self.open_actions.pop() {
+            self.markup.actions.push(Action {
+                button,
+                target,
+                start,
+                end: self.count,
+            });
+            self.new_run = true;
+        }
+    }
+
+    /// Start scrolling text at the current char.
+    ///
+    /// # Output
+    /// False if there is already a marquee open.
+    fn open_marquee(&mut self, width: u32) -> bool {
+        if self.open_marquee.is_some() {
+            return false;
+        }
+        self.open_marquee = Some((width, self.count));
+        self.new_run = true;
+        true
+    }
+
+    /// End the scrolling text, if there is some.
+    fn close_marquee(&mut self) {
+        if let Some((width, start)) = self.open_marquee.take() {
+            self.markup.marquees.push(Marquee {
+                width,
+                start,
+                end: self.count,
+            });
+            self.new_run = true;
+        }
+    }
+
+    /// Start text that is padded out to a minimum width at the current char, closing any that
+    /// is still open.
+    fn open_min_width(&mut self, width: Width, align: Align) {
+        self.close_min_width();
+        self.open_min_width = Some((width, align, self.count));
+        self.new_run = true;
+    }
+
+    /// End the padded text, if there is some.
+    fn close_min_width(&mut self) {
+        if let Some((width, align, start)) = self.open_min_width.take() {
+            self.markup.min_widths.push(MinWidth {
+                width,
+                align,
+                start,
+                end: self.count,
+            });
+            self.new_run = true;
+        }
+    }
+
+    /// Done reading, any actions left open run to the end of the string.
+    fn finish(mut self) -> Markup {
+        while !self.open_actions.is_empty() {
+            self.close_action();
+        }
+        self.close_marquee();
+        self.close_min_width();
+        self.markup
+    }
+}
+
+#[derive(Default)]
+/// Values each attribute had before the blocks that are still open, so closing a block can put
+/// back whatever it replaced.
+struct Saved {
+    font: Vec<Option<usize>>,
+    colour: Vec<Colour>,
+    background: Vec<Option<Colour>>,
+    underline: Vec<Option<Colour>>,
+    overline: Vec<Option<Colour>>,
+}
+
+/// State for a single parse. Kept private, the parse function is the only way in.
+struct Parser<'a> {
+    chars: Cursor<'a>,
+    limits: &'a Limits,
+    /// Whether the areas around module output are read, see parse_modules.
+    modules: bool,
+    /// Style to use for the next char of text.
+    style: Style,
+    saved: Saved,
+    out: Builder,
+}
+
+impl Parser<'_> {
+    fn run(&mut self) {
+        while let Some((offset, ch)) = self.chars.next() {
+            // Doubled up brackets are the escape for a literal bracket, so we eat the second one
+            // and treat the first as text.
+            let escaped = (ch == '{' || ch == '}') && self.chars.peek() == Some(ch);
+            if escaped {
+                self.chars.next();
+            }
+            match ch {
+                '{' if !escaped => self.block(offset),
+                // An escaped splitter is left in by split_sections, drop the backslash.
+                '\\' if self.chars.rest().starts_with("<|>") => (),
+                _ => self.out.push_char(ch, self.style),
+            }
+        }
+    }
+
+    /// Parse a format block, the opening bracket has already been read.
+    fn block(&mut self, start: usize) {
+        let mut closing = false;
+        loop {
+            match self.chars.next() {
+                None => {
+                    return self
+                        .out
+                        .diagnostic(start, DiagnosticKind::UnterminatedBlock)
+                }
+                Some((_, '}')) => return,
+                Some((_, '/')) => closing = true,
+                Some((offset, tag)) if closing => self.close(offset, tag),
+                Some((offset, tag)) => match tag {
+                    'F' | 'B' | 'H' | 'O' => {
+                        let len = match tag {
+                            'F' => self.limits.font_colours,
+                            'B' => self.limits.background_colours,
+                            'H' => self.limits.underline_colours,
+                            _ => self.limits.overline_colours,
+                        };
+                        // Save the old value even if the new one is bad, so the closing block
+                        // that goes with this one doesn't pop the enclosing value.
+                        let style = &mut self.style;
+                        let saved = &mut self.saved;
+                        match tag {
+                            'F' => saved.colour.push(style.colour),
+                            'B' => saved.background.push(style.background),
+                            'H' => saved.underline.push(style.underline),
+                            _ => saved.overline.push(style.overline),
+                        }
+                        match (self.chars.colour(len, "ABFf"), tag) {
+                            (Ok(colour), 'F') => style.colour = colour,
+                            (Ok(colour), 'B') => style.background = Some(colour),
+                            (Ok(colour), 'H') => style.underline = Some(colour),
+                            (Ok(colour), _) => style.overline = Some(colour),
+                            (Err(kind), _) => self.out.diagnostic(offset, kind),
+                        }
+                    }
+                    'f' => {
+                        self.saved.font.push(self.style.font);
+                        match self.chars.index() {
+                            Some(idx) if idx < self.limits.fonts => self.style.font = Some(idx),
+                            Some(_) => self.out.diagnostic(offset, DiagnosticKind::IndexTooLarge),
+                            None => self.out.diagnostic(offset, DiagnosticKind::MissingIndex),
+                        }
+                    }
+                    // Actions take the rest of the block, so once we have the command we are done.
+                    'A' if self.action(offset) => return,
+                    'A' => (),
+                    MODULE_TAG if self.modules => return self.module(offset),
+                    'M' => match self.chars.index() {
+                        Some(width) => {
+                            if !self.out.open_marquee(width.min(u32::MAX as usize) as u32) {
+                                self.out.diagnostic(offset, DiagnosticKind::NestedMarquee);
+                            }
+                        }
+                        None => self.out.diagnostic(offset, DiagnosticKind::MissingIndex),
+                    },
+                    // Anything else is skipped so the rest of the block still works.
+                    _ => self.out.diagnostic(offset, DiagnosticKind::UnknownTag),
+                },
+            }
+        }
+    }
+
+    /// Handle a tag in a closing block, going back to the value from before the matching block.
+    /// Extra closing blocks go back to the defaults.
+    fn close(&mut self, offset: usize, tag: char) {
+        let style = &mut self.style;
+        let saved = &mut self.saved;
+        match tag {
+            'F' => style.colour = saved.colour.pop().unwrap_or(Colour::Index(0)),
+            'B' => style.background = saved.background.pop().flatten(),
+            'H' => style.underline = saved.underline.pop().flatten(),
+            'O' => style.overline = saved.overline.pop().flatten(),
+            'f' => style.font = saved.font.pop().flatten(),
+            'A' => self.out.close_action(),
+            'M' => self.out.close_marquee(),
+            _ => self.out.diagnostic(offset, DiagnosticKind::UnknownTag),
+        }
+    }
+
+    /// Read the button and command of an action.
+    ///
+    /// # Output
+    /// True if the closing bracket of the block was read along with the command.
+    fn action(&mut self, offset: usize) -> bool {
+        // The button defaults to left click.
+        let button = self.chars.index().unwrap_or(1) as u32;
+        if self.chars.peek() != Some(':') {
+            self.out.diagnostic(offset, DiagnosticKind::MissingCommand);
+            return false;
+        }
+        self.chars.next();
+        // Everything up to the closing bracket is the command to run, with }} used for a literal
+        // bracket inside the command.
+        let mut command = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '}')) if self.chars.peek() == Some('}') => {
+                    self.chars.next();
+                    command.push('}');
+                }
+                Some((_, '}')) => break,
+                Some((_, c)) => command.push(c),
+                None => {
+                    self.out
+                        .diagnostic(offset, DiagnosticKind::UnterminatedBlock);
+                    return true;
+                }
+            }
+        }
+        self.out.open_action(Some(button), Target::Command(command));
+        true
+    }
+
+    /// Read the name of the module an area is around, the rest of the block.
+    fn module(&mut self, offset: usize) {
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '}')) => break,
+                Some((_, c)) => name.push(c),
+                None => {
+                    return self
+                        .out
+                        .diagnostic(offset, DiagnosticKind::UnterminatedBlock)
+                }
+            }
+        }
+        self.out.open_action(None, Target::Module(name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        fonts: 2,
+        font_colours: 16,
+        background_colours: 3,
+        underline_colours: 1,
+        overline_colours: 2,
+    };
+
+    fn style() -> Style {
+        Style::default()
+    }
+
+    #[test]
+    fn clamp_to_smaller_limits() {
+        let mut markup = parse("{f1F9B2H#F00O1}a{/fFBHO}b", &LIMITS);
+        markup.clamp(&Limits {
+            fonts: 1,
+            font_colours: 4,
+            background_colours: 3,
+            underline_colours: 0,
+            overline_colours: 1,
+        });
+        let clamped = Style {
+            background: Some(Colour::Index(2)),
+            underline: Some(Colour::Literal(0xFFFF_0000)),
+            ..style()
+        };
+        assert_eq!(markup.runs, vec![run("a", clamped), run("b", style())]);
+    }
+
+    fn run(text: &str, style: Style) -> Run {
+        Run {
+            text: text.to_string(),
+            style,
+        }
+    }
+
+    fn kinds(m: &Markup) -> Vec<(usize, DiagnosticKind)> {
+        m.diagnostics.iter().map(|d| (d.offset, d.kind)).collect()
+    }
+
+    #[test]
+    fn monitor_targets() {
+        assert_eq!(monitor_target("{>eDP-1}a<|>b"), (Some("eDP-1"), "a<|>b"));
+        assert_eq!(monitor_target("{>}a"), (None, "{>}a"));
+        assert_eq!(monitor_target("a{>HDMI-0}"), (None, "a{>HDMI-0}"));
+    }
+
+    #[test]
+    fn plain_text_is_one_run() {
+        let m = parse("hello world", &LIMITS);
+        assert_eq!(m.runs, vec![run("hello world", style())]);
+        assert!(m.actions.is_empty());
+        assert!(m.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(parse("", &LIMITS), Markup::default());
+    }
+
+    #[test]
+    fn font_colour_block() {
+        let m = parse("a{F1}b{/F}c", &LIMITS);
+        let red = Style {
+            colour: Colour::Index(1),
+            ..style()
+        };
+        assert_eq!(
+            m.runs,
+            vec![run("a", style()), run("b", red), run("c", style())]
+        );
+    }
+
+    #[test]
+    fn every_attribute() {
+        let m = parse("{f1F2B0H0O1}x", &LIMITS);
+        assert_eq!(
+            m.runs,
+            vec![run(
+                "x",
+                Style {
+                    font: Some(1),
+                    colour: Colour::Index(2),
+                    background: Some(Colour::Index(0)),
+                    underline: Some(Colour::Index(0)),
+                    overline: Some(Colour::Index(1)),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn overline_and_underline_together() {
+        let m = parse("{O1}a{H0}b{/O}c{/H}", &LIMITS);
+        let lines: Vec<(Option<Colour>, Option<Colour>)> = m
+            .runs
+            .iter()
+            .map(|r| (r.style.overline, r.style.underline))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (Some(Colour::Index(1)), None),
+                (Some(Colour::Index(1)), Some(Colour::Index(0))),
+                (None, Some(Colour::Index(0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_digit_index() {
+        let m = parse("{F12}x", &LIMITS);
+        assert_eq!(m.runs[0].style.colour, Colour::Index(12));
+        assert!(m.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn overlapping_blocks() {
+        let m = parse("{F1}a{B1}b{/F}c{/B}d", &LIMITS);
+        let f = Colour::Index(1);
+        let b = Some(Colour::Index(1));
+        assert_eq!(
+            m.runs,
+            vec![
+                run(
+                    "a",
+                    Style {
+                        colour: f,
+                        ..style()
+                    }
+                ),
+                run(
+                    "b",
+                    Style {
+                        colour: f,
+                        background: b,
+                        ..style()
+                    }
+                ),
+                run(
+                    "c",
+                    Style {
+                        background: b,
+                        ..style()
+                    }
+                ),
+                run("d", style()),
+            ]
+        );
+    }
+
+    #[test]
+    fn closing_several_tags_at_once() {
+        let m = parse("{F1B1}a{/FB}b", &LIMITS);
+        assert_eq!(m.runs[1], run("b", style()));
+    }
+
+    #[test]
+    fn closing_restores_the_enclosing_value() {
+        let m = parse("{F1}a{F2}b{/F}c{/F}d", &LIMITS);
+        let colours: Vec<Colour> = m.runs.iter().map(|r| r.style.colour).collect();
+        assert_eq!(
+            colours,
+            vec![
+                Colour::Index(1),
+                Colour::Index(2),
+                Colour::Index(1),
+                Colour::Index(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_attribute_restores() {
+        let m = parse("{f0B1H0O1}a{f1B2O0H#fff}b{/fBHO}c", &LIMITS);
+        assert_eq!(m.runs[0].style, m.runs[2].style);
+        assert_eq!(m.runs[2].text, "c");
+    }
+
+    #[test]
+    fn bad_blocks_still_need_closing() {
+        let m = parse("{B1}a{B9}b{/B}c{/B}d", &LIMITS);
+        let bgs: Vec<Option<Colour>> = m.runs.iter().map(|r| r.style.background).collect();
+        assert_eq!(bgs, vec![Some(Colour::Index(1)), None]);
+        assert_eq!(m.text(), "abcd");
+    }
+
+    #[test]
+    fn extra_closing_blocks_reset_to_default() {
+        let m = parse("{F1}a{/F}{/F}b{/B}c", &LIMITS);
+        assert_eq!(m.runs[1], run("bc", style()));
+    }
+
+    #[test]
+    fn style_changes_without_text_leave_no_empty_runs() {
+        let m = parse("{F1}{/F}{B2}{/B}abc", &LIMITS);
+        assert_eq!(m.runs, vec![run("abc", style())]);
+    }
+
+    #[test]
+    fn hex_literals() {
+        let m = parse("{F#FF0000}a{B#80112233}b{H#0F0}c", &LIMITS);
+        assert_eq!(m.runs[0].style.colour, Colour::Literal(0xFFFF0000));
+        assert_eq!(
+            m.runs[1].style.background,
+            Some(Colour::Literal(0x80112233))
+        );
+        assert_eq!(m.runs[2].style.underline, Some(Colour::Literal(0xFF00FF00)));
+        assert!(m.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn full_length_literal_followed_by_tag() {
+        let m = parse("{F#FF000000B1}a", &LIMITS);
+        assert_eq!(m.runs[0].style.colour, Colour::Literal(0xFF000000));
+        assert_eq!(m.runs[0].style.background, Some(Colour::Index(1)));
+    }
+
+    #[test]
+    fn short_literal_followed_by_tag() {
+        let m = parse("{H#F00F1}a", &LIMITS);
+        assert_eq!(m.runs[0].style.underline, Some(Colour::Literal(0xFFFF0000)));
+        assert_eq!(m.runs[0].style.colour, Colour::Index(1));
+        assert!(m.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn ambiguous_literal() {
+        // Either red with background 1 or one AARRGGBB value, so neither is picked.
+        let m = parse("{F#FF0000B1}a", &LIMITS);
+        assert_eq!(kinds(&m), vec![(1, DiagnosticKind::AmbiguousColour)]);
+        assert_eq!(m.runs, vec![run("a", style())]);
+        // A ';' says where the literal ends.
+        let m = parse("{F#FF0000;B1}a{F#FF0000B1;}b", &LIMITS);
+        assert_eq!(m.runs[0].style.colour, Colour::Literal(0xFFFF0000));
+        assert_eq!(m.runs[0].style.background, Some(Colour::Index(1)));
+        assert_eq!(m.runs[1].style.colour, Colour::Literal(0xFF0000B1));
+        assert!(m.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn bad_hex_literal() {
+        let m = parse("{F#12345}a", &LIMITS);
+        assert_eq!(kinds(&m), vec![(1, DiagnosticKind::InvalidColour)]);
+        assert_eq!(m.runs, vec![run("a", style())]);
+    }
+
+    #[test]
+    fn index_too_large() {
+        let m = parse("{B3}a{H1}b{f2}c{F16}d", &LIMITS);
+        assert_eq!(
+            kinds(&m),
+            vec![
+                (1, DiagnosticKind::IndexTooLarge),
+                (6, DiagnosticKind::IndexTooLarge),
+                (11, DiagnosticKind::IndexTooLarge),
+                (16, DiagnosticKind::IndexTooLarge),
+            ]
+        );
+        // Bad blocks are skipped but the text is kept.
+        assert_eq!(m.runs, vec![run("abcd", style())]);
+    }
+
+    #[test]
+    fn missing_index() {
+        let m = parse("{F}a{f}b", &LIMITS);
+        assert_eq!(
+            kinds(&m),
+            vec![
+                (1, DiagnosticKind::MissingIndex),
+                (5, DiagnosticKind::MissingIndex),
+            ]
+        );
+        assert_eq!(m.text(), "ab");
+    }
+
+    #[test]
+    fn unterminated_block() {
+        let m = parse("ab{F1", &LIMITS);
+        assert_eq!(kinds(&m), vec![(2, DiagnosticKind::UnterminatedBlock)]);
+        assert_eq!(m.runs, vec![run("ab", style())]);
+    }
+
+    #[test]
+    fn block_left_open_runs_to_the_end() {
+        let m = parse("a{B0}bc", &LIMITS);
+        assert_eq!(m.runs[1].text, "bc");
+        assert_eq!(m.runs[1].style.background, Some(Colour::Index(0)));
+        assert!(m.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unknown_tags_are_skipped_and_reported() {
+        let m = parse("{ZF1}a{/Q}", &LIMITS);
+        assert_eq!(
+            m.runs,
+            vec![run(
+                "a",
+                Style {
+                    colour: Colour::Index(1),
+                    ..style()
+                }
+            )]
+        );
+        assert_eq!(
+            kinds(&m),
+            vec![
+                (1, DiagnosticKind::UnknownTag),
+                (8, DiagnosticKind::UnknownTag)
+            ]
+        );
+        assert_eq!(m.diagnostics[1].tag, 'Q');
+    }
+
+    #[test]
+    fn escaped_brackets() {
+        let m = parse("{{json}} {{{F1}x{/F}}}", &LIMITS);
+        assert_eq!(m.text(), "{json} {x}");
+        assert_eq!(m.runs[1].text, "x");
+        assert!(m.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn lone_closing_bracket_is_text() {
+        assert_eq!(parse("a}b", &LIMITS).text(), "a}b");
+    }
+
+    #[test]
+    fn action_block() {
+        let m = parse("a{A3:bspc desktop -f 1}b{/A}c", &LIMITS);
+        assert_eq!(
+            m.actions,
+            vec![Action {
+                button: Some(3),
+                target: Target::Command("bspc desktop -f 1".to_string()),
+                start: 1,
+                end: 2,
+            }]
+        );
+        // Action boundaries always split the runs.
+        assert_eq!(
+            m.runs,
+            vec![run("a", style()), run("b", style()), run("c", style())]
+        );
+    }
+
+    #[test]
+    fn action_defaults_to_left_click() {
+        let m = parse("{A:cmd}x", &LIMITS);
+        assert_eq!(m.actions[0].button, Some(1));
+        assert_eq!(m.actions[0].end, 1);
+    }
+
+    #[test]
+    fn nested_actions() {
+        let m = parse("{A1:one}a{A3:three}b{/A}c{/A}", &LIMITS);
+        assert_eq!(m.actions.len(), 2);
+        assert_eq!(m.actions[0].button, Some(3));
+        assert_eq!((m.actions[0].start, m.actions[0].end), (1, 2));
+        assert_eq!((m.actions[1].start, m.actions[1].end), (0, 3));
+    }
+
+    #[test]
+    fn action_command_with_brackets() {
+        let m = parse("{A1:echo '{}}'}x{/A}", &LIMITS);
+        assert_eq!(
+            m.actions[0].target,
+            Target::Command("echo '{}'".to_string())
+        );
+    }
+
+    #[test]
+    fn module_areas() {
+        let input = "{\0clock}12:00{/A}";
+        let m = parse_modules(input, &LIMITS);
+        assert_eq!(
+            m.actions,
+            vec![Action {
+                button: None,
+                target: Target::Module("clock".to_string()),
+                start: 0,
+                end: 5,
+            }]
+        );
+        // Anywhere else the tag is unknown.
+        let m = parse(input, &LIMITS);
+        assert!(m.actions.is_empty());
+        assert_eq!(kinds(&m)[0], (1, DiagnosticKind::UnknownTag));
+    }
+
+    #[test]
+    fn action_without_command() {
+        let m = parse("{A1}x", &LIMITS);
+        assert_eq!(kinds(&m), vec![(1, DiagnosticKind::MissingCommand)]);
+        assert!(m.actions.is_empty());
+    }
+
+    #[test]
+    fn unterminated_action() {
+        let m = parse("x{A1:echo", &LIMITS);
+        assert_eq!(kinds(&m), vec![(2, DiagnosticKind::UnterminatedBlock)]);
+        assert!(m.actions.is_empty());
+    }
+
+    #[test]
+    fn marquee_blocks() {
+        let m = parse("a{M120F1}bc{/M}d", &LIMITS);
+        assert_eq!(
+            m.marquees,
+            vec![Marquee {
+                width: 120,
+                start: 1,
+                end: 3,
+            }]
+        );
+        let texts: Vec<&str> = m.runs.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(texts, vec!["a", "bc", "d"]);
+    }
+
+    #[test]
+    fn marquee_problems() {
+        let m = parse("{M}a{M10}b{M20}c", &LIMITS);
+        assert_eq!(
+            kinds(&m),
+            vec![
+                (1, DiagnosticKind::MissingIndex),
+                (11, DiagnosticKind::NestedMarquee)
+            ]
+        );
+        // Left open it runs to the end.
+        assert_eq!(m.marquees[0].start, 1);
+        assert_eq!(m.marquees[0].end, 3);
+    }
+
+    #[test]
+    fn unicode_offsets_are_in_chars() {
+        let m = parse("éé{F99}", &LIMITS);
+        assert_eq!(kinds(&m), vec![(3, DiagnosticKind::IndexTooLarge)]);
+        assert_eq!(m.diagnostics[0].byte, 5);
+    }
+
+    #[test]
+    fn unterminated_block_points_at_the_bracket() {
+        let m = parse("ab{F1", &LIMITS);
+        assert_eq!(m.diagnostics[0].tag, '{');
+        assert_eq!(
+            m.diagnostics[0].to_string(),
+            "Invalid format block at char 2 (byte 2) on '{' -- format block is never closed."
+        );
+    }
+
+    #[test]
+    fn split_on_separators() {
+        assert_eq!(split_sections("a<|>b<|>c"), vec!["a", "b", "c"]);
+        assert_eq!(split_sections("a"), vec!["a"]);
+        assert_eq!(split_sections("<|>"), vec!["", ""]);
+    }
+
+    #[test]
+    fn split_keeps_escaped_separators() {
+        assert_eq!(split_sections("a\\<|>b<|>c"), vec!["a\\<|>b", "c"]);
+        assert_eq!(parse("a\\<|>b\\x", &LIMITS).text(), "a<|>b\\x");
+    }
+
+    #[test]
+    fn line_sections() {
+        let sections = parse_line("a<|>b<|>c<|>d", &LIMITS);
+        let texts: Vec<String> = sections.iter().map(|s| s.markup.text()).collect();
+        assert_eq!(texts, vec!["a", "b", "c", "d"]);
+        assert!(sections.iter().all(|s| s.region.is_none()));
+    }
+
+    #[test]
+    fn named_sections() {
+        let sections = parse_line("{@title}a{F1}b<|>c<|>{@}d<|>{@tray", &LIMITS);
+        let named: Vec<(Option<&str>, String)> = sections
+            .iter()
+            .map(|s| (s.region.as_deref(), s.markup.text()))
+            .collect();
+        assert_eq!(
+            named,
+            vec![
+                (Some("title"), "ab".to_string()),
+                (None, "c".to_string()),
+                (None, "d".to_string()),
+                (None, "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_diagnostics_point_into_the_line() {
+        let sections = parse_line("é<|>{F99}<|>{@x}{B9}", &LIMITS);
+        let d = sections[1].markup.diagnostics[0];
+        assert_eq!((d.offset, d.byte, d.tag), (5, 6, 'F'));
+        let d = sections[2].markup.diagnostics[0];
+        assert_eq!((d.offset, d.byte, d.tag), (17, 18, 'B'));
+    }
+
+    #[test]
+    fn hex_colour_lengths() {
+        assert_eq!(parse_hex_colour("fff"), Some(0xFFFFFFFF));
+        assert_eq!(parse_hex_colour("F0A"), Some(0xFFFF00AA));
+        assert_eq!(parse_hex_colour("123456"), Some(0xFF123456));
+        assert_eq!(parse_hex_colour("00123456"), Some(0x00123456));
+        assert_eq!(parse_hex_colour("1234"), None);
+        assert_eq!(parse_hex_colour(""), None);
+        assert_eq!(parse_hex_colour("xyz"), None);
+    }
+}
@@ -0,0 +1,333 @@
+// Finding the monitors to put the bar on, with Xinerama indexes or XRandR output names.
+// By: Curtis Jones <mail@curtisjones.ca>
+// Started on: October 18, 2026
+//
+
+use anyhow::Result;
+use std::ptr;
+use x11_dl::{xinerama, xlib, xrandr};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Where a monitor sits on the XDisplay.
+pub struct Monitor {
+    /// XRandR output name, or the index for Xinerama screens.
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Set for the XRandR primary monitor.
+    pub primary: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What to do with a window when its monitor goes away.
+pub enum Fallback {
+    /// Unmap it until the monitor comes back.
+    Hide,
+    /// Move it onto the primary monitor, unless there is a window there already.
+    Primary,
+}
+
+impl Fallback {
+    /// Read the fallback option from the config.
+    pub fn from_str(s: &str) -> Option<Fallback> {
+        match &s.trim().to_lowercase()[..] {
+            "hide" => Some(Fallback::Hide),
+            "primary" => Some(Fallback::Primary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Which monitors the config wants a bar on.
+pub enum MonitorSpec {
+    /// Nothing set, one bar across the full XDisplay.
+    Display,
+    /// A bar on every monitor.
+    All,
+    /// Xinerama indexes and XRandR output names, in the order the bars are made.
+    List(Vec<String>),
+}
+
+impl MonitorSpec {
+    /// Read the monitor option, `all` or a comma seperated list.
+    pub fn parse(s: &str) -> MonitorSpec {
+        let s = s.trim();
+        if s.is_empty() {
+            MonitorSpec::Display
+        } else if s.eq_ignore_ascii_case("all") {
+            MonitorSpec::All
+        } else {
+            MonitorSpec::List(
+                s.split(',')
+                    .map(|m| m.trim().to_string())
+                    .filter(|m| !m.is_empty())
+                    .collect(),
+            )
+        }
+    }
+
+    /// Whether a window on the monitor is still asked for, once its monitor was found. Ones
+    /// that aren't were taken out of the config, rather than unplugged.
+    ///
+    /// # Arguments
+    /// * name: -> name of the monitor the window is on, empty for the full XDisplay.
+    pub fn wants(&self, name: &str) -> bool {
+        match self {
+            MonitorSpec::Display => name.is_empty(),
+            MonitorSpec::All => true,
+            MonitorSpec::List(names) => names.iter().any(|n| n == name),
+        }
+    }
+}
+
+/// Pick out the monitors the config asked for. Integers are looked up in the Xinerama screens and
+/// anything else in the XRandR outputs, `all` takes every XRandR output or every Xinerama screen
+/// if XRandR has none.
+///
+/// # Arguments
+/// * spec:     -> monitors from the config.
+/// * xinerama: -> Xinerama screens, in index order.
+/// * randr:    -> XRandR outputs.
+/// * display:  -> the full XDisplay, used when nothing else is found.
+///
+/// # Output
+/// The monitors to put a bar on, never empty.
+pub fn select(
+    spec: &MonitorSpec,
+    xinerama: &[Monitor],
+    randr: &[Monitor],
+    display: &Monitor,
+) -> Vec<Monitor> {
+    let res = match spec {
+        MonitorSpec::Display => {
+            eprintln!("No monitor provided, using full XDisplay!");
+            Vec::new()
+        }
+        MonitorSpec::All if !randr.is_empty() => randr.to_vec(),
+        MonitorSpec::All => xinerama.to_vec(),
+        MonitorSpec::List(names) => names
+            .iter()
+            .filter_map(|name| match name.parse::<usize>() {
+                Ok(_) if xinerama.is_empty() => {
+                    eprintln!("Xinerama is not currently active -- using full XDisplay width.");
+                    None
+                }
+                // If the monitor set is not available, use first screen.
+                Ok(idx) => Some(xinerama.get(idx).unwrap_or_else(|| {
+                    eprintln!("Monitor index: {} is too large! Using first screen.", idx);
+                    &xinerama[0]
+                })),
+                Err(_) => {
+                    let found = randr.iter().find(|m| &m.name == name);
+                    if found.is_none() {
+                        eprintln!("Xrandr monitor -> {} <- not found!", name);
+                    }
+                    found
+                }
+            })
+            .cloned()
+            .collect(),
+    };
+    if res.is_empty() {
+        vec![display.clone()]
+    } else {
+        res
+    }
+}
+
+/// The monitor to fall back on, the primary one or else the first one found.
+///
+/// # Arguments
+/// * monitors: -> every monitor found, XRandR outputs first.
+pub fn primary(monitors: &[Monitor]) -> Option<&Monitor> {
+    monitors
+        .iter()
+        .find(|m| m.primary)
+        .or_else(|| monitors.first())
+}
+
+/// Get the Xinerama screens, named by their index.
+///
+/// # Output
+/// The screens in index order, empty if Xinerama isn't available or active.
+pub unsafe fn xinerama_screens(xlib: &xlib::Xlib) -> Vec<Monitor> {
+    let xin = match xinerama::Xlib::open() {
+        Ok(xin) => xin,
+        Err(e) => {
+            eprintln!("Could not connect to Xinerama lib.\n{}", e);
+            return Vec::new();
+        }
+    };
+    // Grab another copy of the XDisplay. Because the Xinerama methods change the pointer
+    // and causes the close to seg fault.
+    let dpy = (xlib.XOpenDisplay)(ptr::null());
+    if dpy.is_null() {
+        return Vec::new();
+    }
+    let mut res = Vec::new();
+    // Even if we have connected to the library that doesn't necessarily mean that Xinerama
+    // is active. So we make another check here.
+    // Old school c bool where 0 is false and anything else is true.
+    if (xin.XineramaIsActive)(dpy) != 0 {
+        // Temp var because the query strings funtion needs a pointer to a i32.
+        let mut num_scr = 0;
+        // Gets a dumb mutable pointer to an array of ScreenInfo objects for each screen.
+        let scrns = (xin.XineramaQueryScreens)(dpy, &mut num_scr);
+        if !scrns.is_null() {
+            res = (0..num_scr as usize)
+                .map(|i| {
+                    let scrn = *scrns.add(i);
+                    Monitor {
+                        name: i.to_string(),
+                        x: scrn.x_org as i32,
+                        y: scrn.y_org as i32,
+                        width: scrn.width as i32,
+                        height: scrn.height as i32,
+                        primary: false,
+                    }
+                })
+                .collect();
+            (xlib.XFree)(scrns as *mut _);
+        }
+    }
+    // Close out the temp display we opened.
+    (xlib.XCloseDisplay)(dpy);
+    res
+}
+
+/// Get the XRandR outputs of every active monitor, named like `xrandr` shows them.
+///
+/// # Output
+/// The outputs, empty if XRandR isn't available.
+pub unsafe fn randr_outputs(xlib: &xlib::Xlib) -> Result<Vec<Monitor>> {
+    let xrr = match xrandr::Xrandr::open() {
+        Ok(xrr) => xrr,
+        Err(_) => {
+            eprintln!("XRandr not available!");
+            return Ok(Vec::new());
+        }
+    };
+    // again we load a seperate pointer to the display, because otherwise we get segfaults.
+    let dpy = (xlib.XOpenDisplay)(ptr::null());
+    if dpy.is_null() {
+        return Ok(Vec::new());
+    }
+    let root = (xlib.XDefaultRootWindow)(dpy);
+    let resources = (xrr.XRRGetScreenResources)(dpy, root);
+    // doesn't matter what we set here, the GetMonitors function overrides with the real val
+    // before we read.
+    let mut num_mon: i32 = 0;
+    // Now we query the library for a list on monitors and it helpfully (kill me now) returns a
+    // pointer to the first monitor and a total count in the num_mon var.
+    let mons = (xrr.XRRGetMonitors)(dpy, root, xlib::True, &mut num_mon);
+    let mut res = Vec::new();
+    for i in 0..num_mon as usize {
+        let m = *mons.add(i);
+        // Basically every Xrandr Monitor has outputs. Unless you have multiple cords from pc to
+        // monitor you only have one output.
+        for j in 0..m.noutput as usize {
+            let info = (xrr.XRRGetOutputInfo)(dpy, resources, *m.outputs.add(j));
+            if info.is_null() {
+                continue;
+            }
+            // Inside the output object we have another object called CRTC, with the information
+            // an actual human or basic ass application like this may need.
+            let crtc = (xrr.XRRGetCrtcInfo)(dpy, resources, (*info).crtc);
+            if !crtc.is_null() {
+                // This library returns strings just like arrays, you get a pointer to the first
+                // char and a count.
+                let name: Vec<u8> = (0..(*info).nameLen as usize)
+                    .map(|k| *(*info).name.add(k) as u8)
+                    .collect();
+                res.push(Monitor {
+                    name: String::from_utf8(name)?,
+                    x: (*crtc).x,
+                    y: (*crtc).y,
+                    width: (*crtc).width as i32,
+                    height: (*crtc).height as i32,
+                    primary: m.primary != 0,
+                });
+                (xrr.XRRFreeCrtcInfo)(crtc);
+            }
+            (xrr.XRRFreeOutputInfo)(info);
+        }
+    }
+    (xrr.XRRFreeMonitors)(mons);
+    (xrr.XRRFreeScreenResources)(resources);
+    (xlib.XCloseDisplay)(dpy);
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mon(name: &str, x: i32) -> Monitor {
+        Monitor {
+            name: name.to_string(),
+            x,
+            y: 0,
+            width: 100,
+            height: 50,
+            primary: false,
+        }
+    }
+
+    #[test]
+    fn parse_specs() {
+        assert_eq!(MonitorSpec::parse(" "), MonitorSpec::Display);
+        assert_eq!(MonitorSpec::parse("ALL"), MonitorSpec::All);
+        assert_eq!(
+            MonitorSpec::parse("eDP-1, 1,"),
+            MonitorSpec::List(vec!["eDP-1".to_string(), "1".to_string()])
+        );
+    }
+
+    #[test]
+    fn wanted_monitors() {
+        assert!(MonitorSpec::parse("").wants(""));
+        assert!(!MonitorSpec::parse("").wants("eDP-1"));
+        assert!(MonitorSpec::parse("all").wants("HDMI-0"));
+        assert!(MonitorSpec::parse("eDP-1,1").wants("1"));
+        assert!(!MonitorSpec::parse("eDP-1,1").wants("HDMI-0"));
+    }
+
+    #[test]
+    fn select_monitors() {
+        let xin = [mon("0", 0), mon("1", 100)];
+        let randr = [mon("eDP-1", 0), mon("HDMI-0", 100)];
+        let display = mon("", 0);
+        let names = |spec: &str, xin: &[Monitor], randr: &[Monitor]| -> Vec<String> {
+            select(&MonitorSpec::parse(spec), xin, randr, &display)
+                .into_iter()
+                .map(|m| m.name)
+                .collect()
+        };
+        assert_eq!(names("all", &xin, &randr), vec!["eDP-1", "HDMI-0"]);
+        assert_eq!(names("all", &xin, &[]), vec!["0", "1"]);
+        assert_eq!(names("HDMI-0,1", &xin, &randr), vec!["HDMI-0", "1"]);
+        // Indexes that are too large get the first screen, unknown names are skipped.
+        assert_eq!(names("5,DP-3", &xin, &randr), vec!["0"]);
+        assert_eq!(names("DP-3", &xin, &randr), vec![""]);
+        assert_eq!(names("", &xin, &randr), vec![""]);
+    }
+
+    #[test]
+    fn primary_monitor() {
+        let mut mons = vec![mon("eDP-1", 0), mon("HDMI-0", 100)];
+        assert_eq!(primary(&mons).map(|m| m.x), Some(0));
+        mons[1].primary = true;
+        assert_eq!(primary(&mons).map(|m| m.x), Some(100));
+        assert_eq!(primary(&[]), None);
+    }
+
+    #[test]
+    fn fallbacks() {
+        assert_eq!(Fallback::from_str(" Primary"), Some(Fallback::Primary));
+        assert_eq!(Fallback::from_str("hide"), Some(Fallback::Hide));
+        assert_eq!(Fallback::from_str("move"), None);
+    }
+}
@@ -0,0 +1,332 @@
+//! Module that shows the charge of a battery, read from /sys/class/power_supply.
+
+use super::{interval_option, placeholders, Error, Module, ModuleOptions, Style, Thresholds};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Where the kernel puts power supplies.
+const SYSFS: &str = "/sys/class/power_supply";
+
+pub struct BatteryModule {
+    root: PathBuf,
+    // Battery to show, or None for the first one found.
+    battery: Option<String>,
+    format: String,
+    ramp: Vec<String>,
+    colours: Thresholds,
+    critical: f64,
+    critical_style: Style,
+    interval: Duration,
+    last: String,
+}
+
+/// What is read from the battery each update.
+#[derive(Debug, Clone, PartialEq)]
+struct Reading {
+    capacity: f64,
+    status: String,
+    // Hours until it is empty when discharging, or full when charging.
+    hours: Option<f64>,
+}
+
+/// Make a battery module from its options, see BatteryModule::new.
+pub fn build(options: &ModuleOptions) -> Result<Box<dyn Module>, Error> {
+    Ok(Box::new(BatteryModule::new(options)?))
+}
+
+impl BatteryModule {
+    /// Make a battery module from its options.
+    ///
+    /// # Options
+    /// * root: -> directory with the power supplies in it. Default /sys/class/power_supply.
+    /// * battery: -> name of the battery, ex. BAT0. Default is the first one found.
+    /// * format: -> has {capacity}, {status}, {time}, and {icon} in it. Default {capacity}%.
+    /// * ramp: -> icons split by commas, going from empty to full.
+    /// * colours: -> thresholds for the capacity, ex. 15:F1, 30:F3.
+    /// * critical: -> capacity at or below which it is critical when not charging. Default 5.
+    /// * critical_colours: -> palette indexes put around all of it when critical, ex. F0 B1.
+    ///   There is no default since no index is sure to be in the palette, so being critical only
+    ///   shows once these are set.
+    /// * interval: -> seconds between each update. Default 5.
+    pub fn new(options: &ModuleOptions) -> Result<BatteryModule, Error> {
+        let bad = |opt: &str, why| Error::BadOption(opt.to_string(), why);
+        let critical = match options.get("critical") {
+            Some(val) => val
+                .parse::<f64>()
+                .ok()
+                .filter(|c| (0.0..=100.0).contains(c))
+                .ok_or_else(|| bad("critical", "Needs to be a percent from 0 to 100."))?,
+            None => 5.0,
+        };
+        let critical_style = match options.get("critical_colours") {
+            Some(val) => Style::parse(val).ok_or_else(|| {
+                bad(
+                    "critical_colours",
+                    "Needs to be palette indexes like F0 B1.",
+                )
+            })?,
+            None => Style::default(),
+        };
+        Ok(BatteryModule {
+            root: PathBuf::from(options.get("root").map_or(SYSFS, |s| s.as_str())),
+            battery: options.get("battery").cloned(),
+            format: options
+                .get("format")
+                .cloned()
+                .unwrap_or_else(|| "{capacity}%".to_string()),
+            ramp: options.get("ramp").map_or(Vec::new(), |val| {
+                val.split(',').map(|s| s.trim().to_string()).collect()
+            }),
+            colours: Thresholds::option(options, "colours")?,
+            critical,
+            critical_style,
+            interval: interval_option(options, Duration::from_secs(5))?,
+            last: String::new(),
+        })
+    }
+
+    /// Directory of the battery to show.
+    fn battery_dir(&self) -> Option<PathBuf> {
+        match &self.battery {
+            Some(name) => Some(self.root.join(name)),
+            None => find_battery(&self.root),
+        }
+    }
+
+    /// Markup for a reading.
+    fn text(&self, reading: &Reading) -> String {
+        let icon = if self.ramp.is_empty() {
+            String::new()
+        } else {
+            let idx = (reading.capacity.max(0.0) as usize * self.ramp.len() / 100)
+                .min(self.ramp.len() - 1);
+            self.ramp[idx].clone()
+        };
+        let time = reading.hours.map_or(String::new(), |hours| {
+            let mins = (hours * 60.0).round() as u64;
+            format!("{}:{:02}", mins / 60, mins % 60)
+        });
+        let capacity = format!("{:.0}", reading.capacity);
+        let text = placeholders(
+            &self.format,
+            &[
+                ("capacity", capacity),
+                ("status", reading.status.clone()),
+                ("time", time),
+                ("icon", icon),
+            ],
+        );
+        // The critical colours take the place of the threshold ones, so they aren't hidden.
+        let critical = reading.capacity <= self.critical && reading.status != "Charging";
+        let style = if critical && self.critical_style != Style::default() {
+            Some(&self.critical_style)
+        } else {
+            self.colours.below(reading.capacity)
+        };
+        match style {
+            Some(style) => style.apply(&text),
+            None => text,
+        }
+    }
+}
+
+impl Module for BatteryModule {
+    fn update(&mut self) -> Option<String> {
+        // Nothing is shown while there isn't a battery, it may be plugged in later.
+        let text = self
+            .battery_dir()
+            .and_then(|dir| read_battery(&dir))
+            .map_or(String::new(), |reading| self.text(&reading));
+        if text == self.last {
+            return None;
+        }
+        self.last = text.clone();
+        Some(text)
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Read one file of a power supply, without the newline.
+fn read(dir: &Path, file: &str) -> Option<String> {
+    fs::read_to_string(dir.join(file))
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Read a number from one file of a power supply.
+fn read_num(dir: &Path, file: &str) -> Option<f64> {
+    read(dir, file).and_then(|s| s.parse::<f64>().ok())
+}
+
+/// Find the first battery, by name, among the power supplies.
+fn find_battery(root: &Path) -> Option<PathBuf> {
+    let mut dirs = fs::read_dir(root)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|dir| read(dir, "type").as_deref() == Some("Battery"))
+        .collect::<Vec<PathBuf>>();
+    dirs.sort();
+    dirs.into_iter().next()
+}
+
+/// Read the state of a battery.
+///
+/// # Arguments
+/// * dir: -> directory of the battery in sysfs.
+///
+/// # Output
+/// The reading, or None if it has no capacity to read.
+fn read_battery(dir: &Path) -> Option<Reading> {
+    let capacity = read_num(dir, "capacity")?;
+    let status = read(dir, "status").unwrap_or_else(|| "Unknown".to_string());
+    // Some batteries give energy in µWh and µW, others charge in µAh and µA. Either works the
+    // same for hours left.
+    let (now, full, rate) = match read_num(dir, "energy_now") {
+        Some(now) => (
+            Some(now),
+            read_num(dir, "energy_full"),
+            read_num(dir, "power_now"),
+        ),
+        None => (
+            read_num(dir, "charge_now"),
+            read_num(dir, "charge_full"),
+            read_num(dir, "current_now"),
+        ),
+    };
+    let hours = match (&status[..], now, rate) {
+        (_, _, Some(rate)) if rate <= 0.0 => None,
+        ("Discharging", Some(now), Some(rate)) => Some(now / rate),
+        ("Charging", Some(now), Some(rate)) => full.map(|full| (full - now).max(0.0) / rate),
+        _ => None,
+    };
+    Some(Reading {
+        capacity,
+        status,
+        hours,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{options, FakeRoot},
+        *,
+    };
+
+    /// Write the files of a power supply, with a value in each.
+    fn supply(sysfs: &FakeRoot, name: &str, files: &[(&str, &str)]) {
+        for (file, val) in files {
+            sysfs.write(&format!("{}/{}", name, file), &format!("{}\n", val));
+        }
+    }
+
+    fn module(root: &Path, list: &[(&str, &str)]) -> BatteryModule {
+        let mut options = options(list);
+        options.insert("root".to_string(), root.display().to_string());
+        BatteryModule::new(&options).unwrap()
+    }
+
+    #[test]
+    fn finds_the_battery() {
+        let sysfs = FakeRoot::new("battery-find");
+        supply(&sysfs, "AC", &[("type", "Mains"), ("online", "1")]);
+        supply(&sysfs, "BAT1", &[("type", "Battery"), ("capacity", "20")]);
+        supply(&sysfs, "BAT0", &[("type", "Battery"), ("capacity", "80")]);
+        assert_eq!(find_battery(&sysfs.0), Some(sysfs.0.join("BAT0")));
+
+        let mut battery = module(&sysfs.0, &[]);
+        assert_eq!(battery.update(), Some("80%".to_string()));
+        assert_eq!(battery.update(), None);
+        let mut battery = module(&sysfs.0, &[("battery", "BAT1")]);
+        assert_eq!(battery.update(), Some("20%".to_string()));
+        let mut battery = module(&sysfs.0, &[("battery", "BAT9")]);
+        assert_eq!(battery.update(), None);
+    }
+
+    #[test]
+    fn time_remaining() {
+        let sysfs = FakeRoot::new("battery-time");
+        supply(
+            &sysfs,
+            "BAT0",
+            &[
+                ("capacity", "50"),
+                ("status", "Discharging"),
+                ("energy_now", "25000000"),
+                ("energy_full", "50000000"),
+                ("power_now", "10000000"),
+            ],
+        );
+        supply(
+            &sysfs,
+            "BAT1",
+            &[
+                ("capacity", "50"),
+                ("status", "Charging"),
+                ("charge_now", "2000000"),
+                ("charge_full", "4000000"),
+                ("current_now", "1500000"),
+            ],
+        );
+        supply(
+            &sysfs,
+            "BAT2",
+            &[
+                ("capacity", "100"),
+                ("status", "Full"),
+                ("energy_now", "50000000"),
+                ("power_now", "0"),
+            ],
+        );
+        let format = [("format", "{status} {time}")];
+        let text = |name| {
+            module(&sysfs.0, &[format[0], ("battery", name)])
+                .update()
+                .unwrap()
+        };
+        assert_eq!(text("BAT0"), "Discharging 2:30");
+        assert_eq!(text("BAT1"), "Charging 1:20");
+        assert_eq!(text("BAT2"), "Full ");
+    }
+
+    #[test]
+    fn ramps_and_colours() {
+        let battery = module(
+            Path::new("/nowhere"),
+            &[
+                ("format", "{icon} {capacity}"),
+                ("ramp", "E, L, H, F"),
+                ("colours", "15:F1, 40:F3"),
+                ("critical", "5"),
+                ("critical_colours", "F0 B1"),
+            ],
+        );
+        let reading = |capacity: f64, status: &str| Reading {
+            capacity,
+            status: status.to_string(),
+            hours: None,
+        };
+        assert_eq!(battery.text(&reading(100.0, "Full")), "F 100");
+        assert_eq!(battery.text(&reading(60.0, "Discharging")), "H 60");
+        assert_eq!(battery.text(&reading(30.0, "Discharging")), "{F3}L 30{/F}");
+        assert_eq!(battery.text(&reading(10.0, "Discharging")), "{F1}E 10{/F}");
+        assert_eq!(
+            battery.text(&reading(4.0, "Discharging")),
+            "{F0}{B1}E 4{/FB}"
+        );
+        assert_eq!(battery.text(&reading(4.0, "Charging")), "{F1}E 4{/F}");
+    }
+
+    #[test]
+    fn bad_options() {
+        assert!(BatteryModule::new(&options(&[("critical", "101")])).is_err());
+        assert!(BatteryModule::new(&options(&[("critical_colours", "red")])).is_err());
+        assert!(BatteryModule::new(&options(&[("colours", "15")])).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+//! Module that shows the time, formatted by strftime, without forking date every second.
+
+use super::{tzfile::Zone, Error, Module, ModuleOptions};
+use std::{
+    env,
+    ffi::CString,
+    mem::MaybeUninit,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Where the tzdata is when neither the zoneinfo option nor TZDIR is set.
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+// Not in the libc crate for every target, but always in the C library.
+extern "C" {
+    fn strftime(
+        s: *mut libc::c_char,
+        max: libc::size_t,
+        format: *const libc::c_char,
+        tm: *const libc::tm,
+    ) -> libc::size_t;
+}
+
+pub struct ClockModule {
+    // Formats a click cycles through.
+    formats: Vec<CString>,
+    current: usize,
+    // Zone the main time is shown in, None for local time.
+    zone: Option<Zone>,
+    // Extra zones shown after it.
+    extra: Vec<Zone>,
+    separator: String,
+    last: String,
+}
+
+/// Make a clock from its options, see ClockModule::new.
+pub fn build(options: &ModuleOptions) -> Result<Box<dyn Module>, Error> {
+    Ok(Box::new(ClockModule::new(options)?))
+}
+
+impl ClockModule {
+    /// Make a clock from its options.
+    ///
+    /// # Options
+    /// * format: -> strftime formats split by |, a click goes to the next one. Default %H:%M.
+    /// * timezone: -> tzdata zone to show instead of local time, ex. UTC.
+    /// * timezones: -> more zones split by commas, each shown after the main time.
+    /// * separator: -> put between the times of each zone. Default two spaces.
+    /// * zoneinfo: -> where the tzdata is. Default TZDIR, or /usr/share/zoneinfo.
+    pub fn new(options: &ModuleOptions) -> Result<ClockModule, Error> {
+        let bad = |opt: &str, why| Error::BadOption(opt.to_string(), why);
+        let dir = match options.get("zoneinfo") {
+            Some(dir) => PathBuf::from(dir),
+            None => env::var_os("TZDIR").map_or_else(|| PathBuf::from(ZONEINFO), PathBuf::from),
+        };
+        let formats = options
+            .get("format")
+            .map_or("%H:%M", |s| s.as_str())
+            .split('|')
+            .map(|f| CString::new(f.trim()))
+            .collect::<Result<Vec<CString>, _>>()
+            .map_err(|_| bad("format", "Formats can't have a null in them."))?;
+        let zone = match options.get("timezone") {
+            Some(name) => Some(
+                read_zone(&dir, name)
+                    .ok_or_else(|| bad("timezone", "No such zone in the tzdata."))?,
+            ),
+            None => None,
+        };
+        let extra = match options.get("timezones") {
+            Some(names) => names
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|name| read_zone(&dir, name))
+                .collect::<Option<Vec<Zone>>>()
+                .ok_or_else(|| bad("timezones", "No such zone in the tzdata."))?,
+            None => Vec::new(),
+        };
+        Ok(ClockModule {
+            formats,
+            current: 0,
+            zone,
+            extra,
+            separator: options
+                .get("separator")
+                .cloned()
+                .unwrap_or_else(|| "  ".to_string()),
+            last: String::new(),
+        })
+    }
+
+    /// The time in every zone with the current format.
+    fn text(&self, time: libc::time_t) -> String {
+        let format = &self.formats[self.current];
+        let mut text = format_time(format, time, self.zone.as_ref());
+        for zone in &self.extra {
+            text.push_str(&self.separator);
+            text.push_str(&format_time(format, time, Some(zone)));
+        }
+        text
+    }
+}
+
+impl Module for ClockModule {
+    fn update(&mut self) -> Option<String> {
+        let text = self.text(unix_time().as_secs() as libc::time_t);
+        if text == self.last {
+            return None;
+        }
+        self.last = text.clone();
+        Some(text)
+    }
+
+    /// Until the next second if the format shows seconds, or the next minute if it doesn't.
+    fn interval(&self) -> Duration {
+        let period = if shows_seconds(&self.formats[self.current].to_string_lossy()) {
+            1
+        } else {
+            60
+        };
+        until_next(unix_time(), period)
+    }
+
+    /// Left click goes to the next format, right click back to the last one.
+    fn click(&mut self, button: u32) -> bool {
+        let len = self.formats.len();
+        match button {
+            1 => self.current = (self.current + 1) % len,
+            3 => self.current = (self.current + len - 1) % len,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Time since the epoch, by the wall clock.
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// How long until the wall clock is on a whole number of periods.
+///
+/// # Arguments
+/// * now: -> time since the epoch.
+/// * period: -> seconds between each boundary.
+fn until_next(now: Duration, period: u64) -> Duration {
+    let into = Duration::new(now.as_secs() % period, now.subsec_nanos());
+    Duration::from_secs(period) - into
+}
+
+/// Check if a strftime format changes more than once a minute.
+fn shows_seconds(format: &str) -> bool {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        // Skip the flags, width, and E or O modifiers to get to the conversion.
+        let conv = chars
+            .by_ref()
+            .find(|c| !matches!(c, '_' | '-' | '0'..='9' | '^' | '#' | 'E' | 'O'));
+        if let Some('S') | Some('T') | Some('s') | Some('r') | Some('X') | Some('c') | Some('+') =
+            conv
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Find a zone in the tzdata.
+///
+/// # Arguments
+/// * dir:  -> where the tzdata is.
+/// * name: -> name of the zone, ex. Europe/London.
+fn find_zone(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = Path::new(name.trim());
+    // Only names inside the tzdata, not any file on the system.
+    if path.is_absolute() || path.components().any(|c| c.as_os_str() == "..") {
+        return None;
+    }
+    let path = dir.join(path);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Read a zone from the tzdata, see find_zone.
+fn read_zone(dir: &Path, name: &str) -> Option<Zone> {
+    find_zone(dir, name).and_then(|path| Zone::read(&path).ok())
+}
+
+/// Format a time with strftime.
+///
+/// # Arguments
+/// * format: -> strftime format.
+/// * time: -> seconds since the epoch.
+/// * zone: -> zone to use, or None for local time.
+// time_t isn't an i64 on every target.
+#[allow(clippy::unnecessary_cast)]
+fn format_time(format: &CString, time: libc::time_t, zone: Option<&Zone>) -> String {
+    if format.as_bytes().is_empty() {
+        return String::new();
+    }
+    let mut tm = MaybeUninit::<libc::tm>::zeroed();
+    let tm = match zone {
+        // Other zones are written as UTC moved by their offset, so TZ never has to change.
+        Some(zone) => {
+            let local = zone.local(time as i64);
+            let moved = (time as i64 + local.offset) as libc::time_t;
+            let mut tm = unsafe {
+                libc::gmtime_r(&moved, tm.as_mut_ptr());
+                tm.assume_init()
+            };
+            tm.tm_gmtoff = local.offset as libc::c_long;
+            tm.tm_isdst = local.dst as libc::c_int;
+            tm.tm_zone = local.abbr.as_ptr();
+            tm
+        }
+        None => unsafe {
+            libc::localtime_r(&time, tm.as_mut_ptr());
+            tm.assume_init()
+        },
+    };
+
+    // strftime gives back 0 if the buffer is too small, so keep making it bigger.
+    let mut buf = vec![0u8; 128];
+    loop {
+        let len = unsafe {
+            strftime(
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+                format.as_ptr(),
+                &tm,
+            )
+        };
+        if len > 0 || buf.len() >= 4096 {
+            buf.truncate(len);
+            return String::from_utf8_lossy(&buf).into_owned();
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::options, *};
+
+    /// Zones built from tests/zoneinfo.zi, so the tests don't need the tzdata.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/zoneinfo");
+
+    /// Options with the zones read from the fixtures.
+    fn fixture(list: &[(&str, &str)]) -> ModuleOptions {
+        let mut options = options(list);
+        options.insert("zoneinfo".to_string(), FIXTURES.to_string());
+        options
+    }
+
+    fn clock(list: &[(&str, &str)]) -> ClockModule {
+        ClockModule::new(&fixture(list)).unwrap()
+    }
+
+    #[test]
+    fn seconds_in_formats() {
+        assert!(!shows_seconds("%H:%M"));
+        assert!(shows_seconds("%H:%M:%S"));
+        assert!(shows_seconds("%T"));
+        assert!(shows_seconds("%-S"));
+        assert!(shows_seconds("%OS"));
+        assert!(!shows_seconds("%a %d %b 100%% %R"));
+        assert!(!shows_seconds("%%S"));
+    }
+
+    #[test]
+    fn wake_on_the_boundary() {
+        let now = Duration::new(120 + 45, 250_000_000);
+        assert_eq!(until_next(now, 60), Duration::new(14, 750_000_000));
+        assert_eq!(until_next(now, 1), Duration::new(0, 750_000_000));
+        assert_eq!(
+            until_next(Duration::from_secs(180), 60),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn zones() {
+        let dir = Path::new(FIXTURES);
+        assert!(find_zone(dir, "UTC").is_some());
+        assert!(find_zone(dir, "Nowhere/Special").is_none());
+        assert!(find_zone(dir, "../zoneinfo/UTC").is_none());
+        assert!(find_zone(dir, "/etc/passwd").is_none());
+        assert!(build(&fixture(&[("timezone", "Not/A_Zone")])).is_err());
+        assert!(build(&fixture(&[("timezones", "UTC, Not/A_Zone")])).is_err());
+    }
+
+    #[test]
+    fn formats_in_zones() {
+        // 2021-03-04 05:06:07 UTC
+        let time = 1_614_834_367;
+        let clock = clock(&[
+            ("format", "%H:%M:%S %Z | %Y-%m-%d"),
+            ("timezone", "UTC"),
+            ("timezones", "Asia/Tokyo"),
+        ]);
+        assert_eq!(clock.text(time), "05:06:07 UTC  14:06:07 JST");
+    }
+
+    #[test]
+    fn clicks_cycle_formats() {
+        let time = 1_614_834_367;
+        let mut clock = clock(&[("format", "%H:%M | %Y-%m-%d | %S"), ("timezone", "UTC")]);
+        assert_eq!(clock.text(time), "05:06");
+        assert!(clock.click(1));
+        assert_eq!(clock.text(time), "2021-03-04");
+        assert!(clock.click(1));
+        assert!(clock.click(1));
+        assert_eq!(clock.text(time), "05:06");
+        assert!(clock.click(3));
+        assert_eq!(clock.text(time), "07");
+        assert!(!clock.click(4));
+        assert_eq!(clock.text(time), "07");
+    }
+
+    #[test]
+    fn only_changes_are_sent() {
+        let mut clock = clock(&[("format", "%Y"), ("timezone", "UTC")]);
+        assert!(clock.update().is_some());
+        assert_eq!(clock.update(), None);
+        assert!(clock.interval() <= Duration::from_secs(60));
+    }
+}
@@ -4,9 +4,747 @@
 //!
 //! * Kill Me => Provide unibar process-ids that it will run a command against on close. The
 //! default is just the kill command.
+//! * Modules => Built-in sources of input, set with `module = name` in the config. Each one
+//! makes markup on its own schedule, which is put into a region through a template like
+//! `region.right.template = {battery}  {clock}`.
 //!
 //! # Planned
 //!
 //!
 
+pub mod battery;
+pub mod clock;
 pub mod kill_me;
+pub mod network;
+pub mod sysstats;
+mod tzfile;
+
+use crate::{
+    control::{parse_index, PaletteKind},
+    markup::MODULE_TAG,
+};
+use std::{
+    collections::HashMap,
+    mem,
+    os::unix::io::RawFd,
+    time::{Duration, Instant},
+};
+
+/// Options for one module, `module.name.option = val` in the config, by lowercase option.
+pub type ModuleOptions = HashMap<String, String>;
+
+/// Options from a list of pairs, for the tests of each module.
+#[cfg(test)]
+fn options(list: &[(&str, &str)]) -> ModuleOptions {
+    list.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// A directory of fake files for the tests of each module, removed when dropped.
+#[cfg(test)]
+struct FakeRoot(std::path::PathBuf);
+
+#[cfg(test)]
+impl FakeRoot {
+    /// An empty directory, the name has to be different for each test.
+    fn new(name: &str) -> FakeRoot {
+        let root = std::env::temp_dir().join(format!("unibar-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        FakeRoot(root)
+    }
+
+    /// Write a file under the root, along with the directories it is in.
+    fn write(&self, file: &str, contents: &str) {
+        let path = self.0.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+}
+
+#[cfg(test)]
+impl Drop for FakeRoot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("There is no kind of module called {0}")]
+    UnknownKind(String),
+    #[error("Invaild {0} option! {1}")]
+    BadOption(String, &'static str),
+}
+
+/// A built-in source of input, run by the bar itself instead of a script writing to stdin.
+pub trait Module {
+    /// Work out what to show.
+    ///
+    /// # Output
+    /// Unibar markup, or None if nothing changed since the last update.
+    fn update(&mut self) -> Option<String>;
+
+    /// How long to wait before the next update.
+    fn interval(&self) -> Duration;
+
+    /// For modules driven by events, an fd that is readable when there is something new. The
+    /// module is updated as soon as it is, as well as on its interval.
+    fn fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// A click on the module's output.
+    ///
+    /// # Arguments
+    /// * button: -> mouse button that was pressed.
+    ///
+    /// # Output
+    /// True if the module should be updated right away.
+    fn click(&mut self, _button: u32) -> bool {
+        false
+    }
+}
+
+/// Makes a module of one kind from its options.
+type Builder = fn(&ModuleOptions) -> Result<Box<dyn Module>, Error>;
+
+/// Every kind of module there is, by the name used in the config.
+const KINDS: &[(&str, Builder)] = &[
+    ("battery", battery::build),
+    ("clock", clock::build),
+    ("network", network::build),
+    ("sysstats", sysstats::build),
+];
+
+/// A module as it is set in the config, `module = name` or `module = name:kind` to have more
+/// than one of the same kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleConfig {
+    /// Name used for its options and in templates.
+    pub name: String,
+    pub kind: String,
+}
+
+impl ModuleConfig {
+    /// Parse the value of a module option.
+    ///
+    /// # Output
+    /// The module, or None if the name is empty or has anything but letters, digits, - and _.
+    pub fn parse(val: &str) -> Option<ModuleConfig> {
+        let mut split = val.splitn(2, ':');
+        let name = split.next().unwrap_or("").trim().to_lowercase();
+        let kind = split
+            .next()
+            .map_or(name.clone(), |k| k.trim().to_lowercase());
+        let valid = |s: &str| {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        if valid(&name) && valid(&kind) {
+            Some(ModuleConfig { name, kind })
+        } else {
+            None
+        }
+    }
+
+    /// Make the module.
+    ///
+    /// # Arguments
+    /// * options: -> its options from the config.
+    pub fn build(&self, options: &ModuleOptions) -> Result<Box<dyn Module>, Error> {
+        KINDS
+            .iter()
+            .find(|(kind, _)| *kind == self.kind)
+            .ok_or_else(|| Error::UnknownKind(self.kind.clone()))
+            .and_then(|(_, build)| build(options))
+    }
+}
+
+/// Read a number of seconds for an interval option.
+///
+/// # Arguments
+/// * options: -> options of the module.
+/// * default: -> interval to use if the option isn't set.
+pub fn interval_option(options: &ModuleOptions, default: Duration) -> Result<Duration, Error> {
+    match options.get("interval") {
+        None => Ok(default),
+        Some(val) => match val.parse::<f64>() {
+            Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+            _ => Err(Error::BadOption(
+                "interval".to_string(),
+                "Needs to be a number of seconds above 0.",
+            )),
+        },
+    }
+}
+
+/// Palette colours put around a module's output, written like they are in markup, ex. `F1 B0`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Style(Vec<(PaletteKind, usize)>);
+
+impl Style {
+    /// Parse a list of palette indexes split by spaces.
+    ///
+    /// # Output
+    /// The style, or None if any of them isn't a palette index.
+    pub fn parse(val: &str) -> Option<Style> {
+        val.split_whitespace()
+            .map(|s| parse_index(s).ok())
+            .collect::<Option<Vec<(PaletteKind, usize)>>>()
+            .map(Style)
+    }
+
+    /// Wrap text in the colours, ex. `{F1}{B0}text{/FB}`.
+    pub fn apply(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            return text.to_string();
+        }
+        let mut out = String::new();
+        let mut close = String::new();
+        for (kind, index) in &self.0 {
+            out.push_str(&format!("{{{}{}}}", kind.letter(), index));
+            close.push(kind.letter());
+        }
+        format!("{}{}{{/{}}}", out, text, close)
+    }
+}
+
+/// Styles picked by how high a value is, `limit:style` split by commas like `15:F1 B0, 30:F3`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Thresholds(Vec<(f64, Style)>);
+
+impl Thresholds {
+    /// Parse the value of a thresholds option.
+    ///
+    /// # Output
+    /// The thresholds sorted by limit, or None if any of them is bad.
+    pub fn parse(val: &str) -> Option<Thresholds> {
+        let mut list = val
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| {
+                let mut split = s.splitn(2, ':');
+                let limit = split.next()?.trim().parse::<f64>().ok()?;
+                let style = Style::parse(split.next()?)?;
+                if limit.is_finite() {
+                    Some((limit, style))
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<Vec<(f64, Style)>>>()?;
+        list.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        Some(Thresholds(list))
+    }
+
+    /// Read a thresholds option.
+    ///
+    /// # Arguments
+    /// * options: -> options of the module.
+    /// * option: -> name of the option.
+    pub fn option(options: &ModuleOptions, option: &str) -> Result<Thresholds, Error> {
+        options
+            .get(option)
+            .map_or(Ok(Thresholds::default()), |val| {
+                Thresholds::parse(val).ok_or_else(|| {
+                    Error::BadOption(
+                        option.to_string(),
+                        "Needs to be like 15:F1 B0, 30:F3 with palette indexes.",
+                    )
+                })
+            })
+    }
+
+    /// Style for a value where lower is worse, the lowest limit at or above it.
+    pub fn below(&self, value: f64) -> Option<&Style> {
+        self.0
+            .iter()
+            .find(|(limit, _)| value <= *limit)
+            .map(|t| &t.1)
+    }
+
+    /// Style for a value where higher is worse, the highest limit at or below it.
+    pub fn above(&self, value: f64) -> Option<&Style> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(limit, _)| value >= *limit)
+            .map(|t| &t.1)
+    }
+}
+
+/// Put values into a format, `{name}` for each one and `{{` for a brace. Names that aren't known
+/// are left as they are, so the format can have markup in it too.
+///
+/// # Arguments
+/// * format: -> format from the options.
+/// * values: -> name of each placeholder and what goes there.
+pub fn placeholders(format: &str, values: &[(&str, String)]) -> String {
+    substitute(format, |name| {
+        values
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, val)| val.clone())
+    })
+}
+
+/// Replace each `{name}` with what the lookup gives for it. Escaped braces are passed on as they
+/// are, so the markup parser still sees them escaped.
+///
+/// # Arguments
+/// * format: -> text with the names in it.
+/// * lookup: -> what goes in place of a name, None leaves it as it is.
+fn substitute(format: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut res = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some(idx) = rest.find('{') {
+        res.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        if rest.starts_with("{{") {
+            res.push_str("{{");
+            rest = &rest[2..];
+            continue;
+        }
+        let value = rest
+            .find('}')
+            .and_then(|end| lookup(&rest[1..end]).map(|val| (val, end)));
+        match value {
+            Some((val, end)) => {
+                res.push_str(&val);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                res.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// One running module and what it last showed.
+struct Entry {
+    name: String,
+    // Kind and options it was made with, to tell if a reload changed it.
+    kind: String,
+    options: ModuleOptions,
+    module: Box<dyn Module>,
+    next: Instant,
+    output: String,
+}
+
+/// The modules the bar is running, and the templates that put them into regions.
+#[derive(Default)]
+pub struct Modules {
+    entries: Vec<Entry>,
+    // Region name and its template, for every region fed by modules.
+    templates: Vec<(String, String)>,
+    // Set when the templates changed, so every region is filled on the next update.
+    refill: bool,
+}
+
+impl Modules {
+    /// Start up the modules, all of them due for an update right away.
+    ///
+    /// # Arguments
+    /// * modules:   -> name of each module and the module itself.
+    /// * templates: -> region name and template, for the regions the modules go in.
+    pub fn new(
+        modules: Vec<(String, Box<dyn Module>)>,
+        templates: Vec<(String, String)>,
+    ) -> Modules {
+        let now = Instant::now();
+        Modules {
+            entries: modules
+                .into_iter()
+                .map(|(name, module)| Entry {
+                    name,
+                    kind: String::new(),
+                    options: ModuleOptions::new(),
+                    module,
+                    next: now,
+                    output: String::new(),
+                })
+                .collect(),
+            templates: templates
+                .into_iter()
+                .map(|(region, template)| (region, template.replace(MODULE_TAG, "")))
+                .collect(),
+            refill: false,
+        }
+    }
+
+    /// Switch to the modules set in the config. A module with the same name, kind, and options
+    /// as one already running is kept as it is, so it doesn't lose what it has measured, the
+    /// rest are started up. Modules that can't be made are skipped with a warning. Regions with
+    /// a template take the modules named in it, and a region with the same name as a module and
+    /// no template takes just that module. Every region is filled again on the next update.
+    ///
+    /// # Arguments
+    /// * configs:   -> the modules, in the order they were set.
+    /// * options:   -> the options of each module, by lowercase name.
+    /// * templates: -> templates by lowercase region name.
+    /// * regions:   -> names of the regions of the bar.
+    pub fn reload(
+        &mut self,
+        configs: &[ModuleConfig],
+        options: &HashMap<String, ModuleOptions>,
+        templates: &HashMap<String, String>,
+        regions: &[&str],
+    ) {
+        let now = Instant::now();
+        let empty = ModuleOptions::new();
+        let mut old = mem::take(&mut self.entries);
+        self.entries = configs
+            .iter()
+            .filter_map(|conf| {
+                let options = options.get(&conf.name).unwrap_or(&empty);
+                let same = old.iter().position(|e| {
+                    e.name == conf.name && e.kind == conf.kind && e.options == *options
+                });
+                if let Some(idx) = same {
+                    return Some(old.swap_remove(idx));
+                }
+                match conf.build(options) {
+                    Ok(module) => Some(Entry {
+                        name: conf.name.clone(),
+                        kind: conf.kind.clone(),
+                        options: options.clone(),
+                        module,
+                        next: now,
+                        output: String::new(),
+                    }),
+                    Err(e) => {
+                        eprintln!("Module {} is not running: {}", conf.name, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        let entries = &self.entries;
+        self.templates = regions
+            .iter()
+            .filter_map(|&region| match templates.get(&region.to_lowercase()) {
+                Some(template) => Some((region.to_string(), template.replace(MODULE_TAG, ""))),
+                None => entries
+                    .iter()
+                    .find(|e| e.name.eq_ignore_ascii_case(region))
+                    .map(|e| (region.to_string(), format!("{{{}}}", e.name))),
+            })
+            .collect();
+        self.refill = true;
+    }
+
+    /// Whether a region is filled by modules, so input from stdin shouldn't clear it.
+    pub fn owns(&self, region: &str) -> bool {
+        self.templates.iter().any(|(r, _)| r == region)
+    }
+
+    /// How long until the next module is due, or the regions need to be filled after a reload.
+    ///
+    /// # Output
+    /// None when there are no modules.
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        if self.refill {
+            return Some(Duration::from_secs(0));
+        }
+        self.entries
+            .iter()
+            .map(|e| e.next.saturating_duration_since(now))
+            .min()
+    }
+
+    /// The fds of the modules driven by events, to wait on along with everything else.
+    pub fn fds(&self) -> Vec<RawFd> {
+        self.entries.iter().filter_map(|e| e.module.fd()).collect()
+    }
+
+    /// Make the module with this fd due now, after it was readable.
+    pub fn wake(&mut self, fd: RawFd, now: Instant) {
+        self.entries
+            .iter_mut()
+            .filter(|e| e.module.fd() == Some(fd))
+            .for_each(|e| e.next = now);
+    }
+
+    /// Update every module that is due.
+    ///
+    /// # Output
+    /// The new markup for each region that changed by region name, to parse with parse_modules.
+    pub fn update(&mut self, now: Instant) -> Vec<(String, String)> {
+        let mut changed = Vec::new();
+        for entry in self.entries.iter_mut().filter(|e| e.next <= now) {
+            // The tag is only meant to come from fill, so modules can't take each other's clicks.
+            if let Some(output) = entry.module.update().map(|o| o.replace(MODULE_TAG, "")) {
+                if output != entry.output {
+                    entry.output = output;
+                    changed.push(entry.name.clone());
+                }
+            }
+            entry.next = now + entry.module.interval();
+        }
+        let refill = mem::take(&mut self.refill);
+        self.templates
+            .iter()
+            .filter(|(_, template)| refill || changed.iter().any(|name| uses(template, name)))
+            .map(|(region, template)| (region.clone(), self.fill(template)))
+            .collect()
+    }
+
+    /// Pass a click on to a module, updating it right away if it asks.
+    ///
+    /// # Arguments
+    /// * name:   -> name of the module whose area was clicked.
+    /// * button: -> mouse button that was pressed.
+    ///
+    /// # Output
+    /// The regions that changed.
+    pub fn click(&mut self, name: &str, button: u32, now: Instant) -> Vec<(String, String)> {
+        let entry = match self.entries.iter_mut().find(|e| e.name == name) {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+        if !entry.module.click(button) {
+            return Vec::new();
+        }
+        entry.next = now;
+        self.update(now)
+    }
+
+    /// Put the output of each module named in a template in its place, inside a clickable area
+    /// that sends clicks back to it. Anything else in braces is left for the markup parser.
+    fn fill(&self, template: &str) -> String {
+        substitute(template, |name| {
+            self.entries
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| format!("{{{}{}}}{}{{/A}}", MODULE_TAG, e.name, e.output))
+        })
+    }
+}
+
+/// Whether a template has a place for the module.
+fn uses(template: &str, name: &str) -> bool {
+    template.contains(&format!("{{{}}}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    /// Counts up every update, and goes back to 0 on a click.
+    struct Counter {
+        count: Rc<Cell<u32>>,
+        interval: Duration,
+    }
+
+    impl Module for Counter {
+        fn update(&mut self) -> Option<String> {
+            self.count.set(self.count.get() + 1);
+            Some(self.count.get().to_string())
+        }
+
+        fn interval(&self) -> Duration {
+            self.interval
+        }
+
+        fn click(&mut self, button: u32) -> bool {
+            self.count.set(0);
+            button == 1
+        }
+    }
+
+    fn counter(secs: u64) -> (Rc<Cell<u32>>, Box<dyn Module>) {
+        let count = Rc::new(Cell::new(0));
+        let module = Counter {
+            count: count.clone(),
+            interval: Duration::from_secs(secs),
+        };
+        (count, Box::new(module))
+    }
+
+    fn area(name: &str, output: &str) -> String {
+        format!("{{{}{}}}{}{{/A}}", MODULE_TAG, name, output)
+    }
+
+    #[test]
+    fn parse_configs() {
+        let conf = |name: &str, kind: &str| {
+            Some(ModuleConfig {
+                name: name.to_string(),
+                kind: kind.to_string(),
+            })
+        };
+        assert_eq!(ModuleConfig::parse(" Clock "), conf("clock", "clock"));
+        assert_eq!(ModuleConfig::parse("utc:clock"), conf("utc", "clock"));
+        assert_eq!(ModuleConfig::parse("a b"), None);
+        assert_eq!(ModuleConfig::parse(":clock"), None);
+        assert_eq!(
+            ModuleConfig::parse("nope").map(|c| c.build(&ModuleOptions::new()).err()),
+            Some(Some(Error::UnknownKind("nope".to_string())))
+        );
+    }
+
+    #[test]
+    fn intervals() {
+        let mut options = ModuleOptions::new();
+        let default = Duration::from_secs(5);
+        assert_eq!(interval_option(&options, default), Ok(default));
+        options.insert("interval".to_string(), "0.5".to_string());
+        assert_eq!(
+            interval_option(&options, default),
+            Ok(Duration::from_millis(500))
+        );
+        options.insert("interval".to_string(), "-1".to_string());
+        assert!(interval_option(&options, default).is_err());
+    }
+
+    #[test]
+    fn styles_and_thresholds() {
+        let style = Style::parse("F1 b0").unwrap();
+        assert_eq!(style.apply("50%"), "{F1}{B0}50%{/FB}");
+        assert_eq!(Style::default().apply("50%"), "50%");
+        assert_eq!(Style::parse("F1 X2"), None);
+
+        let thresholds = Thresholds::parse("30:F3, 10:F1 B0").unwrap();
+        assert_eq!(thresholds.below(5.0), Style::parse("F1 B0").as_ref());
+        assert_eq!(thresholds.below(10.0), Style::parse("F1 B0").as_ref());
+        assert_eq!(thresholds.below(20.0), Style::parse("F3").as_ref());
+        assert_eq!(thresholds.below(50.0), None);
+        assert_eq!(thresholds.above(5.0), None);
+        assert_eq!(thresholds.above(20.0), Style::parse("F1 B0").as_ref());
+        assert_eq!(thresholds.above(30.0), Style::parse("F3").as_ref());
+        assert_eq!(Thresholds::parse("10:F1, F3"), None);
+        assert_eq!(Thresholds::parse(""), Some(Thresholds::default()));
+    }
+
+    #[test]
+    fn fill_placeholders() {
+        let values = [("cpu", "12".to_string()), ("mem", "40".to_string())];
+        assert_eq!(
+            placeholders("{F1}{cpu}%{/F} {{mem} {mem}% {load", &values),
+            "{F1}12%{/F} {{mem} 40% {load"
+        );
+    }
+
+    #[test]
+    fn updates_on_each_interval() {
+        let (fast, a) = counter(1);
+        let (slow, b) = counter(10);
+        let mut modules = Modules::new(
+            vec![("a".to_string(), a), ("b".to_string(), b)],
+            vec![("right".to_string(), "{a} {F1}{b}{/F}".to_string())],
+        );
+        let start = Instant::now();
+        assert_eq!(
+            modules.update(start),
+            vec![(
+                "right".to_string(),
+                format!("{} {{F1}}{}{{/F}}", area("a", "1"), area("b", "1"))
+            )]
+        );
+        assert_eq!(modules.timeout(start), Some(Duration::from_secs(1)));
+        modules.update(start + Duration::from_secs(1));
+        assert_eq!((fast.get(), slow.get()), (2, 1));
+        // Nothing is due yet.
+        assert!(modules
+            .update(start + Duration::from_millis(1500))
+            .is_empty());
+        assert_eq!((fast.get(), slow.get()), (2, 1));
+    }
+
+    #[test]
+    fn only_changed_regions() {
+        let (_, a) = counter(1);
+        let (_, b) = counter(10);
+        let mut modules = Modules::new(
+            vec![("a".to_string(), a), ("b".to_string(), b)],
+            vec![
+                ("left".to_string(), "{a}".to_string()),
+                ("right".to_string(), "{{b}} {b}".to_string()),
+            ],
+        );
+        let start = Instant::now();
+        let first = modules.update(start);
+        assert_eq!(first.len(), 2);
+        assert_eq!(
+            first[1],
+            ("right".to_string(), format!("{{{{b}}}} {}", area("b", "1")))
+        );
+        let changed = modules.update(start + Duration::from_secs(1));
+        assert_eq!(changed, vec![("left".to_string(), area("a", "2"))]);
+        assert!(modules.owns("right"));
+        assert!(!modules.owns("center"));
+    }
+
+    #[test]
+    fn clicks_go_to_the_module() {
+        let (count, a) = counter(60);
+        let mut modules = Modules::new(
+            vec![("a".to_string(), a)],
+            vec![("right".to_string(), "{a}".to_string())],
+        );
+        let start = Instant::now();
+        modules.update(start);
+        modules.update(start + Duration::from_secs(60));
+        assert!(modules.click("nope", 1, start).is_empty());
+        // The module asks to be updated for button 1 but not for 3.
+        assert!(modules.click("a", 3, start).is_empty());
+        assert_eq!(count.get(), 0);
+        assert_eq!(
+            modules.click("a", 1, start),
+            vec![("right".to_string(), area("a", "1"))]
+        );
+    }
+
+    #[test]
+    fn templates_from_config() {
+        let configs = [ModuleConfig::parse("nope").unwrap()];
+        let mut templates = HashMap::new();
+        templates.insert("right".to_string(), "x{nope}".to_string());
+        let mut modules = Modules::default();
+        modules.reload(&configs, &HashMap::new(), &templates, &["left", "Right"]);
+        // Modules that fail to build are skipped, but the region is still theirs.
+        assert!(modules.owns("Right"));
+        assert!(!modules.owns("left"));
+        let now = Instant::now();
+        assert_eq!(
+            modules.update(now),
+            vec![("Right".to_string(), "x{nope}".to_string())]
+        );
+        assert_eq!(modules.timeout(now), None);
+    }
+
+    #[test]
+    fn reload_keeps_unchanged_modules() {
+        let configs = [
+            ModuleConfig::parse("a:clock").unwrap(),
+            ModuleConfig::parse("b:clock").unwrap(),
+        ];
+        let mut opts = HashMap::new();
+        opts.insert("a".to_string(), options(&[("format", "one|two")]));
+        opts.insert("b".to_string(), options(&[("format", "one|two")]));
+        let mut templates = HashMap::new();
+        templates.insert("right".to_string(), "{a} {b}".to_string());
+        let mut modules = Modules::default();
+        modules.reload(&configs, &opts, &templates, &["right"]);
+        modules.update(Instant::now());
+        modules.click("a", 1, Instant::now());
+        modules.click("b", 1, Instant::now());
+        // Only b is started over, a stays on the format it was clicked to.
+        opts.insert("b".to_string(), options(&[("format", "one|two|three")]));
+        modules.reload(&configs, &opts, &templates, &["right"]);
+        assert_eq!(
+            modules.update(Instant::now()),
+            vec![(
+                "right".to_string(),
+                format!("{} {}", area("a", "two"), area("b", "one"))
+            )]
+        );
+    }
+}
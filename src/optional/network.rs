@@ -0,0 +1,427 @@
+//! Module that shows the state, address, and throughput of a network interface.
+
+use super::{interval_option, placeholders, Error, Module, ModuleOptions, Style};
+use std::{
+    ffi::CStr,
+    fs,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    ptr,
+    time::{Duration, Instant},
+};
+
+/// Where the kernel puts network interfaces.
+const SYSFS: &str = "/sys/class/net";
+/// Routing table used to find the interface of the default route.
+const ROUTES: &str = "/proc/net/route";
+
+pub struct NetworkModule {
+    root: PathBuf,
+    routes: PathBuf,
+    // Interface to show, or None to follow the default route.
+    interface: Option<String>,
+    format: String,
+    down_format: String,
+    down_style: Style,
+    interval: Duration,
+    // Interface, bytes received, bytes sent, and when, from the last update.
+    counters: Option<(String, u64, u64, Instant)>,
+    last: String,
+}
+
+/// What is known about an interface each update.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Reading {
+    interface: String,
+    up: bool,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    rx: u64,
+    tx: u64,
+}
+
+/// What getifaddrs knows about an interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Addresses {
+    // Flags like IFF_UP, None if the interface wasn't found.
+    flags: Option<u32>,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+}
+
+/// Make a network module from its options, see NetworkModule::new.
+pub fn build(options: &ModuleOptions) -> Result<Box<dyn Module>, Error> {
+    Ok(Box::new(NetworkModule::new(options)?))
+}
+
+impl NetworkModule {
+    /// Make a network module from its options.
+    ///
+    /// # Options
+    /// * interface: -> name of the interface, ex. wlan0. Default is the one of the default route.
+    /// * format: -> has {interface}, {state}, {ipv4}, {ipv6}, {rx}, and {tx} in it, the last
+    ///   two in bytes a second. Default {interface} {ipv4}.
+    /// * down_format: -> format used when the interface is down. Default {interface} down.
+    /// * down_colours: -> palette indexes put around it when down, ex. F1.
+    /// * root: -> directory with the interfaces in it. Default /sys/class/net.
+    /// * routes: -> routing table to find the default route in. Default /proc/net/route.
+    /// * interval: -> seconds between each update. Default 2.
+    pub fn new(options: &ModuleOptions) -> Result<NetworkModule, Error> {
+        let bad = |opt: &str, why| Error::BadOption(opt.to_string(), why);
+        let interface = match options.get("interface") {
+            Some(name) if name.is_empty() || name.contains('/') => {
+                return Err(bad("interface", "Needs to be the name of an interface."))
+            }
+            Some(name) => Some(name.clone()),
+            None => None,
+        };
+        let down_style = match options.get("down_colours") {
+            Some(val) => Style::parse(val)
+                .ok_or_else(|| bad("down_colours", "Needs to be palette indexes like F1."))?,
+            None => Style::default(),
+        };
+        Ok(NetworkModule {
+            root: PathBuf::from(options.get("root").map_or(SYSFS, |s| s.as_str())),
+            routes: PathBuf::from(options.get("routes").map_or(ROUTES, |s| s.as_str())),
+            interface,
+            format: options
+                .get("format")
+                .cloned()
+                .unwrap_or_else(|| "{interface} {ipv4}".to_string()),
+            down_format: options
+                .get("down_format")
+                .cloned()
+                .unwrap_or_else(|| "{interface} down".to_string()),
+            down_style,
+            interval: interval_option(options, Duration::from_secs(2))?,
+            counters: None,
+            last: String::new(),
+        })
+    }
+
+    /// Read the interface to show.
+    ///
+    /// # Output
+    /// The reading, or None if there is no interface to show.
+    fn read(&self) -> Option<Reading> {
+        let interface = match &self.interface {
+            Some(name) => name.clone(),
+            None => default_interface(&fs::read_to_string(&self.routes).ok()?)?,
+        };
+        let dir = self.root.join(&interface);
+        let found = addresses(&interface);
+        Some(Reading {
+            up: is_up(read(&dir, "operstate").as_deref(), found.flags),
+            rx: read(&dir, "statistics/rx_bytes")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            tx: read(&dir, "statistics/tx_bytes")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            interface,
+            ipv4: found.ipv4,
+            ipv6: found.ipv6,
+        })
+    }
+
+    /// Markup for a reading, with the rates since the last one.
+    ///
+    /// # Arguments
+    /// * reading: -> what was just read.
+    /// * now: -> when it was read.
+    fn text(&mut self, reading: &Reading, now: Instant) -> String {
+        // The rate starts over when the interface changes, or the counters go back.
+        let (rx, tx) = match &self.counters {
+            Some((interface, rx, tx, when))
+                if *interface == reading.interface && reading.rx >= *rx && reading.tx >= *tx =>
+            {
+                let secs = now.duration_since(*when).as_secs_f64();
+                if secs > 0.0 {
+                    (
+                        (reading.rx - rx) as f64 / secs,
+                        (reading.tx - tx) as f64 / secs,
+                    )
+                } else {
+                    (0.0, 0.0)
+                }
+            }
+            _ => (0.0, 0.0),
+        };
+        self.counters = Some((reading.interface.clone(), reading.rx, reading.tx, now));
+
+        let values = [
+            ("interface", reading.interface.clone()),
+            ("state", if reading.up { "up" } else { "down" }.to_string()),
+            (
+                "ipv4",
+                reading.ipv4.map_or(String::new(), |ip| ip.to_string()),
+            ),
+            (
+                "ipv6",
+                reading.ipv6.map_or(String::new(), |ip| ip.to_string()),
+            ),
+            ("rx", human_rate(rx)),
+            ("tx", human_rate(tx)),
+        ];
+        if reading.up {
+            placeholders(&self.format, &values)
+        } else {
+            self.down_style
+                .apply(&placeholders(&self.down_format, &values))
+        }
+    }
+}
+
+impl Module for NetworkModule {
+    fn update(&mut self) -> Option<String> {
+        let text = match self.read() {
+            Some(reading) => self.text(&reading, Instant::now()),
+            None => {
+                self.counters = None;
+                String::new()
+            }
+        };
+        if text == self.last {
+            return None;
+        }
+        self.last = text.clone();
+        Some(text)
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Read one file of an interface, without the newline.
+fn read(dir: &Path, file: &str) -> Option<String> {
+    fs::read_to_string(dir.join(file))
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Find the interface of the default route, the one with the lowest metric.
+///
+/// # Arguments
+/// * routes: -> contents of /proc/net/route.
+fn default_interface(routes: &str) -> Option<String> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            if fields.len() < 8 {
+                return None;
+            }
+            let flags = u32::from_str_radix(fields[3], 16).ok()?;
+            let metric = fields[6].parse::<u32>().ok()?;
+            // Up, to everywhere.
+            if fields[1] == "00000000" && fields[7] == "00000000" && flags & 1 != 0 {
+                Some((metric, fields[0].to_string()))
+            } else {
+                None
+            }
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, interface)| interface)
+}
+
+/// Whether an interface is up. Loopback, tun, and WireGuard interfaces have an operstate of
+/// unknown even while they work, so for those the flags from getifaddrs decide.
+///
+/// # Arguments
+/// * operstate: -> contents of its operstate file.
+/// * flags:     -> its flags like IFF_UP, None if getifaddrs didn't find it.
+fn is_up(operstate: Option<&str>, flags: Option<u32>) -> bool {
+    let running = (libc::IFF_UP | libc::IFF_RUNNING) as u32;
+    match operstate {
+        Some("up") => true,
+        Some("unknown") | None => flags.is_some_and(|f| f & running == running),
+        _ => false,
+    }
+}
+
+/// Get the flags and addresses of an interface. Link local IPv6 addresses are only used if
+/// there is nothing better.
+///
+/// # Arguments
+/// * interface: -> name of the interface.
+fn addresses(interface: &str) -> Addresses {
+    let mut found = Addresses::default();
+    let mut list = ptr::null_mut::<libc::ifaddrs>();
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return found;
+    }
+    let mut next = list;
+    while let Some(ifa) = unsafe { next.as_ref() } {
+        next = ifa.ifa_next;
+        if unsafe { CStr::from_ptr(ifa.ifa_name) }.to_bytes() != interface.as_bytes() {
+            continue;
+        }
+        found.flags = Some(ifa.ifa_flags);
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET if found.ipv4.is_none() => {
+                let addr = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                found.ipv4 = Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)));
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                let addr = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                let better = match found.ipv6 {
+                    Some(old) => link_local(&old) && !link_local(&addr),
+                    None => true,
+                };
+                if better {
+                    found.ipv6 = Some(addr);
+                }
+            }
+            _ => (),
+        }
+    }
+    unsafe { libc::freeifaddrs(list) };
+    found
+}
+
+/// Check if an IPv6 address is in fe80::/10.
+fn link_local(addr: &Ipv6Addr) -> bool {
+    addr.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// Write a number of bytes a second short, like 1.2M.
+fn human_rate(bytes: f64) -> String {
+    let mut size = bytes;
+    for unit in &["B", "K", "M"] {
+        if size < 1024.0 {
+            return format!("{:.1}{}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1}G", size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{options, FakeRoot},
+        *,
+    };
+
+    const ROUTES: &str =
+        "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+        wlan0\t00000000\t0102A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+        eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+        eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n";
+
+    fn module(list: &[(&str, &str)]) -> NetworkModule {
+        NetworkModule::new(&options(list)).unwrap()
+    }
+
+    #[test]
+    fn default_route() {
+        assert_eq!(default_interface(ROUTES), Some("eth0".to_string()));
+        let no_default = ROUTES
+            .lines()
+            .filter(|l| !l.contains("0003"))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        assert_eq!(default_interface(&no_default), None);
+        assert_eq!(default_interface(""), None);
+    }
+
+    #[test]
+    fn rates_and_states() {
+        let mut network = module(&[
+            ("format", "{interface} {state} {ipv4} {rx} {tx}"),
+            ("down_colours", "F1"),
+        ]);
+        let mut reading = Reading {
+            interface: "eth0".to_string(),
+            up: true,
+            ipv4: Some(Ipv4Addr::new(192, 168, 1, 20)),
+            ipv6: None,
+            rx: 1000,
+            tx: 1000,
+        };
+        let start = Instant::now();
+        assert_eq!(
+            network.text(&reading, start),
+            "eth0 up 192.168.1.20 0.0B 0.0B"
+        );
+        reading.rx += 4 * 2048;
+        reading.tx += 4 * 100;
+        assert_eq!(
+            network.text(&reading, start + Duration::from_secs(4)),
+            "eth0 up 192.168.1.20 2.0K 100.0B"
+        );
+        reading.up = false;
+        assert_eq!(
+            network.text(&reading, start + Duration::from_secs(6)),
+            "{F1}eth0 down{/F}"
+        );
+        // A new interface starts the rates over.
+        reading.interface = "wlan0".to_string();
+        reading.up = true;
+        reading.rx += 1 << 30;
+        assert_eq!(
+            network.text(&reading, start + Duration::from_secs(8)),
+            "wlan0 up 192.168.1.20 0.0B 0.0B"
+        );
+    }
+
+    #[test]
+    fn reads_sysfs() {
+        // A name no real interface has, so nothing from getifaddrs is mixed in.
+        let root = FakeRoot::new("network");
+        root.write("sys/fake0/operstate", "up\n");
+        root.write("sys/fake0/statistics/rx_bytes", "1234\n");
+        root.write("sys/fake0/statistics/tx_bytes", "5678\n");
+        root.write(
+            "route",
+            &ROUTES.replace("eth0", "fake0").replace("wlan0", "fake1"),
+        );
+        let path = |file: &str| root.0.join(file).to_str().unwrap().to_string();
+        let reading = module(&[("root", &path("sys")), ("routes", &path("route"))])
+            .read()
+            .unwrap();
+        assert_eq!(reading.interface, "fake0");
+        assert!(reading.up);
+        assert_eq!((reading.rx, reading.tx), (1234, 5678));
+        assert_eq!(reading.ipv4, None);
+        let missing = module(&[("root", &path("sys")), ("routes", &path("nothing"))]).read();
+        assert_eq!(missing, None);
+
+        // Without flags to back it up an unknown state is down.
+        root.write("sys/fake0/operstate", "unknown\n");
+        let reading = module(&[("root", &path("sys")), ("routes", &path("route"))]).read();
+        assert!(!reading.unwrap().up);
+    }
+
+    #[test]
+    fn up_states() {
+        let running = Some((libc::IFF_UP | libc::IFF_RUNNING) as u32);
+        let no_carrier = Some(libc::IFF_UP as u32);
+        assert!(is_up(Some("up"), None));
+        assert!(is_up(Some("unknown"), running));
+        assert!(!is_up(Some("unknown"), no_carrier));
+        assert!(!is_up(Some("unknown"), None));
+        assert!(is_up(None, running));
+        assert!(!is_up(Some("down"), running));
+        assert!(!is_up(Some("dormant"), running));
+    }
+
+    #[test]
+    fn addresses_of_missing_interfaces() {
+        assert_eq!(addresses("not-an-interface"), Addresses::default());
+        assert!(link_local(&"fe80::1".parse().unwrap()));
+        assert!(!link_local(&"2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn bad_options() {
+        assert!(NetworkModule::new(&options(&[("interface", "../eth0")])).is_err());
+        assert!(NetworkModule::new(&options(&[("down_colours", "red")])).is_err());
+    }
+}
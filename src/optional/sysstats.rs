@@ -0,0 +1,304 @@
+//! Module that shows CPU, memory, and load, read from /proc.
+
+use super::{interval_option, placeholders, Error, Module, ModuleOptions, Thresholds};
+use std::{fs, path::PathBuf, time::Duration};
+
+/// Where the kernel puts its stats.
+const PROCFS: &str = "/proc";
+
+pub struct SysStatsModule {
+    root: PathBuf,
+    format: String,
+    cpu_colours: Thresholds,
+    mem_colours: Thresholds,
+    load_colours: Thresholds,
+    interval: Duration,
+    // Total and idle time of all the CPUs and then each core, from the last update.
+    last_times: Vec<CpuTime>,
+    last: String,
+}
+
+/// Time a CPU has spent, in ticks since boot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct CpuTime {
+    total: u64,
+    idle: u64,
+}
+
+/// Memory from /proc/meminfo, in kB.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Memory {
+    total: u64,
+    available: u64,
+}
+
+/// Make a stats module from its options, see SysStatsModule::new.
+pub fn build(options: &ModuleOptions) -> Result<Box<dyn Module>, Error> {
+    Ok(Box::new(SysStatsModule::new(options)?))
+}
+
+impl SysStatsModule {
+    /// Make a stats module from its options.
+    ///
+    /// # Options
+    /// * root: -> where proc is mounted. Default /proc.
+    /// * format: -> has {cpu}, {cpu0} and so on for each core, {mem_used}, {mem_total},
+    ///   {mem_pct}, {load1}, {load5}, and {load15} in it. Default {cpu}%.
+    /// * cpu_colours: -> thresholds for CPU use in percent, ex. 50:F3, 90:F1.
+    /// * mem_colours: -> thresholds for memory used in percent.
+    /// * load_colours: -> thresholds for the load averages.
+    /// * interval: -> seconds between each update. Default 2.
+    pub fn new(options: &ModuleOptions) -> Result<SysStatsModule, Error> {
+        let root = PathBuf::from(options.get("root").map_or(PROCFS, |s| s.as_str()));
+        // A first sample to measure from, or the first update would be the average since boot.
+        let last_times = parse_stat(&fs::read_to_string(root.join("stat")).unwrap_or_default());
+        Ok(SysStatsModule {
+            root,
+            format: options
+                .get("format")
+                .cloned()
+                .unwrap_or_else(|| "{cpu}%".to_string()),
+            cpu_colours: Thresholds::option(options, "cpu_colours")?,
+            mem_colours: Thresholds::option(options, "mem_colours")?,
+            load_colours: Thresholds::option(options, "load_colours")?,
+            interval: interval_option(options, Duration::from_secs(2))?,
+            last_times,
+            last: String::new(),
+        })
+    }
+
+    /// Read a file under the proc root.
+    fn read(&self, file: &str) -> String {
+        fs::read_to_string(self.root.join(file)).unwrap_or_default()
+    }
+
+    /// Work out the markup from what was read.
+    fn text(&mut self, stat: &str, meminfo: &str, loadavg: &str) -> String {
+        let mut values = Vec::new();
+
+        let times = parse_stat(stat);
+        for (i, time) in times.iter().enumerate() {
+            // A core that wasn't there last time has nothing to measure from yet.
+            let usage = self
+                .last_times
+                .get(i)
+                .map_or(0.0, |last| usage(*last, *time));
+            let usage = format!("{:.0}", usage);
+            let usage = styled(&self.cpu_colours, usage);
+            let name = match i {
+                0 => "cpu".to_string(),
+                _ => format!("cpu{}", i - 1),
+            };
+            values.push((name, usage));
+        }
+        self.last_times = times;
+
+        let mem = parse_meminfo(meminfo);
+        let used = mem.total.saturating_sub(mem.available);
+        let pct = if mem.total > 0 {
+            used as f64 * 100.0 / mem.total as f64
+        } else {
+            0.0
+        };
+        let mem_style = self.mem_colours.above(pct);
+        let with_style = |text: String| match mem_style {
+            Some(style) => style.apply(&text),
+            None => text,
+        };
+        values.push(("mem_used".to_string(), with_style(human_size(used))));
+        values.push(("mem_total".to_string(), human_size(mem.total)));
+        values.push(("mem_pct".to_string(), with_style(format!("{:.0}", pct))));
+
+        let loads = loadavg.split_whitespace().take(3);
+        for (name, load) in ["load1", "load5", "load15"].iter().zip(loads) {
+            values.push((
+                name.to_string(),
+                styled(&self.load_colours, load.to_string()),
+            ));
+        }
+
+        let values = values
+            .iter()
+            .map(|(name, val)| (name.as_str(), val.clone()))
+            .collect::<Vec<(&str, String)>>();
+        placeholders(&self.format, &values)
+    }
+}
+
+impl Module for SysStatsModule {
+    fn update(&mut self) -> Option<String> {
+        let stat = self.read("stat");
+        let meminfo = self.read("meminfo");
+        let loadavg = self.read("loadavg");
+        let text = self.text(&stat, &meminfo, &loadavg);
+        if text == self.last {
+            return None;
+        }
+        self.last = text.clone();
+        Some(text)
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Put the colours for a number around it, where higher is worse.
+fn styled(thresholds: &Thresholds, text: String) -> String {
+    match text
+        .parse::<f64>()
+        .ok()
+        .and_then(|val| thresholds.above(val))
+    {
+        Some(style) => style.apply(&text),
+        None => text,
+    }
+}
+
+/// Percent of the time a CPU was busy between two readings.
+fn usage(last: CpuTime, now: CpuTime) -> f64 {
+    let total = now.total.saturating_sub(last.total);
+    let idle = now.idle.saturating_sub(last.idle);
+    if total == 0 {
+        0.0
+    } else {
+        (total - idle.min(total)) as f64 * 100.0 / total as f64
+    }
+}
+
+/// Read the CPU lines of /proc/stat, all of them first and then each core.
+fn parse_stat(stat: &str) -> Vec<CpuTime> {
+    stat.lines()
+        .filter(|line| line.starts_with("cpu"))
+        .map(|line| {
+            // user nice system idle iowait irq softirq steal, guest time is already in user.
+            let fields = line
+                .split_whitespace()
+                .skip(1)
+                .take(8)
+                .map(|f| f.parse::<u64>().unwrap_or(0))
+                .collect::<Vec<u64>>();
+            CpuTime {
+                total: fields.iter().sum(),
+                idle: fields.iter().skip(3).take(2).sum(),
+            }
+        })
+        .collect()
+}
+
+/// Read the total and available memory from /proc/meminfo.
+fn parse_meminfo(meminfo: &str) -> Memory {
+    let mut mem = Memory::default();
+    for line in meminfo.lines() {
+        let mut split = line.split_whitespace();
+        let field = match split.next() {
+            Some("MemTotal:") => &mut mem.total,
+            Some("MemAvailable:") => &mut mem.available,
+            _ => continue,
+        };
+        *field = split.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+    }
+    mem
+}
+
+/// Write a number of kB short, like 3.2G.
+fn human_size(kb: u64) -> String {
+    let mut size = kb as f64;
+    for unit in &["K", "M", "G"] {
+        if size < 1024.0 {
+            return format!("{:.1}{}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1}T", size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{options, FakeRoot},
+        *,
+    };
+
+    const MEMINFO: &str = "MemTotal:        8000000 kB\n\
+                           MemFree:         1000000 kB\n\
+                           MemAvailable:    2000000 kB\n";
+
+    /// A module with nothing to measure from, unless a root is given.
+    fn module(list: &[(&str, &str)]) -> SysStatsModule {
+        let mut options = options(list);
+        options
+            .entry("root".to_string())
+            .or_insert_with(|| "/nowhere".to_string());
+        SysStatsModule::new(&options).unwrap()
+    }
+
+    #[test]
+    fn cpu_between_ticks() {
+        let mut stats = module(&[("format", "{cpu} {cpu0} {cpu1}")]);
+        let first = "cpu  100 0 100 800 0 0 0 0 0 0\n\
+                     cpu0 50 0 50 400 0 0 0 0 0 0\n\
+                     cpu1 50 0 50 400 0 0 0 0 0 0\n\
+                     intr 12345\n";
+        // Nothing to measure from yet.
+        assert_eq!(stats.text(first, "", ""), "0 0 0");
+        // cpu0 was busy the whole time and cpu1 half of it.
+        let second = "cpu  250 0 100 850 0 0 0 0 0 0\n\
+                      cpu0 150 0 50 400 0 0 0 0 0 0\n\
+                      cpu1 100 0 50 450 0 0 0 0 0 0\n";
+        assert_eq!(stats.text(second, "", ""), "75 100 50");
+        // No time passed at all.
+        assert_eq!(stats.text(second, "", ""), "0 0 0");
+    }
+
+    #[test]
+    fn memory_and_load() {
+        let mut stats = module(&[(
+            "format",
+            "{mem_used}/{mem_total} {mem_pct}% {load1} {load5} {load15}",
+        )]);
+        assert_eq!(
+            stats.text("", MEMINFO, "0.52 1.10 0.98 2/345 6789\n"),
+            "5.7G/7.6G 75% 0.52 1.10 0.98"
+        );
+        assert_eq!(human_size(512), "512.0K");
+        assert_eq!(human_size(2048), "2.0M");
+    }
+
+    #[test]
+    fn colours() {
+        let mut stats = module(&[
+            ("format", "{cpu} {mem_pct} {load1}"),
+            ("cpu_colours", "50:F3, 90:F1"),
+            ("mem_colours", "70:F1 B0"),
+            ("load_colours", "4:F1"),
+        ]);
+        stats.text("cpu  0 0 0 0 0 0 0 0\n", "", "");
+        let stat = "cpu  95 0 0 5 0 0 0 0\n";
+        assert_eq!(
+            stats.text(stat, MEMINFO, "0.52 1.10 0.98"),
+            "{F1}95{/F} {F1}{B0}75{/FB} 0.52"
+        );
+        let stat = "cpu  155 0 0 45 0 0 0 0\n";
+        assert_eq!(
+            stats.text(stat, "", "4.00 1.10 0.98"),
+            "{F3}60{/F} 0 {F1}4.00{/F}"
+        );
+    }
+
+    #[test]
+    fn reads_the_proc_root() {
+        let proc = FakeRoot::new("sysstats");
+        proc.write("stat", "cpu  10 0 10 80 0 0 0 0\n");
+        proc.write("meminfo", MEMINFO);
+        proc.write("loadavg", "1.00 0.50 0.25 1/100 42\n");
+        let mut stats = module(&[
+            ("root", proc.0.to_str().unwrap()),
+            ("format", "{cpu}% {mem_pct}% {load1}"),
+        ]);
+        // Usage is measured from when the module was made.
+        proc.write("stat", "cpu  40 0 20 140 0 0 0 0\n");
+        assert_eq!(stats.update(), Some("40% 75% 1.00".to_string()));
+        assert_eq!(stats.update(), Some("0% 75% 1.00".to_string()));
+    }
+}
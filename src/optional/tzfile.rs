@@ -0,0 +1,471 @@
+//! Time zones read straight from the system tzdata, so the clock can show other zones without
+//! changing TZ for the whole process.
+
+use std::{ffi::CString, fs, io, path::Path};
+
+/// One kind of local time in a zone, like EST or EDT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalType {
+    /// Seconds east of UTC.
+    pub offset: i64,
+    pub dst: bool,
+    pub abbr: CString,
+}
+
+/// A day of the year in a POSIX TZ rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Date {
+    /// Jn, 1 to 365 with February 29th never counted.
+    Julian(i64),
+    /// n, 0 to 365 counting February 29th.
+    Day(i64),
+    /// Mm.w.d, day d of week w of month m. Week 5 is the last one.
+    Month(i64, i64, i64),
+}
+
+/// Daylight time from a POSIX TZ string, and when it starts and ends in local time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Daylight {
+    local: LocalType,
+    start: (Date, i64),
+    end: (Date, i64),
+}
+
+/// The rule at the end of a tzfile, for times after its last transition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    std: LocalType,
+    dst: Option<Daylight>,
+}
+
+/// A zone from the tzdata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    // Times of each change, and the index of the type used from then on.
+    transitions: Vec<(i64, usize)>,
+    types: Vec<LocalType>,
+    rule: Option<Rule>,
+}
+
+impl Zone {
+    /// Read a tzfile.
+    ///
+    /// # Arguments
+    /// * path: -> the file, ex. /usr/share/zoneinfo/Europe/London.
+    pub fn read(path: &Path) -> io::Result<Zone> {
+        Zone::parse(&fs::read(path)?).ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))
+    }
+
+    /// Parse the contents of a tzfile, version 1 or later.
+    fn parse(data: &[u8]) -> Option<Zone> {
+        let mut r = Reader(data);
+        let mut header = r.header()?;
+        let mut time_size = 4;
+        // Version 2 and up repeat everything with 64 bit times, which are the ones we want.
+        if header.version >= b'2' {
+            r.take(header.body_len(4))?;
+            header = r.header()?;
+            time_size = 8;
+        }
+        let times = (0..header.timecnt)
+            .map(|_| r.time(time_size))
+            .collect::<Option<Vec<i64>>>()?;
+        let indexes = r.take(header.timecnt)?.to_vec();
+        let raw_types = (0..header.typecnt)
+            .map(|_| Some((r.time(4)?, r.take(1)?[0] != 0, r.take(1)?[0] as usize)))
+            .collect::<Option<Vec<(i64, bool, usize)>>>()?;
+        let chars = r.take(header.charcnt)?;
+        r.take(header.leapcnt * (time_size + 4) + header.isstdcnt + header.isutcnt)?;
+
+        let types = raw_types
+            .into_iter()
+            .map(|(offset, dst, idx)| {
+                let abbr = chars.get(idx..)?;
+                let end = abbr.iter().position(|&c| c == 0)?;
+                Some(LocalType {
+                    offset,
+                    dst,
+                    abbr: CString::new(&abbr[..end]).ok()?,
+                })
+            })
+            .collect::<Option<Vec<LocalType>>>()?;
+        if types.is_empty() || indexes.iter().any(|&i| i as usize >= types.len()) {
+            return None;
+        }
+        let transitions = times
+            .into_iter()
+            .zip(indexes.into_iter().map(usize::from))
+            .collect();
+
+        // The footer is a POSIX TZ string between newlines, empty if there is no rule.
+        let rule = if time_size == 8 {
+            let footer = r.0.strip_prefix(b"\n")?;
+            let end = footer.iter().position(|&c| c == b'\n')?;
+            match &footer[..end] {
+                [] => None,
+                tz => Some(Rule::parse(std::str::from_utf8(tz).ok()?)?),
+            }
+        } else {
+            None
+        };
+        Some(Zone {
+            transitions,
+            types,
+            rule,
+        })
+    }
+
+    /// The local time in use at a moment.
+    ///
+    /// # Arguments
+    /// * time: -> seconds since the epoch.
+    pub fn local(&self, time: i64) -> &LocalType {
+        let idx = self.transitions.partition_point(|&(t, _)| t <= time);
+        match (idx, &self.rule) {
+            (idx, Some(rule)) if idx == self.transitions.len() => rule.local(time),
+            // Before the first change the first type is used.
+            (0, _) => &self.types[0],
+            (idx, _) => &self.types[self.transitions[idx - 1].1],
+        }
+    }
+}
+
+impl Rule {
+    /// Parse a POSIX TZ string, like EST5EDT,M3.2.0,M11.1.0.
+    fn parse(tz: &str) -> Option<Rule> {
+        let mut rest = tz;
+        let std = local_type(&mut rest, None, false)?;
+        if rest.is_empty() {
+            return Some(Rule { std, dst: None });
+        }
+        let local = local_type(&mut rest, Some(std.offset + 3600), true)?;
+        // Without dates the US rules are used, like glibc does.
+        let (start, end) = match rest.strip_prefix(',') {
+            Some(dates) => {
+                let mut split = dates.splitn(2, ',');
+                (boundary(split.next()?)?, boundary(split.next()?)?)
+            }
+            None if rest.is_empty() => {
+                ((Date::Month(3, 2, 0), 7200), (Date::Month(11, 1, 0), 7200))
+            }
+            None => return None,
+        };
+        Some(Rule {
+            std,
+            dst: Some(Daylight { local, start, end }),
+        })
+    }
+
+    /// The local time in use at a moment.
+    fn local(&self, time: i64) -> &LocalType {
+        let dst = match &self.dst {
+            Some(dst) => dst,
+            None => return &self.std,
+        };
+        let year = year_of((time + self.std.offset).div_euclid(86400));
+        // Daylight time starts by standard time and ends by daylight time.
+        let start = day_of(year, dst.start.0) * 86400 + dst.start.1 - self.std.offset;
+        let end = day_of(year, dst.end.0) * 86400 + dst.end.1 - dst.local.offset;
+        let in_dst = if start < end {
+            start <= time && time < end
+        } else {
+            // South of the equator it goes over the new year.
+            !(end <= time && time < start)
+        };
+        if in_dst {
+            &dst.local
+        } else {
+            &self.std
+        }
+    }
+}
+
+/// Header of one block of a tzfile.
+struct Header {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl Header {
+    /// Length of the block after the header.
+    fn body_len(&self, time_size: usize) -> usize {
+        self.timecnt * (time_size + 1)
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+/// Reads big endian numbers off the front of a tzfile.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (front, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(front)
+    }
+
+    /// A signed number of 4 or 8 bytes.
+    fn time(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.take(size)?;
+        Some(match size {
+            4 => i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64,
+            _ => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(bytes);
+                i64::from_be_bytes(buf)
+            }
+        })
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        Some(self.time(4)? as u32 as usize)
+    }
+
+    fn header(&mut self) -> Option<Header> {
+        if self.take(4)? != b"TZif" {
+            return None;
+        }
+        let version = self.take(1)?[0];
+        self.take(15)?;
+        Some(Header {
+            version,
+            isutcnt: self.count()?,
+            isstdcnt: self.count()?,
+            leapcnt: self.count()?,
+            timecnt: self.count()?,
+            typecnt: self.count()?,
+            charcnt: self.count()?,
+        })
+    }
+}
+
+/// Read a name and offset off the front of a TZ string, like EST5 or <+0545>-5:45.
+///
+/// # Arguments
+/// * rest:    -> the TZ string, moved past what was read.
+/// * default: -> offset to use if there isn't one, or None if it is needed.
+/// * dst:     -> whether it is daylight time.
+fn local_type(rest: &mut &str, default: Option<i64>, dst: bool) -> Option<LocalType> {
+    let (name, after) = match rest.strip_prefix('<') {
+        Some(quoted) => {
+            let end = quoted.find('>')?;
+            (&quoted[..end], &quoted[end + 1..])
+        }
+        None => {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        }
+    };
+    if name.len() < 3 {
+        return None;
+    }
+    *rest = after;
+    let offset = match (seconds(rest), default) {
+        // TZ strings count hours west of UTC.
+        (Some(secs), _) => -secs,
+        (None, Some(offset)) => offset,
+        (None, None) => return None,
+    };
+    Some(LocalType {
+        offset,
+        dst,
+        abbr: CString::new(name).ok()?,
+    })
+}
+
+/// Read a time like -5, 1:30, or 2:00:00 off the front of a TZ string, in seconds.
+fn seconds(rest: &mut &str) -> Option<i64> {
+    let end = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '+' || c == '-'))
+        .unwrap_or(rest.len());
+    let (time, after) = rest.split_at(end);
+    let (sign, time) = match time.strip_prefix('-') {
+        Some(time) => (-1, time),
+        None => (1, time.strip_prefix('+').unwrap_or(time)),
+    };
+    let mut secs = 0;
+    let mut scale = 3600;
+    for part in time.split(':') {
+        if scale == 0 || part.is_empty() {
+            return None;
+        }
+        secs += part.parse::<i64>().ok()? * scale;
+        scale /= 60;
+    }
+    *rest = after;
+    Some(sign * secs)
+}
+
+/// Read when daylight time starts or ends, like M3.2.0/2.
+fn boundary(s: &str) -> Option<(Date, i64)> {
+    let mut split = s.splitn(2, '/');
+    let date = split.next()?;
+    let time = match split.next() {
+        Some(mut time) => {
+            let secs = seconds(&mut time)?;
+            if !time.is_empty() {
+                return None;
+            }
+            secs
+        }
+        None => 7200,
+    };
+    let date = if let Some(day) = date.strip_prefix('J') {
+        Date::Julian(day.parse().ok().filter(|d| (1..=365).contains(d))?)
+    } else if let Some(month) = date.strip_prefix('M') {
+        let mut parts = month.splitn(3, '.').map(|p| p.parse::<i64>().ok());
+        let (m, w, d) = (parts.next()??, parts.next()??, parts.next()??);
+        if !(1..=12).contains(&m) || !(1..=5).contains(&w) || !(0..=6).contains(&d) {
+            return None;
+        }
+        Date::Month(m, w, d)
+    } else {
+        Date::Day(date.parse().ok().filter(|d| (0..=365).contains(d))?)
+    };
+    Some((date, time))
+}
+
+/// Days since the epoch of a date, from Howard Hinnant's days_from_civil.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Year a number of days since the epoch is in, from Howard Hinnant's civil_from_days.
+fn year_of(days: i64) -> i64 {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let month = (5 * doy + 2) / 153;
+    // Months are counted from March, so January and February are in the next year.
+    yoe + era * 400 + if month >= 10 { 1 } else { 0 }
+}
+
+/// Days since the epoch of a date from a TZ rule.
+fn day_of(year: i64, date: Date) -> i64 {
+    let jan1 = days_from_civil(year, 1, 1);
+    let leap = days_from_civil(year + 1, 1, 1) - jan1 == 366;
+    match date {
+        Date::Julian(n) if leap && n >= 60 => jan1 + n,
+        Date::Julian(n) => jan1 + n - 1,
+        Date::Day(n) => jan1 + n,
+        Date::Month(month, week, weekday) => {
+            let first = days_from_civil(year, month, 1);
+            let next = match month {
+                12 => days_from_civil(year + 1, 1, 1),
+                _ => days_from_civil(year, month + 1, 1),
+            };
+            // The epoch was on a Thursday.
+            let first_weekday = (first + 4).rem_euclid(7);
+            let mut day = first + (weekday - first_weekday).rem_euclid(7) + 7 * (week - 1);
+            while day >= next {
+                day -= 7;
+            }
+            day
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Zones built from tests/zoneinfo.zi, so the tests don't need the tzdata.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/zoneinfo");
+
+    fn at(year: i64, month: i64, day: i64, hour: i64) -> i64 {
+        days_from_civil(year, month, day) * 86400 + hour * 3600
+    }
+
+    fn abbr(local: &LocalType) -> &str {
+        local.abbr.to_str().unwrap()
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2021, 3, 4), 18690);
+        assert_eq!(year_of(18690), 2021);
+        assert_eq!(year_of(-1), 1969);
+        assert_eq!(year_of(days_from_civil(2024, 2, 29)), 2024);
+        assert_eq!(year_of(days_from_civil(2023, 12, 31)), 2023);
+        // The second Sunday in March 2021, and the last Sunday in October.
+        assert_eq!(
+            day_of(2021, Date::Month(3, 2, 0)),
+            days_from_civil(2021, 3, 14)
+        );
+        assert_eq!(
+            day_of(2021, Date::Month(10, 5, 0)),
+            days_from_civil(2021, 10, 31)
+        );
+        assert_eq!(day_of(2024, Date::Julian(60)), days_from_civil(2024, 3, 1));
+        assert_eq!(day_of(2024, Date::Day(59)), days_from_civil(2024, 2, 29));
+    }
+
+    #[test]
+    fn posix_rules() {
+        let rule = Rule::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        let start = at(2021, 3, 14, 7);
+        assert_eq!(abbr(rule.local(start - 1)), "EST");
+        assert_eq!(rule.local(start - 1).offset, -5 * 3600);
+        assert_eq!(abbr(rule.local(start)), "EDT");
+        assert_eq!(rule.local(start).offset, -4 * 3600);
+        let end = at(2021, 11, 7, 6);
+        assert_eq!(abbr(rule.local(end - 1)), "EDT");
+        assert_eq!(abbr(rule.local(end)), "EST");
+
+        // South of the equator, with a quoted name and times.
+        let rule = Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(abbr(rule.local(at(2021, 1, 1, 0))), "AEDT");
+        assert_eq!(rule.local(at(2021, 1, 1, 0)).offset, 11 * 3600);
+        assert_eq!(abbr(rule.local(at(2021, 7, 1, 0))), "AEST");
+
+        let rule = Rule::parse("<+0545>-5:45").unwrap();
+        assert_eq!(rule.local(0).offset, 5 * 3600 + 45 * 60);
+        assert_eq!(abbr(rule.local(0)), "+0545");
+
+        assert_eq!(Rule::parse("E5"), None);
+        assert_eq!(Rule::parse("EST"), None);
+        assert_eq!(Rule::parse("EST5EDT,M13.1.0,M11.1.0"), None);
+    }
+
+    #[test]
+    fn zone_files() {
+        let zone = |name: &str| Zone::read(&Path::new(FIXTURES).join(name)).unwrap();
+        let london = zone("Europe/London");
+        assert_eq!(abbr(london.local(at(2021, 1, 1, 0))), "GMT");
+        assert_eq!(london.local(at(2021, 7, 1, 0)).offset, 3600);
+        // Far past the transitions in the file, the rule takes over.
+        assert_eq!(abbr(london.local(at(2100, 7, 1, 0))), "BST");
+        assert_eq!(abbr(london.local(at(2100, 12, 1, 0))), "GMT");
+        let tokyo = zone("Asia/Tokyo");
+        assert_eq!(tokyo.local(at(2021, 3, 4, 5)).offset, 9 * 3600);
+        // Summer time only ever happened in the transitions.
+        assert_eq!(abbr(tokyo.local(at(1949, 7, 1, 0))), "JDT");
+        assert_eq!(tokyo.local(at(1949, 7, 1, 0)).offset, 10 * 3600);
+        assert_eq!(abbr(tokyo.local(at(1952, 7, 1, 0))), "JST");
+        let utc = zone("UTC");
+        assert_eq!((utc.local(0).offset, abbr(utc.local(0))), (0, "UTC"));
+        assert!(Zone::parse(b"TZif2 not really").is_none());
+    }
+}
@@ -0,0 +1,494 @@
+// Named regions of the bar, deciding which section of input goes where and where each is drawn.
+// By: Curtis Jones <mail@curtisjones.ca>
+// Started on: September 07, 2020
+//
+
+use super::markup::{Markup, Section};
+
+/// Which side of the bar a region sits on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    /// Read an alignment from the config, accepts either spelling of centre.
+    pub fn from_str(s: &str) -> Option<Align> {
+        match &s.trim().to_lowercase()[..] {
+            "left" => Some(Align::Left),
+            "center" | "centre" => Some(Align::Center),
+            "right" => Some(Align::Right),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with a region that doesn't have room for all of its text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Cut the text short and end it with an ellipsis.
+    Ellipsis,
+    /// Draw as much as fits and cut the rest off mid glyph.
+    Clip,
+}
+
+impl Overflow {
+    pub fn from_str(s: &str) -> Option<Overflow> {
+        match &s.trim().to_lowercase()[..] {
+            "ellipsis" => Some(Overflow::Ellipsis),
+            "clip" => Some(Overflow::Clip),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for how a region shares the bar, `region.name.option = val` in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionOptions {
+    /// When regions run into each other the one with the lower priority is cut short.
+    pub priority: i32,
+    /// Pixels the region can take up, even if there is room for more.
+    pub max_width: Option<u32>,
+    pub overflow: Overflow,
+}
+
+impl Default for RegionOptions {
+    fn default() -> RegionOptions {
+        RegionOptions {
+            priority: 0,
+            max_width: None,
+            overflow: Overflow::Ellipsis,
+        }
+    }
+}
+
+impl RegionOptions {
+    /// Set one of the options from the config.
+    ///
+    /// # Arguments
+    /// * opt: -> the last part of the key, after region.name.
+    /// * val: -> the value to set it to.
+    ///
+    /// # Output
+    /// False if the option doesn't exist or the value isn't valid for it.
+    pub fn set(&mut self, opt: &str, val: &str) -> bool {
+        match opt {
+            "priority" => val.parse().map(|p| self.priority = p).is_ok(),
+            "max_width" => val.parse().map(|w| self.max_width = Some(w)).is_ok(),
+            "overflow" => Overflow::from_str(val).map(|o| self.overflow = o).is_some(),
+            _ => false,
+        }
+    }
+}
+
+/// A region as it is set in the config, `region = name:align`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionConfig {
+    pub name: String,
+    pub align: Align,
+}
+
+impl RegionConfig {
+    /// Parse the value of a region option.
+    ///
+    /// # Arguments
+    /// * val: -> the name and alignment, seperated by a colon.
+    ///
+    /// # Output
+    /// The region, or None if the name is empty or the alignment isn't valid.
+    pub fn parse(val: &str) -> Option<RegionConfig> {
+        let mut split = val.splitn(2, ':');
+        let name = split.next()?.trim();
+        let align = Align::from_str(split.next()?)?;
+        // Names are used inside of {@name} blocks so they can't have a closing bracket.
+        if name.is_empty() || name.contains('}') {
+            return None;
+        }
+        Some(RegionConfig {
+            name: name.to_string(),
+            align,
+        })
+    }
+
+    /// The left, center, and right regions used when none are set in the config.
+    pub fn defaults() -> Vec<RegionConfig> {
+        [
+            ("left", Align::Left),
+            ("center", Align::Center),
+            ("right", Align::Right),
+        ]
+        .iter()
+        .map(|&(name, align)| RegionConfig {
+            name: name.to_string(),
+            align,
+        })
+        .collect()
+    }
+}
+
+/// Hand out the sections of a line to the regions.
+/// Sections with a name go to the region with that name, the rest go to the regions in order.
+/// A line made up of only named sections just updates those regions, anything else replaces the
+/// whole bar and clears the regions it doesn't mention.
+///
+/// # Arguments
+/// * regions:  -> the regions of the bar, in the order they were set.
+/// * sections: -> every section of the line, see markup::parse_line.
+///
+/// # Output
+/// New markup for each region, None for regions that should be left alone. Also the names used
+/// that don't match any region.
+pub fn assign(
+    regions: &[RegionConfig],
+    sections: Vec<Section>,
+) -> (Vec<Option<Markup>>, Vec<String>) {
+    let only_named = sections.iter().all(|s| s.region.is_some());
+    let mut res: Vec<Option<Markup>> = regions
+        .iter()
+        .map(|_| Some(Markup::default()).filter(|_| !only_named))
+        .collect();
+    let mut unknown = Vec::new();
+
+    let (named, positional): (Vec<Section>, Vec<Section>) =
+        sections.into_iter().partition(|s| s.region.is_some());
+    // The old left, center, right layout puts a line with one splitter on the left and right.
+    let slots: Vec<usize> = if positional.len() == 2 && *regions == RegionConfig::defaults()[..] {
+        vec![0, 2]
+    } else {
+        (0..regions.len()).collect()
+    };
+    // Anything past the last region is dropped.
+    slots
+        .into_iter()
+        .zip(positional)
+        .for_each(|(idx, section)| res[idx] = Some(section.markup));
+    for section in named {
+        let name = section.region.expect("partitioned on this");
+        match regions.iter().position(|r| r.name == name) {
+            Some(idx) => res[idx] = Some(section.markup),
+            None => unknown.push(name),
+        }
+    }
+    (res, unknown)
+}
+
+/// Hand out the left, center, and right sections from a line that sets its own alignment, like
+/// lemonbar markup does. Each goes to the first region on that side, the rest are cleared.
+///
+/// # Arguments
+/// * regions:  -> the regions of the bar, in the order they were set.
+/// * sections: -> markup for the left, center, and right of the bar.
+///
+/// # Output
+/// New markup for each region.
+pub fn assign_aligned(regions: &[RegionConfig], sections: [Markup; 3]) -> Vec<Option<Markup>> {
+    let mut res: Vec<Option<Markup>> = regions.iter().map(|_| Some(Markup::default())).collect();
+    for (side, markup) in [Align::Left, Align::Center, Align::Right]
+        .iter()
+        .zip(sections)
+    {
+        if let Some(idx) = regions.iter().position(|r| r.align == *side) {
+            res[idx] = Some(markup);
+        }
+    }
+    res
+}
+
+/// Work out where each region starts on the bar. Regions on the same side are placed one after
+/// the other in the order they were set, with the gap between any that aren't empty.
+///
+/// # Arguments
+/// * regions: -> alignment and pixel width of each region.
+/// * width:   -> pixel width of the bar.
+/// * gap:     -> pixels between regions on the same side.
+///
+/// # Output
+/// The x-value each region starts at, in the same order.
+pub fn place(regions: &[(Align, u32)], width: i32, gap: i32) -> Vec<i32> {
+    let mut res = vec![0; regions.len()];
+    for &side in &[Align::Left, Align::Center, Align::Right] {
+        let group: Vec<usize> = (0..regions.len())
+            .filter(|&i| regions[i].0 == side)
+            .collect();
+        let shown = group.iter().filter(|&&i| regions[i].1 > 0).count() as i32;
+        let total =
+            group.iter().map(|&i| regions[i].1 as i32).sum::<i32>() + gap * (shown - 1).max(0);
+        let mut x = match side {
+            Align::Left => 0,
+            Align::Center => (width - total) / 2,
+            Align::Right => width - total,
+        };
+        for i in group {
+            res[i] = x;
+            if regions[i].1 > 0 {
+                x += regions[i].1 as i32 + gap;
+            }
+        }
+    }
+    res
+}
+
+/// Work out where each region goes and how much room it gets, so that no two regions draw over
+/// each other. Whenever regions from different sides run into each other the loser is cut down
+/// until they don't. The loser is the one with the lower priority, then the centered one, then
+/// whichever region was set later.
+///
+/// # Arguments
+/// * regions: -> alignment, full pixel width, and options for each region.
+/// * width:   -> pixel width of the bar.
+/// * gap:     -> pixels between regions on the same side.
+///
+/// # Output
+/// The x-value each region starts at and the pixel width it can use, in the same order.
+pub fn fit(regions: &[(Align, u32, RegionOptions)], width: i32, gap: i32) -> Vec<(i32, u32)> {
+    let bar = width.max(0) as u32;
+    let mut widths: Vec<u32> = regions
+        .iter()
+        .map(|(_, w, opts)| opts.max_width.map_or(*w, |max| max.min(*w)).min(bar))
+        .collect();
+    // The loser of a clash between regions i and j, where i was set first.
+    let loser = |i: usize, j: usize| {
+        let (a, b) = (&regions[i], &regions[j]);
+        if a.2.priority != b.2.priority {
+            return if a.2.priority < b.2.priority { i } else { j };
+        }
+        match (a.0, b.0) {
+            (Align::Center, _) => i,
+            (_, Align::Center) => j,
+            _ => j,
+        }
+    };
+    loop {
+        let placed: Vec<(Align, u32)> = regions
+            .iter()
+            .zip(widths.iter())
+            .map(|(r, &w)| (r.0, w))
+            .collect();
+        let xs = place(&placed, width, gap);
+        let span = |i: usize| (xs[i], xs[i] + widths[i] as i32);
+        let clash = (0..regions.len())
+            .flat_map(|i| (i + 1..regions.len()).map(move |j| (i, j)))
+            .filter(|&(i, j)| regions[i].0 != regions[j].0 && widths[i] > 0 && widths[j] > 0)
+            .map(|(i, j)| (i, j, span(i).1.min(span(j).1) - span(i).0.max(span(j).0)))
+            .find(|&(_, _, overlap)| overlap > 0);
+        match clash {
+            None => return xs.into_iter().zip(widths).collect(),
+            Some((i, j, overlap)) => {
+                let idx = loser(i, j);
+                // Centered regions only move away from the clash by half of what they shrink.
+                let shrink = match regions[idx].0 {
+                    Align::Center => overlap * 2,
+                    _ => overlap,
+                };
+                widths[idx] = widths[idx].saturating_sub(shrink as u32);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regions(list: &[(&str, Align)]) -> Vec<RegionConfig> {
+        list.iter()
+            .map(|&(name, align)| RegionConfig {
+                name: name.to_string(),
+                align,
+            })
+            .collect()
+    }
+
+    fn section(region: Option<&str>, text: &str) -> Section {
+        Section {
+            region: region.map(str::to_string),
+            markup: crate::markup::parse(text, &Default::default()),
+        }
+    }
+
+    fn texts(res: &[Option<Markup>]) -> Vec<Option<String>> {
+        res.iter().map(|m| m.as_ref().map(Markup::text)).collect()
+    }
+
+    #[test]
+    fn parse_region_config() {
+        assert_eq!(
+            RegionConfig::parse(" tray : Right"),
+            Some(RegionConfig {
+                name: "tray".to_string(),
+                align: Align::Right,
+            })
+        );
+        assert_eq!(
+            RegionConfig::parse("title:centre").unwrap().align,
+            Align::Center
+        );
+        assert_eq!(RegionConfig::parse("tray"), None);
+        assert_eq!(RegionConfig::parse(":left"), None);
+        assert_eq!(RegionConfig::parse("a}b:left"), None);
+        assert_eq!(RegionConfig::parse("tray:up"), None);
+    }
+
+    #[test]
+    fn default_regions_keep_the_old_splitting() {
+        let defaults = RegionConfig::defaults();
+        let (res, _) = assign(&defaults, vec![section(None, "a")]);
+        assert_eq!(
+            texts(&res),
+            vec![Some("a".into()), Some("".into()), Some("".into())]
+        );
+        let (res, _) = assign(&defaults, vec![section(None, "a"), section(None, "b")]);
+        assert_eq!(
+            texts(&res),
+            vec![Some("a".into()), Some("".into()), Some("b".into())]
+        );
+        let sections = ["a", "b", "c", "d"]
+            .iter()
+            .map(|t| section(None, t))
+            .collect();
+        let (res, _) = assign(&defaults, sections);
+        assert_eq!(
+            texts(&res),
+            vec![Some("a".into()), Some("b".into()), Some("c".into())]
+        );
+    }
+
+    #[test]
+    fn sections_fill_regions_in_order() {
+        let list = regions(&[
+            ("desktops", Align::Left),
+            ("mode", Align::Left),
+            ("tray", Align::Right),
+        ]);
+        let (res, _) = assign(&list, vec![section(None, "a"), section(None, "b")]);
+        assert_eq!(
+            texts(&res),
+            vec![Some("a".into()), Some("b".into()), Some("".into())]
+        );
+    }
+
+    #[test]
+    fn named_sections_only_update_their_region() {
+        let list = regions(&[("desktops", Align::Left), ("tray", Align::Right)]);
+        let (res, unknown) = assign(
+            &list,
+            vec![section(Some("tray"), "t"), section(Some("nope"), "x")],
+        );
+        assert_eq!(texts(&res), vec![None, Some("t".into())]);
+        assert_eq!(unknown, vec!["nope".to_string()]);
+    }
+
+    #[test]
+    fn named_and_positional_sections_mix() {
+        let list = regions(&[("desktops", Align::Left), ("tray", Align::Right)]);
+        let (res, _) = assign(&list, vec![section(Some("tray"), "t"), section(None, "d")]);
+        assert_eq!(texts(&res), vec![Some("d".into()), Some("t".into())]);
+    }
+
+    #[test]
+    fn aligned_sections_go_to_the_first_region_on_each_side() {
+        let list = regions(&[
+            ("desktops", Align::Left),
+            ("mode", Align::Left),
+            ("tray", Align::Right),
+        ]);
+        let sections = [section(None, "l"), section(None, "c"), section(None, "r")];
+        let res = assign_aligned(&list, sections.map(|s| s.markup));
+        assert_eq!(
+            texts(&res),
+            vec![Some("l".into()), Some("".into()), Some("r".into())]
+        );
+    }
+
+    #[test]
+    fn place_each_side() {
+        let list = [
+            (Align::Left, 10),
+            (Align::Left, 0),
+            (Align::Left, 20),
+            (Align::Center, 30),
+            (Align::Right, 5),
+            (Align::Right, 15),
+        ];
+        assert_eq!(place(&list, 200, 4), vec![0, 14, 14, 85, 176, 185]);
+    }
+
+    #[test]
+    fn region_options() {
+        let mut opts = RegionOptions::default();
+        assert!(opts.set("priority", "-2"));
+        assert!(opts.set("max_width", "300"));
+        assert!(opts.set("overflow", "Clip"));
+        assert!(!opts.set("overflow", "wrap"));
+        assert!(!opts.set("colour", "1"));
+        assert_eq!(
+            opts,
+            RegionOptions {
+                priority: -2,
+                max_width: Some(300),
+                overflow: Overflow::Clip,
+            }
+        );
+    }
+
+    fn prio(priority: i32) -> RegionOptions {
+        RegionOptions {
+            priority,
+            ..RegionOptions::default()
+        }
+    }
+
+    #[test]
+    fn fit_leaves_room_alone() {
+        let list = [
+            (Align::Left, 10, prio(0)),
+            (Align::Center, 30, prio(0)),
+            (Align::Right, 15, prio(0)),
+        ];
+        assert_eq!(fit(&list, 100, 0), vec![(0, 10), (35, 30), (85, 15)]);
+    }
+
+    #[test]
+    fn fit_cuts_the_center_before_the_sides() {
+        let list = [
+            (Align::Left, 20, prio(0)),
+            (Align::Center, 80, prio(0)),
+            (Align::Right, 20, prio(0)),
+        ];
+        assert_eq!(fit(&list, 100, 0), vec![(0, 20), (20, 60), (80, 20)]);
+    }
+
+    #[test]
+    fn fit_follows_priority() {
+        let list = [
+            (Align::Left, 60, prio(0)),
+            (Align::Center, 0, prio(0)),
+            (Align::Right, 60, prio(1)),
+        ];
+        assert_eq!(fit(&list, 100, 0), vec![(0, 40), (50, 0), (40, 60)]);
+        let list = [
+            (Align::Left, 20, prio(0)),
+            (Align::Center, 80, prio(5)),
+            (Align::Right, 20, prio(0)),
+        ];
+        assert_eq!(fit(&list, 100, 0), vec![(0, 10), (10, 80), (90, 10)]);
+    }
+
+    #[test]
+    fn fit_uses_max_width() {
+        let opts = RegionOptions {
+            max_width: Some(25),
+            ..RegionOptions::default()
+        };
+        let list = [(Align::Left, 40, opts), (Align::Right, 15, prio(0))];
+        assert_eq!(fit(&list, 100, 0), vec![(0, 25), (85, 15)]);
+    }
+
+    #[test]
+    fn place_without_gap_matches_old_layout() {
+        let list = [(Align::Left, 10), (Align::Center, 30), (Align::Right, 15)];
+        assert_eq!(place(&list, 100, 0), vec![0, 35, 85]);
+    }
+}